@@ -0,0 +1,228 @@
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0>
+// at your option. This file may not be copied, modified,
+// or distributed except according to those terms.
+
+//! Sequence checksums for `--checksums`.
+//!
+//! Every record's sequence is normalised the way refget and the SAM `M5` tag
+//! expect: whitespace (and so line wrapping) removed, letters upper-cased, header
+//! ignored. Its MD5 is given in hex and its refget digest as `SQ.` followed by
+//! sha512t24u, the base64url encoding of the first 24 bytes of SHA-512.
+//!
+//! The per-file digests are order-independent: the per-sequence digests are
+//! sorted, each followed by `\n`, and digested again with the same algorithm, so
+//! reordering the records of a file does not change them.
+
+use md5::Md5;
+use sha2::{Digest, Sha512};
+
+/// Checksums of one record.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SequenceChecksum {
+    pub id: String,
+    pub length: usize,
+    /// Lowercase hex MD5.
+    pub md5: String,
+    /// `SQ.` followed by the sha512t24u digest.
+    pub refget: String,
+}
+
+/// Checksums of every record of a file, in file order, and of the file as a set
+/// of sequences.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Checksums {
+    pub sequences: Vec<SequenceChecksum>,
+    pub file_md5: String,
+    pub file_refget: String,
+}
+
+/// Normalised bytes are hashed through a stack buffer of this size.
+const NORMALISE_BUFFER: usize = 8 * 1024;
+
+struct Hashers {
+    md5: Md5,
+    sha512: Sha512,
+    length: usize,
+}
+
+/// Hashes the records of one input, or of one chunk of it. Sequence text may
+/// arrive in pieces.
+pub struct ChecksumBuilder {
+    current: Option<Hashers>,
+    sequences: Vec<SequenceChecksum>,
+}
+
+impl ChecksumBuilder {
+    pub fn new() -> Self {
+        Self {
+            current: None,
+            sequences: Vec::new(),
+        }
+    }
+
+    pub fn begin_record(&mut self) {
+        self.current = Some(Hashers {
+            md5: Md5::new(),
+            sha512: Sha512::new(),
+            length: 0,
+        });
+    }
+
+    pub fn sequence_bytes(&mut self, bytes: &[u8]) {
+        let Some(hashers) = &mut self.current else {
+            return;
+        };
+        let mut buffer = [0u8; NORMALISE_BUFFER];
+        for piece in bytes.chunks(NORMALISE_BUFFER) {
+            let mut len = 0;
+            for &byte in piece {
+                if !byte.is_ascii_whitespace() {
+                    buffer[len] = byte.to_ascii_uppercase();
+                    len += 1;
+                }
+            }
+            hashers.md5.update(&buffer[..len]);
+            hashers.sha512.update(&buffer[..len]);
+            hashers.length += len;
+        }
+    }
+
+    /// Ends the current record, if any, whose ID is `id`. It is dropped unless
+    /// `keep`, as for records left out by a filter.
+    pub fn end_record(&mut self, id: &[u8], keep: bool) {
+        let Some(hashers) = self.current.take().filter(|_| keep) else {
+            return;
+        };
+        self.sequences.push(SequenceChecksum {
+            id: String::from_utf8_lossy(id).into_owned(),
+            length: hashers.length,
+            md5: hex(&hashers.md5.finalize()),
+            refget: format!("SQ.{}", sha512t24u(hashers.sha512.finalize().as_slice())),
+        });
+    }
+
+    /// Appends the records of the chunk directly following this one, whose open
+    /// record, if any, stays open. This one's last record must have been ended.
+    pub fn append(&mut self, mut next: ChecksumBuilder) {
+        self.sequences.append(&mut next.sequences);
+        self.current = next.current;
+    }
+
+    /// The checksums of the ended records.
+    pub fn finish(self) -> Checksums {
+        Checksums::new(self.sequences)
+    }
+}
+
+impl Checksums {
+    /// The checksums of `sequences`, with the file digests computed over them.
+    pub fn new(sequences: Vec<SequenceChecksum>) -> Self {
+        let mut md5s: Vec<&str> = sequences.iter().map(|s| s.md5.as_str()).collect();
+        md5s.sort_unstable();
+        let mut refgets: Vec<&str> = sequences.iter().map(|s| &s.refget[3..]).collect();
+        refgets.sort_unstable();
+
+        let mut md5 = Md5::new();
+        for digest in md5s {
+            md5.update(digest.as_bytes());
+            md5.update(b"\n");
+        }
+        let mut sha512 = Sha512::new();
+        for digest in refgets {
+            sha512.update(digest.as_bytes());
+            sha512.update(b"\n");
+        }
+        let file_md5 = hex(&md5.finalize());
+        let file_refget = format!("SQ.{}", sha512t24u(sha512.finalize().as_slice()));
+        Checksums {
+            sequences,
+            file_md5,
+            file_refget,
+        }
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Base64url, without padding, of the first 24 bytes of a SHA-512 digest.
+fn sha512t24u(digest: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] =
+        b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+    // 24 bytes are exactly 32 base64 characters, so no padding is needed.
+    let mut encoded = String::with_capacity(32);
+    for triple in digest[..24].chunks_exact(3) {
+        let bits = (triple[0] as u32) << 16 | (triple[1] as u32) << 8 | triple[2] as u32;
+        for shift in [18, 12, 6, 0] {
+            encoded.push(ALPHABET[(bits >> shift & 0x3f) as usize] as char);
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matches_refget_and_ignores_wrapping() {
+        let mut builder = ChecksumBuilder::new();
+        builder.begin_record();
+        builder.sequence_bytes(b"ac\n");
+        builder.sequence_bytes(b"gt\r\n");
+        builder.end_record(b"seq1", true);
+        builder.begin_record();
+        builder.sequence_bytes(b"ACGT\n");
+        builder.end_record(b"filtered", false);
+        builder.begin_record();
+        builder.end_record(b"empty", true);
+        let checksums = builder.finish();
+
+        let acgt = &checksums.sequences[0];
+        assert_eq!((acgt.id.as_str(), acgt.length), ("seq1", 4));
+        assert_eq!(acgt.md5, "f1f8f4bf413b16ad135722aa4591043e");
+        // Values from the refget specification.
+        assert_eq!(acgt.refget, "SQ.aKF498dAxcJAqme6QYQ7EZ07-fiw8Kw2");
+        assert_eq!(checksums.sequences.len(), 2);
+        assert_eq!(checksums.sequences[1].md5, "d41d8cd98f00b204e9800998ecf8427e");
+        assert_eq!(
+            checksums.sequences[1].refget,
+            "SQ.z4PhNX7vuL3xVChQ1m2AB9Yg5AULVxXc"
+        );
+    }
+
+    #[test]
+    fn test_file_digest_is_order_independent() {
+        let digest = |records: &[&[u8]]| {
+            let mut builder = ChecksumBuilder::new();
+            for record in records {
+                builder.begin_record();
+                builder.sequence_bytes(record);
+                builder.end_record(b"", true);
+            }
+            builder.finish()
+        };
+        let forward = digest(&[b"ACGT", b"GGCC", b"TTTT"]);
+        let shuffled = digest(&[b"TTTT", b"ACGT", b"GGCC"]);
+        assert_eq!(forward.file_md5, shuffled.file_md5);
+        assert_eq!(forward.file_refget, shuffled.file_refget);
+        assert_ne!(forward.file_md5, digest(&[b"ACGT", b"GGCC"]).file_md5);
+
+        // Chunks appended in order give the same result as one pass.
+        let mut first = ChecksumBuilder::new();
+        first.begin_record();
+        first.sequence_bytes(b"ACGT");
+        first.end_record(b"", true);
+        let mut second = ChecksumBuilder::new();
+        second.begin_record();
+        second.sequence_bytes(b"GGCC");
+        second.end_record(b"", true);
+        second.begin_record();
+        second.sequence_bytes(b"TTTT");
+        first.append(second);
+        first.end_record(b"", true);
+        assert_eq!(first.finish(), forward);
+    }
+}
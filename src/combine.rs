@@ -0,0 +1,201 @@
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0>
+// at your option. This file may not be copied, modified,
+// or distributed except according to those terms.
+
+//! Aggregate reports over several input files.
+//!
+//! Assemblies are sometimes delivered as one FASTA per chromosome. `--combine`
+//! merges every input into one report and `--group-by` merges the files sharing a
+//! directory or a regex match, so N50, GC% and the rest are computed over the union.
+
+use crate::process_files::AnalysisResults;
+use regex::Regex;
+use std::path::{Path, PathBuf};
+
+/// Name given to the report covering all input files.
+pub const COMBINED_NAME: &str = "combined";
+
+#[derive(Debug, Clone)]
+pub enum GroupBy {
+    /// Files in the same parent directory form a group.
+    Directory,
+    /// Files whose path matches the pattern are grouped by the first capture
+    /// group, or by the whole match if the pattern has none.
+    Pattern(Regex),
+}
+
+impl GroupBy {
+    /// Parses a `--group-by` value: `dir` or a regular expression.
+    pub fn parse(value: &str) -> Result<Self, String> {
+        if value == "dir" {
+            return Ok(GroupBy::Directory);
+        }
+        Regex::new(value)
+            .map(GroupBy::Pattern)
+            .map_err(|e| format!("invalid --group-by pattern: {e}"))
+    }
+
+    /// The group `path` belongs to, if any.
+    pub fn key(&self, path: &Path) -> Option<String> {
+        match self {
+            GroupBy::Directory => {
+                let parent = path.parent().filter(|p| !p.as_os_str().is_empty());
+                Some(parent.unwrap_or(Path::new(".")).display().to_string())
+            }
+            GroupBy::Pattern(regex) => {
+                let path = path.to_string_lossy();
+                let captures = regex.captures(&path)?;
+                let key = captures.get(1).or_else(|| captures.get(0))?;
+                Some(key.as_str().to_string())
+            }
+        }
+    }
+}
+
+/// Builds the aggregate reports for `per_file`, the results of each entry of
+/// `files`. Groups are listed in order of first appearance, followed by the
+/// combined report if requested. Files matching no group are left out of the
+/// group reports but still count towards the combined one.
+pub fn aggregate(
+    files: &[PathBuf],
+    per_file: &[Vec<AnalysisResults>],
+    group_by: Option<&GroupBy>,
+    combine: bool,
+) -> Vec<AnalysisResults> {
+    let mut reports = Vec::new();
+
+    if let Some(group_by) = group_by {
+        let mut groups: Vec<(String, Vec<&AnalysisResults>)> = Vec::new();
+        for (path, results) in files.iter().zip(per_file) {
+            let Some(key) = group_by.key(path) else {
+                continue;
+            };
+            match groups.iter_mut().find(|(k, _)| *k == key) {
+                Some((_, members)) => members.extend(results),
+                None => groups.push((key, results.iter().collect())),
+            }
+        }
+        reports.extend(
+            groups
+                .into_iter()
+                .map(|(key, members)| AnalysisResults::combine(key, members)),
+        );
+    }
+
+    if combine {
+        let all = per_file.iter().flatten();
+        reports.push(AnalysisResults::combine(COMBINED_NAME.to_string(), all));
+    }
+
+    reports
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checksum::{Checksums, SequenceChecksum};
+    use crate::fai::{FaiIndex, LineWidths};
+    use crate::validate::ValidationReport;
+
+    fn result(lengths: Vec<usize>, gc: usize) -> AnalysisResults {
+        let mut r = AnalysisResults::new("x.fa".to_string());
+        r.sequence_count = lengths.len();
+        r.gc_count = gc;
+        r.calculate_stats(lengths);
+        r
+    }
+
+    #[test]
+    fn test_combine_matches_single_file() {
+        let mut whole = result(vec![500, 400, 300, 200, 100], 700);
+        whole.filename = COMBINED_NAME.to_string();
+        let files = vec![PathBuf::from("a/chr1.fa"), PathBuf::from("b/chr2.fa")];
+        let per_file = vec![
+            vec![result(vec![100, 400], 200)],
+            vec![result(vec![300, 500, 200], 500)],
+        ];
+
+        let reports = aggregate(&files, &per_file, None, true);
+        assert_eq!(reports.len(), 1);
+        assert_eq!(format!("{:?}", reports[0]), format!("{:?}", whole));
+    }
+
+    #[test]
+    fn test_group_by_directory_and_pattern() {
+        let files = vec![
+            PathBuf::from("asm1/chr1.fa"),
+            PathBuf::from("asm2/chr1.fa"),
+            PathBuf::from("asm1/chr2.fa"),
+            PathBuf::from("loose.fa"),
+        ];
+        let per_file = vec![
+            vec![result(vec![10], 1)],
+            vec![result(vec![20], 2)],
+            vec![result(vec![30], 3)],
+            vec![result(vec![40], 4)],
+        ];
+
+        let by_dir = aggregate(&files, &per_file, Some(&GroupBy::parse("dir").unwrap()), false);
+        let summary: Vec<_> = by_dir
+            .iter()
+            .map(|r| (r.filename.as_str(), r.total_length, r.gc_count))
+            .collect();
+        assert_eq!(summary, vec![("asm1", 40, 4), ("asm2", 20, 2), (".", 40, 4)]);
+
+        let pattern = GroupBy::parse(r"(chr\d+)\.fa$").unwrap();
+        let by_chr = aggregate(&files, &per_file, Some(&pattern), true);
+        let summary: Vec<_> = by_chr
+            .iter()
+            .map(|r| (r.filename.as_str(), r.sequence_count, r.largest_contig))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![("chr1", 2, 20), ("chr2", 1, 30), (COMBINED_NAME, 4, 40)]
+        );
+
+        assert!(GroupBy::parse("(unclosed").is_err());
+    }
+
+    #[test]
+    fn test_combine_merges_checksums_line_widths_and_validation() {
+        let digest = |md5: &str| SequenceChecksum {
+            id: md5.to_string(),
+            length: 1,
+            md5: md5.to_string(),
+            refget: format!("SQ.{md5}"),
+        };
+        let widths = |width: u64, max_line| LineWidths {
+            wrap_widths: [(width, 1)].into(),
+            irregular_records: 0,
+            max_line,
+        };
+        let mut first = result(vec![10], 1);
+        first.checksums = Some(Checksums::new(vec![digest("b")]));
+        first.fai = Some(FaiIndex {
+            line_widths: Some(widths(60, 60)),
+            ..Default::default()
+        });
+        first.validation = Some(ValidationReport::default());
+        let mut second = result(vec![20], 2);
+        second.checksums = Some(Checksums::new(vec![digest("a")]));
+        second.fai = Some(FaiIndex {
+            line_widths: Some(widths(80, 80)),
+            ..Default::default()
+        });
+        second.validation = Some(ValidationReport {
+            issues: Vec::new(),
+            total: 3,
+        });
+        let files = vec![PathBuf::from("chr1.fa"), PathBuf::from("chr2.fa")];
+
+        let reports = aggregate(&files, &[vec![first], vec![second]], None, true);
+        let combined = &reports[0];
+        let expected = Checksums::new(vec![digest("b"), digest("a")]);
+        assert_eq!(combined.checksums, Some(expected));
+        let line_widths = combined.line_widths().unwrap();
+        assert_eq!(line_widths.wrap_widths, [(60, 1), (80, 1)].into());
+        assert_eq!(line_widths.max_line, 80);
+        assert_eq!(combined.validation.as_ref().map(|v| v.total), Some(3));
+    }
+}
@@ -0,0 +1,254 @@
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0>
+// at your option. This file may not be copied, modified,
+// or distributed except according to those terms.
+
+//! Duplicate sequence content detection for `--duplicate-sequences`.
+//!
+//! Each record's sequence is hashed as it streams past, together with the hash of
+//! its reverse complement, so a contig and its reverse complement get the same
+//! canonical hash (the smaller of the two). Line breaks, whitespace and gaps are
+//! skipped and letters are upper-cased, so wrapping and soft-masking do not matter.
+//!
+//! Both hashes are polynomial hashes modulo the Mersenne prime 2^61 - 1: the
+//! forward one is extended at its end, the reverse complement one at its start,
+//! which only needs the running power of the base. Records are matched on hash and
+//! length together.
+
+use std::collections::HashMap;
+
+const MODULUS: u64 = (1 << 61) - 1;
+
+/// Polynomial base, below `MODULUS`.
+const BASE: u64 = 0x1f3d_5b79_a2c4_e681 % MODULUS;
+
+/// Upper-cased complement of each byte, or 0 for bytes that are not hashed
+/// (whitespace and gaps). IUPAC ambiguity codes map to their complements, U to A,
+/// and anything else to itself.
+const COMPLEMENT: [u8; 256] = {
+    let mut table = [0u8; 256];
+    let mut byte = 0;
+    while byte < 256 {
+        table[byte] = (byte as u8).to_ascii_uppercase();
+        byte += 1;
+    }
+    let pairs = b"ATTAUAGCCGRYYRKMMKBVVBDHHD";
+    let mut i = 0;
+    while i < pairs.len() {
+        table[pairs[i] as usize] = pairs[i + 1];
+        table[pairs[i].to_ascii_lowercase() as usize] = pairs[i + 1];
+        i += 2;
+    }
+    let skipped = b" \t\r\n-.";
+    let mut i = 0;
+    while i < skipped.len() {
+        table[skipped[i] as usize] = 0;
+        i += 1;
+    }
+    table
+};
+
+fn mul_mod(a: u64, b: u64) -> u64 {
+    let product = a as u128 * b as u128;
+    let folded = (product & MODULUS as u128) as u64 + (product >> 61) as u64;
+    if folded >= MODULUS {
+        folded - MODULUS
+    } else {
+        folded
+    }
+}
+
+fn add_mod(a: u64, b: u64) -> u64 {
+    let sum = a + b;
+    if sum >= MODULUS { sum - MODULUS } else { sum }
+}
+
+/// Streaming hash of a sequence and of its reverse complement.
+#[derive(Clone, Copy)]
+struct CanonicalHasher {
+    forward: u64,
+    reverse: u64,
+    /// `BASE` to the power of `length`.
+    power: u64,
+    length: usize,
+}
+
+impl CanonicalHasher {
+    fn new() -> Self {
+        Self {
+            forward: 0,
+            reverse: 0,
+            power: 1,
+            length: 0,
+        }
+    }
+
+    fn update(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            let complement = COMPLEMENT[byte as usize];
+            if complement == 0 {
+                continue;
+            }
+            // Complementing twice upper-cases and reads U as T.
+            let base = COMPLEMENT[complement as usize];
+            self.forward = add_mod(mul_mod(self.forward, BASE), base as u64);
+            self.reverse = add_mod(self.reverse, mul_mod(complement as u64, self.power));
+            self.power = mul_mod(self.power, BASE);
+            self.length += 1;
+        }
+    }
+}
+
+/// One hashed record.
+struct Digest {
+    hash: u64,
+    length: usize,
+    /// The canonical hash is that of the reverse complement.
+    reverse: bool,
+    id: Option<Box<str>>,
+}
+
+/// Records that repeat the sequence of an earlier record, on either strand.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DuplicateSequences {
+    /// Records whose sequence was already seen.
+    pub count: usize,
+    /// Total length of those records.
+    pub bases: usize,
+    /// With `--list-duplicates`, each duplicate as (its ID, the ID of the first
+    /// record with the same sequence, whether it is the reverse complement).
+    pub listed: Vec<(String, String, bool)>,
+}
+
+/// Hashes the records of one input, or of one chunk of it. Sequence text may
+/// arrive in pieces.
+pub struct SequenceDigests {
+    list_ids: bool,
+    hasher: Option<CanonicalHasher>,
+    records: Vec<Digest>,
+}
+
+impl SequenceDigests {
+    pub fn new(list_ids: bool) -> Self {
+        Self {
+            list_ids,
+            hasher: None,
+            records: Vec::new(),
+        }
+    }
+
+    pub fn begin_record(&mut self) {
+        self.hasher = Some(CanonicalHasher::new());
+    }
+
+    pub fn sequence_bytes(&mut self, bytes: &[u8]) {
+        if let Some(hasher) = &mut self.hasher {
+            hasher.update(bytes);
+        }
+    }
+
+    /// Ends the current record, if any, whose ID is `id`. It is dropped unless
+    /// `keep`, as for records left out by a filter.
+    pub fn end_record(&mut self, id: &[u8], keep: bool) {
+        let Some(hasher) = self.hasher.take().filter(|_| keep) else {
+            return;
+        };
+        // Empty records have no content to duplicate.
+        if hasher.length == 0 {
+            return;
+        }
+        let id = self
+            .list_ids
+            .then(|| String::from_utf8_lossy(id).into());
+        self.records.push(Digest {
+            hash: hasher.forward.min(hasher.reverse),
+            length: hasher.length,
+            reverse: hasher.reverse < hasher.forward,
+            id,
+        });
+    }
+
+    /// Appends the records of the chunk directly following this one, whose open
+    /// record, if any, stays open. This one's last record must have been ended.
+    pub fn append(&mut self, mut next: SequenceDigests) {
+        self.records.append(&mut next.records);
+        self.hasher = next.hasher;
+    }
+
+    /// The report on the ended records.
+    pub fn finish(self) -> DuplicateSequences {
+        let mut report = DuplicateSequences::default();
+        let mut first_seen: HashMap<(u64, usize), usize> = HashMap::new();
+        for (index, record) in self.records.iter().enumerate() {
+            let Some(&first) = first_seen.get(&(record.hash, record.length)) else {
+                first_seen.insert((record.hash, record.length), index);
+                continue;
+            };
+            report.count += 1;
+            report.bases += record.length;
+            if let (Some(id), Some(original)) = (&record.id, &self.records[first].id) {
+                let reverse = record.reverse != self.records[first].reverse;
+                report
+                    .listed
+                    .push((id.to_string(), original.to_string(), reverse));
+            }
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn digest(records: &[(&str, &[&[u8]])]) -> DuplicateSequences {
+        let mut digests = SequenceDigests::new(true);
+        for (id, pieces) in records {
+            digests.begin_record();
+            for piece in *pieces {
+                digests.sequence_bytes(piece);
+            }
+            digests.end_record(id.as_bytes(), true);
+        }
+        digests.finish()
+    }
+
+    #[test]
+    fn test_reverse_complement_and_wrapping() {
+        let report = digest(&[
+            ("a", &[b"ACGTTG\n", b"CA\n"]),
+            ("b", &[b"acg", b"t-tgca"]),
+            ("rc", &[b"TGCAACGT"]),
+            ("other", &[b"TGCAACGA"]),
+            ("empty", &[]),
+            ("empty2", &[b"\n"]),
+            ("rna", &[b"ACGUUGCA"]),
+        ]);
+        assert_eq!(report.count, 3);
+        assert_eq!(report.bases, 24);
+        assert_eq!(
+            report.listed,
+            vec![
+                ("b".to_string(), "a".to_string(), false),
+                ("rc".to_string(), "a".to_string(), true),
+                ("rna".to_string(), "a".to_string(), false),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_append_matches_single_pass() {
+        let mut first = SequenceDigests::new(false);
+        first.begin_record();
+        first.sequence_bytes(b"GATTACA");
+        first.end_record(b"a", true);
+        let mut second = SequenceDigests::new(false);
+        second.begin_record();
+        second.sequence_bytes(b"TGTAATC");
+        first.append(second);
+        first.end_record(b"b", true);
+        let report = first.finish();
+        assert_eq!((report.count, report.bases), (1, 7));
+        assert!(report.listed.is_empty());
+    }
+}
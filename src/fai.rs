@@ -0,0 +1,507 @@
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0>
+// at your option. This file may not be copied, modified,
+// or distributed except according to those terms.
+
+//! samtools-compatible `.fai` index building for `--write-fai`.
+//!
+//! A `.fai` line gives, per record, its name, length, the offset of its first base
+//! and the number of bases and bytes per line. Offsets are in the uncompressed
+//! stream, which is what samtools expects for both plain and BGZF files. The index
+//! is only valid if every line of a record but the last has the same width and the
+//! last is not longer, so the builder checks that as it goes.
+//!
+//! The same line tracking gives the `--line-widths` report: the usual wrapping
+//! width, how many records are wrapped irregularly and the longest line.
+//!
+//! `--from-index` goes the other way and reads an existing `.fai`, plus the `.gzi`
+//! block index of a BGZF file, to get record lengths without decoding anything.
+
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FaiRecord {
+    pub name: String,
+    pub length: u64,
+    pub offset: u64,
+    pub line_bases: u64,
+    pub line_width: u64,
+}
+
+/// The index entries of a file, or why it cannot be indexed.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FaiIndex {
+    pub records: Vec<FaiRecord>,
+    /// The first line-width inconsistency found.
+    pub inconsistency: Option<String>,
+    /// With `--line-widths`.
+    pub line_widths: Option<LineWidths>,
+}
+
+/// How the sequence lines of a file are wrapped.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LineWidths {
+    /// Records spanning several lines, by the bases on their first line.
+    pub wrap_widths: BTreeMap<u64, usize>,
+    /// Records whose lines are not all as long as the first but the last, which
+    /// samtools cannot index.
+    pub irregular_records: usize,
+    /// Bases on the longest line.
+    pub max_line: u64,
+}
+
+impl LineWidths {
+    /// The most common wrapping width, the narrower on a tie. `None` if no record
+    /// is wrapped.
+    pub fn mode(&self) -> Option<u64> {
+        let mut mode = None;
+        for (&width, &count) in &self.wrap_widths {
+            if mode.is_none_or(|(_, best)| count > best) {
+                mode = Some((width, count));
+            }
+        }
+        mode.map(|(width, _)| width)
+    }
+
+    pub fn faidx_compatible(&self) -> bool {
+        self.irregular_records == 0
+    }
+
+    pub fn absorb(&mut self, other: &LineWidths) {
+        for (&width, &count) in &other.wrap_widths {
+            *self.wrap_widths.entry(width).or_default() += count;
+        }
+        self.irregular_records += other.irregular_records;
+        self.max_line = self.max_line.max(other.max_line);
+    }
+}
+
+impl FaiRecord {
+    /// Offset just past the last base, assuming the lines are as the entry says.
+    pub fn end(&self) -> u64 {
+        if self.length == 0 || self.line_bases == 0 {
+            return self.offset;
+        }
+        let lines_before_last = (self.length - 1) / self.line_bases;
+        self.offset
+            + lines_before_last * self.line_width
+            + (self.length - lines_before_last * self.line_bases)
+    }
+}
+
+impl FaiIndex {
+    /// Reads a `.fai`. Lines must have the five samtools columns; FASTQ indexes,
+    /// which have six, are rejected.
+    pub fn read(path: &Path) -> std::io::Result<FaiIndex> {
+        let text = std::fs::read_to_string(path)?;
+        let mut records = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let invalid = || {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("{:?}, line {}: not a FASTA .fai entry", path, number + 1),
+                )
+            };
+            let fields: Vec<&str> = line.split('\t').collect();
+            let [name, numbers @ ..] = fields.as_slice() else {
+                return Err(invalid());
+            };
+            let numbers: Vec<u64> = numbers
+                .iter()
+                .map(|field| field.parse().map_err(|_| invalid()))
+                .collect::<Result<_, _>>()?;
+            let &[length, offset, line_bases, line_width] = numbers.as_slice() else {
+                return Err(invalid());
+            };
+            if line_width < line_bases || (line_bases == 0 && length > 0) {
+                return Err(invalid());
+            }
+            records.push(FaiRecord {
+                name: name.to_string(),
+                length,
+                offset,
+                line_bases,
+                line_width,
+            });
+        }
+        Ok(FaiIndex {
+            records,
+            ..Default::default()
+        })
+    }
+
+    /// Offset just past the last base of the file, as far as the index knows.
+    pub fn data_end(&self) -> u64 {
+        self.records.iter().map(FaiRecord::end).max().unwrap_or(0)
+    }
+
+    pub fn write(&self, path: &Path) -> std::io::Result<()> {
+        let mut writer = std::io::BufWriter::new(std::fs::File::create(path)?);
+        for r in &self.records {
+            writeln!(
+                writer,
+                "{}\t{}\t{}\t{}\t{}",
+                r.name, r.length, r.offset, r.line_bases, r.line_width
+            )?;
+        }
+        writer.flush()
+    }
+}
+
+/// `genome.fa` is indexed as `genome.fa.fai`, `genome.fa.bgz` as `genome.fa.bgz.fai`.
+pub fn fai_path(file: &Path) -> PathBuf {
+    let mut path = file.as_os_str().to_owned();
+    path.push(".fai");
+    PathBuf::from(path)
+}
+
+/// Line geometry of the record being read.
+struct Record {
+    offset: u64,
+    in_header: bool,
+    length: u64,
+    /// Bases and bytes of the first line.
+    first_line: Option<(u64, u64)>,
+    /// A line shorter than the first has ended; only empty lines may follow.
+    short_line_seen: bool,
+    line_bases: u64,
+    line_bytes: u64,
+    last_was_cr: bool,
+    /// Lines holding sequence.
+    lines: u64,
+    longest_line: u64,
+    /// The first line-width inconsistency in the record, named once it ends.
+    inconsistency: Option<String>,
+}
+
+/// Builds the index from the bytes of one input, or one chunk of it, fed in order.
+pub struct FaiBuilder {
+    /// Bytes seen so far.
+    offset: u64,
+    current: Option<Record>,
+    /// Index entries are wanted, not only the line-width report.
+    keep_records: bool,
+    line_widths: LineWidths,
+    index: FaiIndex,
+}
+
+impl FaiBuilder {
+    /// A builder for `--write-fai`, `--line-widths`, or both.
+    pub fn new(write_fai: bool, line_widths: bool) -> Self {
+        Self {
+            offset: 0,
+            current: None,
+            keep_records: write_fai,
+            line_widths: LineWidths::default(),
+            index: FaiIndex {
+                line_widths: line_widths.then(LineWidths::default),
+                ..Default::default()
+            },
+        }
+    }
+
+    /// Bytes that belong to no record, such as text before the first header.
+    pub fn skip(&mut self, len: usize) {
+        self.offset += len as u64;
+    }
+
+    /// Starts a record at its `>`.
+    pub fn begin_record(&mut self) {
+        self.offset += 1;
+        self.current = Some(Record {
+            offset: 0,
+            in_header: true,
+            length: 0,
+            first_line: None,
+            short_line_seen: false,
+            line_bases: 0,
+            line_bytes: 0,
+            last_was_cr: false,
+            lines: 0,
+            longest_line: 0,
+            inconsistency: None,
+        });
+    }
+
+    /// Header text after the `>`, including the newline that ends it.
+    pub fn header_bytes(&mut self, bytes: &[u8]) {
+        self.offset += bytes.len() as u64;
+        if let Some(record) = &mut self.current
+            && bytes.ends_with(b"\n")
+        {
+            record.in_header = false;
+            record.offset = self.offset;
+        }
+    }
+
+    /// Sequence bytes of the current record, newlines included, with the offset
+    /// of each newline in them.
+    pub fn sequence_bytes(&mut self, bytes: &[u8], line_ends: &[usize]) {
+        self.skip(bytes.len());
+        let Some(record) = &mut self.current else {
+            return;
+        };
+        let mut start = 0;
+        for &end in line_ends {
+            record.line_bytes(&bytes[start..end], true);
+            start = end + 1;
+        }
+        if start < bytes.len() {
+            record.line_bytes(&bytes[start..], false);
+        }
+    }
+
+    /// Ends the current record, if any, whose ID is `id`. The index covers every
+    /// record, but the line-width report only those kept by the filters.
+    pub fn end_record(&mut self, id: &[u8], keep: bool) {
+        let Some(mut record) = self.current.take() else {
+            return;
+        };
+        if record.in_header {
+            // The file ended inside the header.
+            record.offset = self.offset;
+        }
+        if record.line_bytes > 0 {
+            // The last line had no newline. Its width is that of the earlier lines.
+            let bases = record.line_bases + u64::from(record.last_was_cr);
+            record.line_bytes = record.first_line.map_or(bases + 1, |(_, width)| width);
+            record.line_bases = bases;
+            record.last_was_cr = false;
+            record.end_line();
+        }
+        if let Some(inconsistency) = &record.inconsistency
+            && self.index.inconsistency.is_none()
+        {
+            let name = String::from_utf8_lossy(id);
+            self.index.inconsistency = Some(format!("record '{name}' {inconsistency}"));
+        }
+        let (line_bases, line_width) = record.first_line.unwrap_or((0, 0));
+        if keep {
+            if record.lines > 1 {
+                *self.line_widths.wrap_widths.entry(line_bases).or_default() += 1;
+            }
+            self.line_widths.irregular_records += usize::from(record.inconsistency.is_some());
+            self.line_widths.max_line = self.line_widths.max_line.max(record.longest_line);
+        }
+        if !self.keep_records {
+            return;
+        }
+        self.index.records.push(FaiRecord {
+            name: String::from_utf8_lossy(id).into_owned(),
+            length: record.length,
+            offset: record.offset,
+            line_bases,
+            line_width,
+        });
+    }
+
+    /// Appends the records of the chunk directly following this one, whose open
+    /// record, if any, stays open. This one's last record must have been ended.
+    pub fn append(&mut self, next: FaiBuilder) {
+        let shift = self.offset;
+        self.index
+            .records
+            .extend(next.index.records.into_iter().map(|mut r| {
+                r.offset += shift;
+                r
+            }));
+        if self.index.inconsistency.is_none() {
+            self.index.inconsistency = next.index.inconsistency;
+        }
+        self.line_widths.absorb(&next.line_widths);
+        self.offset += next.offset;
+        self.current = next.current.map(|mut record| {
+            if !record.in_header {
+                record.offset += shift;
+            }
+            record
+        });
+    }
+
+    /// The index of the ended records.
+    pub fn finish(mut self) -> FaiIndex {
+        if let Some(line_widths) = &mut self.index.line_widths {
+            *line_widths = self.line_widths;
+        }
+        self.index
+    }
+}
+
+impl Record {
+    /// Adds the bytes of a line, or of the start of one. `newline` if they are
+    /// followed by the line's `\n`.
+    fn line_bytes(&mut self, line: &[u8], newline: bool) {
+        if let Some(&last) = line.last() {
+            // A '\r' only counts as a base if more of the line follows it.
+            self.line_bases += line.len() as u64 - u64::from(last == b'\r');
+            self.line_bases += u64::from(self.last_was_cr);
+            self.last_was_cr = last == b'\r';
+        }
+        self.line_bytes += (line.len() + usize::from(newline)) as u64;
+        if newline {
+            self.end_line();
+        }
+    }
+
+    /// Accounts for the line just ended and checks it against the first one.
+    fn end_line(&mut self) {
+        let (bases, width) = (self.line_bases, self.line_bytes);
+        self.length += bases;
+        self.line_bases = 0;
+        self.line_bytes = 0;
+        self.last_was_cr = false;
+        if bases > 0 {
+            self.lines += 1;
+            self.longest_line = self.longest_line.max(bases);
+        }
+        let inconsistency = self.check_line(bases, width);
+        if self.inconsistency.is_none() {
+            self.inconsistency = inconsistency;
+        }
+    }
+
+    /// Compares a line with the first one of the record.
+    fn check_line(&mut self, bases: u64, width: u64) -> Option<String> {
+        match self.first_line {
+            _ if bases == 0 => {
+                self.short_line_seen = true;
+                None
+            }
+            None if self.short_line_seen => Some("has an empty line before its sequence".to_string()),
+            None => {
+                self.first_line = Some((bases, width));
+                None
+            }
+            Some(_) if self.short_line_seen => Some("has a line following a shorter line".to_string()),
+            Some((first_bases, first_width))
+                if bases > first_bases || (bases == first_bases && width != first_width) =>
+            {
+                Some(format!(
+                    "has a line of {bases} bases ({width} bytes) after lines of {first_bases} ({first_width} bytes)"
+                ))
+            }
+            Some((first_bases, _)) => {
+                self.short_line_seen = bases < first_bases;
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ids::HeaderId;
+    use memchr::memchr;
+
+    fn build(data: &[u8]) -> FaiIndex {
+        build_with(data, FaiBuilder::new(true, false))
+    }
+
+    fn build_with(data: &[u8], mut builder: FaiBuilder) -> FaiIndex {
+        // Drive the builder the way the FASTA parser does.
+        let mut pos = 0;
+        let mut at_line_start = true;
+        let mut id = HeaderId::default();
+        while pos < data.len() {
+            if at_line_start && data[pos] == b'>' {
+                builder.end_record(id.as_bytes(), true);
+                builder.begin_record();
+                let end = memchr(b'\n', &data[pos..]).map_or(data.len(), |e| pos + e + 1);
+                builder.header_bytes(&data[pos + 1..end]);
+                id.clear();
+                id.push(&data[pos + 1..end]);
+                pos = end;
+                continue;
+            }
+            let end =
+                memchr::memmem::find(&data[pos..], b"\n>").map_or(data.len(), |e| pos + e + 1);
+            let line_ends: Vec<usize> = memchr::memchr_iter(b'\n', &data[pos..end]).collect();
+            builder.sequence_bytes(&data[pos..end], &line_ends);
+            at_line_start = data[end - 1] == b'\n';
+            pos = end;
+        }
+        builder.end_record(id.as_bytes(), true);
+        builder.finish()
+    }
+
+    fn record(name: &str, length: u64, offset: u64, line_bases: u64, line_width: u64) -> FaiRecord {
+        FaiRecord {
+            name: name.to_string(),
+            length,
+            offset,
+            line_bases,
+            line_width,
+        }
+    }
+
+    #[test]
+    fn test_index_matches_samtools() {
+        let index = build(b">chr1 desc\nACGT\nACGT\nAC\n>chr2\r\nAAA\r\nA\r\n>empty\n>last\nGGGGG");
+        assert_eq!(index.inconsistency, None);
+        assert_eq!(
+            index.records,
+            vec![
+                record("chr1", 10, 11, 4, 5),
+                record("chr2", 4, 31, 3, 5),
+                record("empty", 0, 46, 0, 0),
+                record("last", 5, 52, 5, 6),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_read_round_trips() {
+        let index = build(b">chr1 desc\nACGT\nACGT\nAC\n>empty\n>last\nGGGGG");
+        let mut path = std::env::temp_dir();
+        path.push("round_trip.fa.fai");
+        index.write(&path).unwrap();
+        let read = FaiIndex::read(&path).unwrap();
+        assert_eq!(read, index);
+        assert_eq!(read.records[0].end(), 23);
+        assert_eq!(read.data_end(), 42);
+
+        std::fs::write(&path, "r1\t4\t4\t4\t5\t11\n").unwrap();
+        let err = FaiIndex::read(&path).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn test_line_width_report() {
+        let data = b">a\nACGT\nACGT\nA\n>b\nACGT\nAC\n>c\nACG\nACG\n>ragged\nAC\nACGTACGT\nA\n>single\nACGTACGTACGTA\n>empty\n";
+        let index = build_with(data, FaiBuilder::new(false, true));
+        assert!(index.records.is_empty());
+        let report = index.line_widths.unwrap();
+        assert_eq!(report.wrap_widths, BTreeMap::from([(2, 1), (3, 1), (4, 2)]));
+        assert_eq!(report.mode(), Some(4));
+        assert_eq!(report.irregular_records, 1);
+        assert_eq!(report.max_line, 13);
+        assert!(!report.faidx_compatible());
+
+        let unwrapped = build_with(b">a\nACGT\n>b\nAC\n", FaiBuilder::new(false, true));
+        let report = unwrapped.line_widths.unwrap();
+        assert_eq!((report.mode(), report.max_line), (None, 4));
+        assert!(report.faidx_compatible());
+    }
+
+    #[test]
+    fn test_inconsistent_widths() {
+        for data in [
+            &b">a\nACGT\nAC\nACGT\n"[..],
+            b">a\nACG\nACGT\n",
+            b">a\n\nACGT\n",
+            b">a\nAC\r\nAC\n",
+        ] {
+            let index = build(data);
+            assert!(
+                index.inconsistency.is_some(),
+                "{:?}",
+                String::from_utf8_lossy(data)
+            );
+        }
+        assert_eq!(build(b">a\nACGT\nAC\n\n>b\nA\n").inconsistency, None);
+    }
+}
@@ -0,0 +1,340 @@
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0>
+// at your option. This file may not be copied, modified,
+// or distributed except according to those terms.
+
+//! Streaming FASTQ parser.
+//!
+//! Records are `@header`, one or more sequence lines, a `+` line and quality lines.
+//! Quality lines may start with `@` or `+`, so the end of a record is found by
+//! counting quality characters against the sequence length rather than by looking
+//! at line starts. Sequence lines are counted with the same SIMD kernels as FASTA,
+//! and quality characters go into a Phred+33 histogram.
+
+use crate::ids::{HeaderId, IdTracker};
+use crate::lengths::{FilteredRecords, LengthFilter, SequenceLengths};
+use crate::process_files::{AnalysisResults, ProcessOptions};
+use crate::select::HeaderSelector;
+use crate::simd::{BaseCounts, SimdLevel};
+use memchr::memchr;
+
+/// Highest Phred score representable in Phred+33 (`~`).
+const MAX_PHRED: usize = 93;
+
+/// Distribution of per-base Phred quality scores.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct QualityStats {
+    /// `counts[q]` is the number of bases with Phred score `q`.
+    counts: Vec<usize>,
+}
+
+impl Default for QualityStats {
+    fn default() -> Self {
+        Self {
+            counts: vec![0; MAX_PHRED + 1],
+        }
+    }
+}
+
+impl QualityStats {
+    /// Builds the distribution from counts of raw Phred+33 quality bytes.
+    /// Bytes outside `!`..=`~` are ignored.
+    fn from_bytes(byte_counts: &[usize; 256]) -> Self {
+        let mut stats = Self::default();
+        for (q, count) in stats.counts.iter_mut().enumerate() {
+            *count = byte_counts[q + 33];
+        }
+        stats
+    }
+
+    pub fn absorb(&mut self, other: &QualityStats) {
+        for (a, b) in self.counts.iter_mut().zip(&other.counts) {
+            *a += b;
+        }
+    }
+
+    /// Number of bases with a quality score.
+    pub fn bases(&self) -> usize {
+        self.counts.iter().sum()
+    }
+
+    pub fn mean(&self) -> f64 {
+        let bases = self.bases();
+        if bases == 0 {
+            return 0.0;
+        }
+        let sum: usize = self.counts.iter().enumerate().map(|(q, &c)| q * c).sum();
+        sum as f64 / bases as f64
+    }
+
+    /// Lower median of the per-base scores.
+    pub fn median(&self) -> usize {
+        let half = self.bases().div_ceil(2);
+        let mut cumulative = 0;
+        for (q, &count) in self.counts.iter().enumerate() {
+            cumulative += count;
+            if cumulative >= half && cumulative > 0 {
+                return q;
+            }
+        }
+        0
+    }
+
+    /// Percentage of bases with a score of at least `q`.
+    pub fn percent_at_least(&self, q: usize) -> f64 {
+        let bases = self.bases();
+        if bases == 0 {
+            return 0.0;
+        }
+        let passing: usize = self.counts.iter().skip(q).sum();
+        passing as f64 / bases as f64 * 100.0
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// Before or inside an `@` header line.
+    Header,
+    Sequence,
+    /// Inside the `+` separator line.
+    Plus,
+    Quality,
+}
+
+pub struct FastqParser {
+    lengths: SequenceLengths,
+    /// With `--duplicate-ids`.
+    ids: Option<IdTracker>,
+    /// ID of the current read.
+    id: HeaderId,
+    byte_counts: [usize; 256],
+    state: State,
+    at_line_start: bool,
+    /// Composition of the current read, added to `kept` if it passes the filter.
+    current_counts: BaseCounts,
+    kept: BaseCounts,
+    filter: LengthFilter,
+    selector: Option<HeaderSelector>,
+    /// Whether the current read's header passed the selector.
+    selected: bool,
+    filtered: FilteredRecords,
+    /// Whether the qualities of the current read are counted.
+    keep_quality: bool,
+    /// Sequence bytes of the current record, which the quality must match.
+    current_sequence_bytes: usize,
+    quality_remaining: usize,
+    in_header_line: bool,
+}
+
+impl FastqParser {
+    pub fn new(options: &ProcessOptions) -> Self {
+        Self {
+            lengths: SequenceLengths::new(),
+            ids: options.duplicate_ids.then(IdTracker::new),
+            id: HeaderId::default(),
+            byte_counts: [0; 256],
+            state: State::Header,
+            at_line_start: true,
+            current_counts: BaseCounts::default(),
+            kept: BaseCounts::default(),
+            filter: options.length_filter,
+            selector: options.header_filter.clone().map(HeaderSelector::new),
+            selected: true,
+            filtered: FilteredRecords::default(),
+            keep_quality: true,
+            current_sequence_bytes: 0,
+            quality_remaining: 0,
+            in_header_line: false,
+        }
+    }
+
+    pub fn feed(&mut self, data: &[u8], results: &mut AnalysisResults, simd: SimdLevel) {
+        let mut pos = 0;
+        while pos < data.len() {
+            let rest = &data[pos..];
+            let (line, complete) = match memchr(b'\n', rest) {
+                Some(end) => (&rest[..end], true),
+                None => (rest, false),
+            };
+            pos += line.len() + usize::from(complete);
+            let content = line.strip_suffix(b"\r").unwrap_or(line);
+
+            match self.state {
+                State::Header => {
+                    // Blank or stray lines between records are skipped.
+                    if self.at_line_start {
+                        self.in_header_line = line.first() == Some(&b'@');
+                        if self.in_header_line {
+                            results.sequence_count += 1;
+                            self.id.clear();
+                            self.id.push(&line[1..]);
+                            if let Some(selector) = &mut self.selector {
+                                selector.begin_header();
+                                selector.header_bytes(&line[1..]);
+                            }
+                        }
+                    } else if self.in_header_line {
+                        self.id.push(line);
+                        if let Some(selector) = &mut self.selector {
+                            selector.header_bytes(line);
+                        }
+                    }
+                    if complete && self.in_header_line {
+                        self.end_header();
+                        self.state = State::Sequence;
+                    }
+                }
+                State::Sequence => {
+                    if self.at_line_start && line.first() == Some(&b'+') {
+                        self.state = State::Plus;
+                        if complete {
+                            self.begin_quality();
+                        }
+                    } else {
+                        self.current_counts += crate::simd::update_stats(content, simd);
+                        self.current_sequence_bytes += content.len();
+                    }
+                }
+                State::Plus => {
+                    if complete {
+                        self.begin_quality();
+                    }
+                }
+                State::Quality => {
+                    if self.keep_quality {
+                        for &byte in content {
+                            self.byte_counts[byte as usize] += 1;
+                        }
+                    }
+                    self.quality_remaining = self.quality_remaining.saturating_sub(content.len());
+                    if complete && self.quality_remaining == 0 {
+                        self.end_record();
+                    }
+                }
+            }
+            self.at_line_start = complete;
+        }
+    }
+
+    fn begin_quality(&mut self) {
+        self.state = State::Quality;
+        self.quality_remaining = self.current_sequence_bytes;
+        // A read of length zero still has an (empty) quality line.
+        self.keep_quality = self.keeps(self.current_counts.seq_chars);
+    }
+
+    fn keeps(&self, length: usize) -> bool {
+        self.selected && self.filter.keeps(length)
+    }
+
+    fn end_header(&mut self) {
+        if let Some(selector) = &self.selector {
+            self.selected = selector.end_header(self.id.as_bytes());
+        }
+    }
+
+    fn end_record(&mut self) {
+        let counts = std::mem::take(&mut self.current_counts);
+        if self.keeps(counts.seq_chars) {
+            if let Some(ids) = &mut self.ids {
+                ids.insert(self.id.as_bytes());
+            }
+            self.lengths.push(counts.seq_chars);
+            self.kept += counts;
+        } else {
+            self.filtered.add(counts.seq_chars);
+        }
+        self.current_sequence_bytes = 0;
+        self.state = State::Header;
+        self.in_header_line = false;
+    }
+
+    pub fn finish(mut self, results: &mut AnalysisResults) {
+        // A file may end without the final newline, or without a quality line.
+        if self.state == State::Header && self.in_header_line {
+            self.end_header();
+        }
+        if self.state != State::Header || self.in_header_line {
+            self.end_record();
+        }
+        results.add_bases(self.kept);
+        results.sequence_count -= self.filtered.records;
+        let filtering = self.filter.is_active() || self.selector.is_some();
+        results.filtered = filtering.then_some(self.filtered);
+        results.duplicate_ids = self.ids.map(IdTracker::finish);
+        results.quality = Some(QualityStats::from_bytes(&self.byte_counts));
+        results.calculate_stats(self.lengths);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(chunks: &[&[u8]]) -> AnalysisResults {
+        parse_with(&ProcessOptions::default(), chunks)
+    }
+
+    fn parse_with(options: &ProcessOptions, chunks: &[&[u8]]) -> AnalysisResults {
+        let mut results = AnalysisResults::new("reads.fq".to_string());
+        let mut parser = FastqParser::new(options);
+        for chunk in chunks {
+            parser.feed(chunk, &mut results, SimdLevel::detect());
+        }
+        parser.finish(&mut results);
+        results
+    }
+
+    #[test]
+    fn test_fastq_records_and_quality() {
+        // Quality lines starting with '@' and '+' must not be taken for headers.
+        let data = b"@r1\nACGTN\n+\n@@III\n@r2 desc\r\nGGCC\r\n+r2\r\n+5?I\r\n\n@r3\nA\n+\n#";
+        let results = parse(&[data]);
+        assert_eq!(results.sequence_count, 3);
+        assert_eq!(results.total_length, 10);
+        assert_eq!(results.largest_contig, 5);
+        assert_eq!(results.shortest_contig, 1);
+        assert_eq!(results.gc_count, 6);
+        assert_eq!(results.n_count, 1);
+        assert_eq!(results.duplicate_ids, None);
+
+        let quality = results.quality.unwrap();
+        // '@'=31, 'I'=40, '+'=10, '5'=20, '?'=30, '#'=2
+        assert_eq!(quality.bases(), 10);
+        assert_eq!(quality.median(), 31);
+        assert!((quality.mean() - 284.0 / 10.0).abs() < 1e-9);
+        assert!((quality.percent_at_least(20) - 80.0).abs() < 1e-9);
+        assert!((quality.percent_at_least(30) - 70.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_fastq_split_across_buffers() {
+        let data: &[u8] = b"@a\nACG\nTT\n+\nIII\nII\n@b\nNNNNCC\n+\n!!!!II\n@c\n\n+\n\n";
+        let whole = parse(&[data]);
+        assert_eq!(whole.sequence_count, 3);
+        assert_eq!(whole.total_length, 11);
+        assert_eq!(whole.shortest_contig, 0);
+        for split in 1..data.len() {
+            let (a, b) = data.split_at(split);
+            let parts = parse(&[a, b]);
+            assert_eq!(format!("{parts:?}"), format!("{whole:?}"), "split at {split}");
+        }
+    }
+
+    #[test]
+    fn test_fastq_duplicate_ids() {
+        let options = ProcessOptions {
+            duplicate_ids: true,
+            ..Default::default()
+        };
+        let data: &[u8] = b"@a 1\nAC\n+\nII\n@b\nGG\n+\nII\n@a 2\nT\n+\nI\n@b\n\n+\n\n";
+        let whole = parse_with(&options, &[data]);
+        let duplicates = whole.duplicate_ids.as_ref().unwrap();
+        assert_eq!((duplicates.count, duplicates.examples.clone()), (2, vec!["a".into(), "b".into()]));
+        for split in 1..data.len() {
+            let (a, b) = data.split_at(split);
+            let parts = parse_with(&options, &[a, b]);
+            assert_eq!(parts.duplicate_ids, whole.duplicate_ids, "split at {split}");
+        }
+    }
+}
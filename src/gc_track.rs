@@ -0,0 +1,613 @@
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0>
+// at your option. This file may not be copied, modified,
+// or distributed except according to those terms.
+
+//! Windowed GC content for `--gc-window`, written as bedGraph, and GC skew for
+//! `--gc-skew`, written as a table.
+//!
+//! Windows start every `step` bases of a record and are `size` bases long. Bases
+//! are counted with the same SIMD kernels as the totals, in slices that end where
+//! a window starts or ends, and each window is the difference of the running G and
+//! C counts at its ends. A step of any length thus costs at most two slices, and
+//! sliding windows cost no more than tiled ones. Slices are filled from successive
+//! pieces of sequence bytes, so windows may span any number of buffers. A record's
+//! last window is cut short at its end.
+//!
+//! The lines of a record are written out as it ends, to unnamed temporary files
+//! that the outputs are copied from, so a track holds no more than one record's
+//! windows in memory.
+//!
+//! The skew of a window is (G - C) / (G + C). The cumulative skew at the end of a
+//! window is G - C over the record up to there, so each base counts once however
+//! the windows overlap, and a short last window no more than its bases. It is
+//! lowest near the origin of replication of a bacterial chromosome and highest
+//! near the terminus.
+
+use crate::simd::{self, SimdLevel};
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// `genome.fa.gz` has its GC track at `genome.fa.gz.gc.bedgraph`.
+pub fn gc_track_path(file: &Path) -> PathBuf {
+    let mut path = file.as_os_str().to_owned();
+    path.push(".gc.bedgraph");
+    PathBuf::from(path)
+}
+
+/// `genome.fa.gz` has its GC skew table at `genome.fa.gz.gc_skew.tsv`.
+pub fn gc_skew_path(file: &Path) -> PathBuf {
+    let mut path = file.as_os_str().to_owned();
+    path.push(".gc_skew.tsv");
+    PathBuf::from(path)
+}
+
+/// Window layout given with `--gc-window` and `--step`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GcWindow {
+    pub size: usize,
+    pub step: usize,
+}
+
+/// One record of a track, whose windows are in the track's lines.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GcTrackRecord {
+    pub name: String,
+    pub length: usize,
+    /// G and C bases of the whole record.
+    pub g: usize,
+    pub c: usize,
+    pub skew_summary: SkewSummary,
+}
+
+/// Where the cumulative skew of a record turns, given as window ends.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SkewSummary {
+    /// Skew of the whole record.
+    pub skew: f32,
+    /// Lowest and highest G - C up to a window end.
+    pub min: i64,
+    pub min_position: usize,
+    pub max: i64,
+    pub max_position: usize,
+}
+
+impl Default for SkewSummary {
+    fn default() -> Self {
+        Self {
+            skew: 0.0,
+            min: i64::MAX,
+            min_position: 0,
+            max: i64::MIN,
+            max_position: 0,
+        }
+    }
+}
+
+impl SkewSummary {
+    /// Takes in the cumulative skew at the window end `position`.
+    fn update(&mut self, cumulative: i64, position: usize) {
+        if cumulative < self.min {
+            (self.min, self.min_position) = (cumulative, position);
+        }
+        if cumulative > self.max {
+            (self.max, self.max_position) = (cumulative, position);
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct GcTrack {
+    pub records: Vec<GcTrackRecord>,
+    lines: TrackLines,
+}
+
+impl GcTrack {
+    /// Writes one bedGraph line per window: record ID, 0-based start, end and GC %.
+    pub fn write_lines(&self, writer: &mut impl Write) -> io::Result<()> {
+        self.lines.copy_to(writer, false)
+    }
+
+    /// Writes one table row per window: record ID, 0-based start, end, skew and
+    /// G - C up to the end. Only tracks built with the skew have any.
+    pub fn write_skew_lines(&self, writer: &mut impl Write) -> io::Result<()> {
+        self.lines.copy_to(writer, true)
+    }
+}
+
+/// The lines of a track, in the temporary files they were written to.
+#[derive(Clone, Default)]
+struct TrackLines {
+    /// bedGraph and skew lines, from each builder merged in turn.
+    files: Arc<Vec<(File, Option<File>)>>,
+    /// Bytes in all the bedGraph and skew files, which is what `Debug` shows.
+    bytes: (u64, u64),
+    /// Why the lines could not all be written.
+    error: Option<String>,
+}
+
+impl TrackLines {
+    fn copy_to(&self, writer: &mut impl Write, skew: bool) -> io::Result<()> {
+        if let Some(error) = &self.error {
+            return Err(io::Error::other(format!(
+                "cannot write the GC track: {error}"
+            )));
+        }
+        for (bedgraph, skews) in self.files.iter() {
+            let file = if skew { skews.as_ref() } else { Some(bedgraph) };
+            if let Some(mut file) = file {
+                file.seek(SeekFrom::Start(0))?;
+                io::copy(&mut file, writer)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Debug for TrackLines {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TrackLines")
+            .field("bytes", &self.bytes)
+            .field("error", &self.error)
+            .finish()
+    }
+}
+
+/// Lines of ended records being written.
+struct Spill {
+    bedgraph: BufWriter<File>,
+    skew: Option<BufWriter<File>>,
+}
+
+impl Spill {
+    fn new(skew: bool) -> io::Result<Self> {
+        Ok(Self {
+            bedgraph: BufWriter::new(temp_file()?),
+            skew: if skew {
+                Some(BufWriter::new(temp_file()?))
+            } else {
+                None
+            },
+        })
+    }
+
+    /// Flushes the files and adds them to `files`, and their sizes to `lines`.
+    fn finish(
+        self,
+        lines: &mut TrackLines,
+        files: &mut Vec<(File, Option<File>)>,
+    ) -> io::Result<()> {
+        let mut bedgraph = self
+            .bedgraph
+            .into_inner()
+            .map_err(io::IntoInnerError::into_error)?;
+        lines.bytes.0 += bedgraph.stream_position()?;
+        let skew = match self.skew {
+            Some(skew) => {
+                let mut skew = skew.into_inner().map_err(io::IntoInnerError::into_error)?;
+                lines.bytes.1 += skew.stream_position()?;
+                Some(skew)
+            }
+            None => None,
+        };
+        files.push((bedgraph, skew));
+        Ok(())
+    }
+}
+
+/// A new temporary file with no name: it is unlinked once open, or on Windows
+/// deleted when closed.
+fn temp_file() -> io::Result<File> {
+    static CREATED: AtomicUsize = AtomicUsize::new(0);
+    let path = std::env::temp_dir().join(format!(
+        "count-fasta-{}-{}.gc.tmp",
+        std::process::id(),
+        CREATED.fetch_add(1, Ordering::Relaxed)
+    ));
+    let mut options = OpenOptions::new();
+    options.read(true).write(true).create_new(true);
+    #[cfg(windows)]
+    {
+        use std::os::windows::fs::OpenOptionsExt;
+        const FILE_FLAG_DELETE_ON_CLOSE: u32 = 0x04000000;
+        options.custom_flags(FILE_FLAG_DELETE_ON_CLOSE);
+    }
+    let file = options.open(&path)?;
+    #[cfg(not(windows))]
+    std::fs::remove_file(&path)?;
+    Ok(file)
+}
+
+/// Writes the tracks of the records read from one input as a bedGraph file.
+pub fn write_bedgraph<'a>(
+    tracks: impl IntoIterator<Item = &'a GcTrack>,
+    name: &str,
+    path: &Path,
+) -> io::Result<()> {
+    let mut writer = io::BufWriter::new(std::fs::File::create(path)?);
+    writeln!(writer, "track type=bedGraph name=\"{name} GC%\"")?;
+    for track in tracks {
+        track.write_lines(&mut writer)?;
+    }
+    writer.flush()
+}
+
+/// Writes the GC skew of the records read from one input as a tab-separated table.
+pub fn write_skew_table<'a>(
+    tracks: impl IntoIterator<Item = &'a GcTrack>,
+    path: &Path,
+) -> io::Result<()> {
+    let mut writer = io::BufWriter::new(std::fs::File::create(path)?);
+    writeln!(
+        writer,
+        "sequence\tstart\tend\tgc_skew\tcumulative_g_minus_c"
+    )?;
+    for track in tracks {
+        track.write_skew_lines(&mut writer)?;
+    }
+    writer.flush()
+}
+
+fn percent(gc: usize, bases: usize) -> f32 {
+    (gc as f64 * 100.0 / bases as f64) as f32
+}
+
+/// (G - C) / (G + C), or 0 without either.
+fn skew(g: usize, c: usize) -> f32 {
+    if g + c == 0 {
+        return 0.0;
+    }
+    ((g as f64 - c as f64) / (g + c) as f64) as f32
+}
+
+/// Builds the windows of each record from its sequence bytes, which may arrive in
+/// pieces of any size.
+pub struct GcTrackBuilder {
+    window: GcWindow,
+    /// Skew lines are written too.
+    with_skew: bool,
+    simd: SimdLevel,
+    /// Running G and C counts and bases of the current record.
+    g: usize,
+    c: usize,
+    bases: usize,
+    /// Running G and C counts at the start of each window not ended yet.
+    open: VecDeque<(usize, usize)>,
+    /// Windows started and ended so far in the current record.
+    started: usize,
+    ended: usize,
+    in_record: bool,
+    /// GC % of the current record's windows, and their skew with the G - C up
+    /// to their end, until it ends.
+    values: Vec<f32>,
+    skews: Vec<(f32, i64)>,
+    skew_summary: SkewSummary,
+    records: Vec<GcTrackRecord>,
+    /// Files the lines of ended records went to. More lines go to the last one
+    /// while `spill_open`.
+    spills: Vec<Spill>,
+    spill_open: bool,
+    error: Option<String>,
+}
+
+impl GcTrackBuilder {
+    pub fn new(window: GcWindow, with_skew: bool, simd: SimdLevel) -> Self {
+        Self {
+            window,
+            with_skew,
+            simd,
+            g: 0,
+            c: 0,
+            bases: 0,
+            open: VecDeque::new(),
+            started: 0,
+            ended: 0,
+            in_record: false,
+            values: Vec::new(),
+            skews: Vec::new(),
+            skew_summary: SkewSummary::default(),
+            records: Vec::new(),
+            spills: Vec::new(),
+            spill_open: false,
+            error: None,
+        }
+    }
+
+    pub fn begin_record(&mut self) {
+        self.reset();
+        self.in_record = true;
+        self.cross_boundaries();
+    }
+
+    /// Counts sequence bytes of the current record; line breaks and gaps are
+    /// skipped as in the totals.
+    pub fn sequence_bytes(&mut self, mut data: &[u8]) {
+        if !self.in_record {
+            return;
+        }
+        while !data.is_empty() {
+            // Whitespace and gaps only make the slice count fewer bases, so a
+            // boundary is never passed.
+            let taken = (self.next_boundary() - self.bases).min(data.len());
+            let counts = simd::update_stats(&data[..taken], self.simd);
+            data = &data[taken..];
+            self.g += counts.g;
+            self.c += counts.c;
+            self.bases += counts.seq_chars;
+            self.cross_boundaries();
+        }
+    }
+
+    /// Where the next window starts or ends, past the bases counted so far.
+    fn next_boundary(&self) -> usize {
+        let GcWindow { size, step } = self.window;
+        let start = self.started * step;
+        if self.open.is_empty() {
+            start
+        } else {
+            start.min(self.ended * step + size)
+        }
+    }
+
+    /// Ends and starts the windows bounded where the bases counted so far end.
+    fn cross_boundaries(&mut self) {
+        let GcWindow { size, step } = self.window;
+        if let Some(&(g, c)) = self.open.front()
+            && self.ended * step + size == self.bases
+        {
+            self.open.pop_front();
+            self.ended += 1;
+            self.push_window(self.g - g, self.c - c, size);
+        }
+        if self.started * step == self.bases {
+            self.open.push_back((self.g, self.c));
+            self.started += 1;
+        }
+    }
+
+    /// Adds a window ending where the bases counted so far end.
+    fn push_window(&mut self, g: usize, c: usize, bases: usize) {
+        let cumulative = self.g as i64 - self.c as i64;
+        self.values.push(percent(g + c, bases));
+        self.skews.push((skew(g, c), cumulative));
+        self.skew_summary.update(cumulative, self.bases);
+    }
+
+    /// Closes the current record, whose ID is `id`, and writes out its lines.
+    /// They are dropped unless `keep`, as for records left out by a filter.
+    pub fn end_record(&mut self, id: &[u8], keep: bool) {
+        if !std::mem::take(&mut self.in_record) {
+            return;
+        }
+        let length = self.bases;
+        if keep && length > 0 {
+            // Every window ending inside the record is full. Unless one ended right
+            // at the end, the next is cut short there if it starts in the record.
+            let GcWindow { size, step } = self.window;
+            let start = self.ended * step;
+            let ended_at_end = self.ended > 0 && start - step + size == length;
+            if let Some(&(g, c)) = self.open.front()
+                && !ended_at_end
+                && start < length
+            {
+                self.push_window(self.g - g, self.c - c, length - start);
+            }
+            self.skew_summary.skew = skew(self.g, self.c);
+            let record = GcTrackRecord {
+                name: String::from_utf8_lossy(id).into_owned(),
+                length,
+                g: self.g,
+                c: self.c,
+                skew_summary: self.skew_summary,
+            };
+            if self.error.is_none()
+                && let Err(e) = self.write_record(&record)
+            {
+                self.error = Some(e.to_string());
+            }
+            self.records.push(record);
+        }
+        self.reset();
+    }
+
+    fn write_record(&mut self, record: &GcTrackRecord) -> io::Result<()> {
+        if !self.spill_open {
+            self.spills.push(Spill::new(self.with_skew)?);
+            self.spill_open = true;
+        }
+        let spill = self.spills.last_mut().expect("a spill was just opened");
+        let GcWindow { size, step } = self.window;
+        for (k, (value, (skew, cumulative))) in self.values.iter().zip(&self.skews).enumerate() {
+            let start = k * step;
+            let end = (start + size).min(record.length);
+            writeln!(
+                spill.bedgraph,
+                "{}\t{start}\t{end}\t{value:.2}",
+                record.name
+            )?;
+            if let Some(writer) = &mut spill.skew {
+                writeln!(
+                    writer,
+                    "{}\t{start}\t{end}\t{skew:.4}\t{cumulative}",
+                    record.name
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    fn reset(&mut self) {
+        self.g = 0;
+        self.c = 0;
+        self.bases = 0;
+        self.open.clear();
+        self.started = 0;
+        self.ended = 0;
+        self.values.clear();
+        self.skews.clear();
+        self.skew_summary = SkewSummary::default();
+    }
+
+    /// Appends the records of a builder that read the input following `self`'s,
+    /// starting at a record boundary. `self`'s last record must have been ended.
+    pub fn append(&mut self, mut next: GcTrackBuilder) {
+        self.records.append(&mut next.records);
+        self.spills.append(&mut next.spills);
+        next.records = std::mem::take(&mut self.records);
+        next.spills = std::mem::take(&mut self.spills);
+        next.error = self.error.take().or(next.error);
+        *self = next;
+    }
+
+    /// The windows of the ended records.
+    pub fn finish(self) -> GcTrack {
+        let mut lines = TrackLines {
+            error: self.error,
+            ..Default::default()
+        };
+        let mut files = Vec::new();
+        for spill in self.spills {
+            if let Err(e) = spill.finish(&mut lines, &mut files) {
+                lines.error.get_or_insert(e.to_string());
+            }
+        }
+        lines.files = Arc::new(files);
+        GcTrack {
+            records: self.records,
+            lines,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// bedGraph and skew lines of a record, computed directly from the bases.
+    fn reference(name: &str, bases: &[u8], window: GcWindow) -> (String, String) {
+        let (mut lines, mut skew_lines) = (String::new(), String::new());
+        let mut start = 0;
+        while start < bases.len() {
+            let end = (start + window.size).min(bases.len());
+            let count = |set: &[u8]| bases[start..end].iter().filter(|b| set.contains(b)).count();
+            let (g, c) = (count(b"Gg"), count(b"Cc"));
+            let (value, skew) = (percent(g + c, end - start), skew(g, c));
+            let count = |set: &[u8]| bases[..end].iter().filter(|b| set.contains(b)).count();
+            let cumulative = count(b"Gg") as i64 - count(b"Cc") as i64;
+            lines.push_str(&format!("{name}\t{start}\t{end}\t{value:.2}\n"));
+            skew_lines.push_str(&format!(
+                "{name}\t{start}\t{end}\t{skew:.4}\t{cumulative}\n"
+            ));
+            if end == bases.len() {
+                break;
+            }
+            start += window.step;
+        }
+        (lines, skew_lines)
+    }
+
+    fn lines(track: &GcTrack) -> (String, String) {
+        let (mut lines, mut skew_lines) = (Vec::new(), Vec::new());
+        track.write_lines(&mut lines).unwrap();
+        track.write_skew_lines(&mut skew_lines).unwrap();
+        (
+            String::from_utf8(lines).unwrap(),
+            String::from_utf8(skew_lines).unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_windows_match_reference() {
+        let bases: Vec<u8> = (0..997u32)
+            .map(|i| b"ACGTNgcat"[(i * i % 13 % 9) as usize])
+            .collect();
+        // Wrapped, with a gap, in a record of its own; a short and an empty record follow.
+        let mut text = Vec::new();
+        for line in bases.chunks(61) {
+            text.extend_from_slice(line);
+            text.extend_from_slice(b"\r\n");
+        }
+        text.extend_from_slice(b"--");
+        let layouts = [
+            (100, 100),
+            (100, 30),
+            (10, 25),
+            (7, 7),
+            (1000, 1),
+            (5, 1000),
+            (997, 3),
+            (64, 63),
+        ];
+        for (size, step) in layouts {
+            let window = GcWindow { size, step };
+            let mut first = GcTrackBuilder::new(window, true, SimdLevel::detect());
+            first.begin_record();
+            for piece in text.chunks(17) {
+                first.sequence_bytes(piece);
+            }
+            first.end_record(b"chr1", true);
+            // The rest as read by a chunk parser, appended while a record is open.
+            let mut builder = GcTrackBuilder::new(window, true, SimdLevel::detect());
+            builder.begin_record();
+            builder.sequence_bytes(b"GG");
+            first.append(builder);
+            let mut builder = first;
+            builder.sequence_bytes(b"C\n");
+            builder.end_record(b"short", true);
+            builder.begin_record();
+            builder.sequence_bytes(b"GGC\n");
+            builder.end_record(b"dropped", false);
+            builder.begin_record();
+            builder.end_record(b"empty", true);
+            let track = builder.finish();
+
+            let names: Vec<&str> = track.records.iter().map(|r| r.name.as_str()).collect();
+            assert_eq!(names, ["chr1", "short"]);
+            assert_eq!(track.records[0].length, 997);
+            let (chr1, chr1_skews) = reference("chr1", &bases, window);
+            let (short, short_skews) = reference("short", b"GGC", window);
+            assert_eq!(
+                lines(&track),
+                (chr1 + &short, chr1_skews + &short_skews),
+                "{window:?}"
+            );
+            assert_eq!((track.records[1].g, track.records[1].c), (2, 1));
+        }
+
+        let mut builder =
+            GcTrackBuilder::new(GcWindow { size: 4, step: 2 }, false, SimdLevel::detect());
+        builder.begin_record();
+        builder.sequence_bytes(b"GCAT\nTA");
+        builder.end_record(b"s", true);
+        let (lines, skew_lines) = lines(&builder.finish());
+        assert_eq!(lines, "s\t0\t4\t50.00\ns\t2\t6\t0.00\n");
+        assert_eq!(skew_lines, "");
+    }
+
+    #[test]
+    fn test_skew_summary() {
+        // Mostly C, then mostly G: G - C bottoms out mid-way.
+        let window = GcWindow { size: 4, step: 4 };
+        let mut builder = GcTrackBuilder::new(window, true, SimdLevel::detect());
+        builder.begin_record();
+        builder.sequence_bytes(b"CCCA\nCGCC\nGGGA\nGGCG\nAT");
+        builder.end_record(b"chr", true);
+        let track = builder.finish();
+        let summary = track.records[0].skew_summary;
+        assert_eq!(summary.skew, 0.0);
+        assert_eq!((summary.min, summary.min_position), (-5, 8));
+        assert_eq!((summary.max, summary.max_position), (0, 16));
+
+        let (_, skew_lines) = lines(&track);
+        let skews: Vec<&str> = skew_lines
+            .lines()
+            .map(|line| line.split('\t').nth(3).unwrap())
+            .collect();
+        assert_eq!(skews, ["-1.0000", "-0.5000", "1.0000", "0.5000", "0.0000"]);
+        assert_eq!(skew_lines.lines().nth(1), Some("chr\t4\t8\t-0.5000\t-5"));
+    }
+}
@@ -0,0 +1,284 @@
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0>
+// at your option. This file may not be copied, modified,
+// or distributed except according to those terms.
+
+//! htslib-compatible `.gzi` block indexes for `--write-gzi`.
+//!
+//! The BGZF decoder pulls the compressed file through a `BlockIndexer`, which reads
+//! the framing of each block as it goes past: `BSIZE` from the gzip extra field of
+//! the header and `ISIZE` from the footer. That is all a `.gzi` needs, so the index
+//! comes out of the pass that computes the stats, without decompressing twice.
+//!
+//! `--from-index` reads the index back to get the decompressed size of a file
+//! from its last block alone.
+
+use flate2::read::MultiGzDecoder;
+use noodles::bgzf::gzi;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// `genome.fa.bgz` has its block index at `genome.fa.bgz.gzi`.
+pub fn gzi_path(file: &Path) -> PathBuf {
+    let mut path = file.as_os_str().to_owned();
+    path.push(".gzi");
+    PathBuf::from(path)
+}
+
+/// Writes the block index of `file` next to it.
+pub fn write(file: &Path, index: &gzi::Index) -> std::io::Result<()> {
+    gzi::fs::write(gzi_path(file), index)
+}
+
+/// Decompressed size of `file`: the offset its `.gzi` gives for the last block,
+/// plus what that block and any following it inflate to.
+pub fn uncompressed_size(file: &Path) -> std::io::Result<u64> {
+    let index = gzi::fs::read(gzi_path(file))?;
+    let (compressed, uncompressed) = index.as_ref().last().copied().unwrap_or((0, 0));
+    let mut tail = File::open(file)?;
+    tail.seek(SeekFrom::Start(compressed))?;
+    Ok(uncompressed + std::io::copy(&mut MultiGzDecoder::new(tail), &mut std::io::sink())?)
+}
+
+/// Fixed part of a gzip header, up to and including `XLEN`.
+const FIXED_HEADER: usize = 12;
+
+/// Where the framing parser is within the current block.
+enum Frame {
+    /// Reading the fixed header.
+    Header,
+    /// Reading the `XLEN` bytes of extra subfields.
+    Extra {
+        len: usize,
+    },
+    /// Skipping compressed data up to the 4-byte `ISIZE` at the end of the block.
+    Body,
+    Footer,
+}
+
+/// Block offsets collected from the framing of a BGZF stream.
+pub struct GziBuilder {
+    frame: Frame,
+    /// Bytes of the current header or footer field read so far.
+    field: Vec<u8>,
+    /// Compressed offset of the current block, and of the byte after it.
+    block_start: u64,
+    block_end: u64,
+    position: u64,
+    uncompressed: u64,
+    entries: Vec<(u64, u64)>,
+    error: Option<String>,
+}
+
+impl GziBuilder {
+    pub fn new() -> Self {
+        Self {
+            frame: Frame::Header,
+            field: Vec::with_capacity(FIXED_HEADER),
+            block_start: 0,
+            block_end: 0,
+            position: 0,
+            uncompressed: 0,
+            entries: Vec::new(),
+            error: None,
+        }
+    }
+
+    /// Follows the compressed bytes that come next in the file.
+    pub fn update(&mut self, mut bytes: &[u8]) {
+        while !bytes.is_empty() && self.error.is_none() {
+            let wanted = match self.frame {
+                Frame::Header => FIXED_HEADER,
+                Frame::Extra { len } => FIXED_HEADER + len,
+                Frame::Body => {
+                    let remaining = (self.block_end - 4 - self.position) as usize;
+                    let skipped = remaining.min(bytes.len());
+                    bytes = &bytes[skipped..];
+                    self.position += skipped as u64;
+                    if skipped == remaining {
+                        self.frame = Frame::Footer;
+                    }
+                    continue;
+                }
+                Frame::Footer => 4,
+            };
+            let taken = (wanted - self.field.len()).min(bytes.len());
+            self.field.extend_from_slice(&bytes[..taken]);
+            bytes = &bytes[taken..];
+            self.position += taken as u64;
+            if self.field.len() == wanted {
+                self.end_field();
+            }
+        }
+    }
+
+    fn end_field(&mut self) {
+        match self.frame {
+            Frame::Header => {
+                if self.field[..4] != [0x1f, 0x8b, 8, 4] {
+                    self.error = Some(format!("no BGZF block header at byte {}", self.block_start));
+                    return;
+                }
+                let len = u16::from_le_bytes([self.field[10], self.field[11]]) as usize;
+                self.frame = Frame::Extra { len };
+            }
+            Frame::Extra { .. } => {
+                let Some(block_size) = block_size(&self.field[FIXED_HEADER..]) else {
+                    self.error = Some(format!(
+                        "BGZF block at byte {} has no size",
+                        self.block_start
+                    ));
+                    return;
+                };
+                self.block_end = self.block_start + block_size;
+                if self.block_end < self.position + 8 {
+                    self.error = Some(format!(
+                        "BGZF block at byte {} is too short",
+                        self.block_start
+                    ));
+                    return;
+                }
+                self.field.clear();
+                self.frame = Frame::Body;
+            }
+            Frame::Body => unreachable!("the body is skipped, not collected"),
+            Frame::Footer => {
+                let size = u32::from_le_bytes(self.field[..4].try_into().unwrap());
+                // Empty blocks, such as the EOF marker, add nothing to look up.
+                if size > 0 && self.block_start > 0 {
+                    self.entries.push((self.block_start, self.uncompressed));
+                }
+                self.uncompressed += u64::from(size);
+                self.block_start = self.block_end;
+                self.field.clear();
+                self.frame = Frame::Header;
+            }
+        }
+    }
+
+    /// The index, or why the stream could not be indexed.
+    pub fn finish(self) -> Result<gzi::Index, String> {
+        if let Some(error) = self.error {
+            return Err(error);
+        }
+        if !matches!(self.frame, Frame::Header) || !self.field.is_empty() {
+            return Err("the file ends inside a BGZF block".to_string());
+        }
+        Ok(gzi::Index::from(self.entries))
+    }
+}
+
+/// Whether `file` starts with a BGZF block, a gzip member whose extra field has
+/// a `BC` subfield. `bgzip` names its output `.gz` like plain gzip.
+pub fn is_bgzf(file: &Path) -> bool {
+    let read_header = || -> std::io::Result<bool> {
+        let mut file = File::open(file)?;
+        let mut header = [0; FIXED_HEADER];
+        file.read_exact(&mut header)?;
+        if header[..4] != [0x1f, 0x8b, 8, 4] {
+            return Ok(false);
+        }
+        let mut extra = vec![0; u16::from_le_bytes([header[10], header[11]]) as usize];
+        file.read_exact(&mut extra)?;
+        Ok(block_size(&extra).is_some())
+    };
+    read_header().unwrap_or(false)
+}
+
+/// `BSIZE + 1` from the `BC` subfield of a gzip extra field.
+fn block_size(mut extra: &[u8]) -> Option<u64> {
+    while extra.len() >= 4 {
+        let len = u16::from_le_bytes([extra[2], extra[3]]) as usize;
+        let data = extra.get(4..4 + len)?;
+        if extra[..2] == *b"BC" && len == 2 {
+            return Some(u64::from(u16::from_le_bytes([data[0], data[1]])) + 1);
+        }
+        extra = &extra[4 + len..];
+    }
+    None
+}
+
+/// Passes the compressed file through to the BGZF decoder while building its
+/// index. The builder is shared because the multithreaded decoder reads on a
+/// thread of its own.
+pub struct BlockIndexer<R> {
+    inner: R,
+    builder: Arc<Mutex<GziBuilder>>,
+}
+
+impl<R> BlockIndexer<R> {
+    pub fn new(inner: R, builder: Arc<Mutex<GziBuilder>>) -> Self {
+        Self { inner, builder }
+    }
+}
+
+impl<R: Read> Read for BlockIndexer<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.builder.lock().unwrap().update(&buf[..n]);
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use noodles::bgzf;
+    use std::io::Write;
+
+    #[test]
+    fn test_index_matches_block_layout() {
+        let data: Vec<u8> = (0..300_000u32).map(|i| b"ACGT\n"[i as usize % 5]).collect();
+        let mut writer = bgzf::io::Writer::new(Vec::new());
+        writer.write_all(&data).unwrap();
+        let compressed = writer.finish().unwrap();
+
+        // Fed in awkward pieces, as reads of any size may arrive.
+        let mut builder = GziBuilder::new();
+        for piece in compressed.chunks(7) {
+            builder.update(piece);
+        }
+        let index = builder.finish().unwrap();
+        let entries = index.as_ref();
+        assert!(entries.len() >= 4, "{entries:?}");
+
+        // Each entry is the start of a block holding the data from that offset on.
+        for &(compressed_offset, uncompressed_offset) in entries {
+            let mut reader = bgzf::io::Reader::new(&compressed[compressed_offset as usize..]);
+            let mut block = vec![0; 16];
+            reader.read_exact(&mut block).unwrap();
+            let start = uncompressed_offset as usize;
+            assert_eq!(block, data[start..start + 16]);
+        }
+
+        let mut truncated = GziBuilder::new();
+        truncated.update(&compressed[..compressed.len() - 10]);
+        assert!(truncated.finish().is_err());
+        let mut not_bgzf = GziBuilder::new();
+        not_bgzf.update(b">not compressed\nACGT\n");
+        assert!(not_bgzf.finish().is_err());
+    }
+
+    #[test]
+    fn test_sniffs_bgzf() {
+        use flate2::write::GzEncoder;
+
+        let mut path = std::env::temp_dir();
+        path.push(format!("count-fasta-{}-sniff.fa.gz", std::process::id()));
+        let mut writer = bgzf::io::Writer::new(File::create(&path).unwrap());
+        writer.write_all(b">a\nACGT\n").unwrap();
+        writer.finish().unwrap();
+        assert!(is_bgzf(&path));
+
+        let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(b">a\nACGT\n").unwrap();
+        std::fs::write(&path, encoder.finish().unwrap()).unwrap();
+        assert!(!is_bgzf(&path));
+        std::fs::write(&path, b"\x1f\x8b").unwrap();
+        assert!(!is_bgzf(&path));
+        let _ = std::fs::remove_file(&path);
+        assert!(!is_bgzf(&path));
+    }
+}
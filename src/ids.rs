@@ -0,0 +1,202 @@
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0>
+// at your option. This file may not be copied, modified,
+// or distributed except according to those terms.
+
+//! Duplicate sequence ID detection for `--duplicate-ids`.
+//!
+//! The ID is the header token up to the first whitespace, which is what indexers
+//! such as `samtools faidx` key on. IDs are remembered as 64-bit hashes, exactly
+//! up to `EXACT_ID_LIMIT` of them; past that the hashes move into a fixed-size
+//! Bloom filter so memory stays bounded on read sets with billions of records, and
+//! the count is flagged as approximate.
+//!
+//! The filter's error grows with the IDs it holds: a new ID is taken for a
+//! repeat with a probability of about 0.006% at 25 million IDs, 1% at 100
+//! million, 13% at 250 million and 90% at a billion. Counts past a few hundred
+//! million IDs are only an upper bound.
+
+use std::collections::HashSet;
+use std::hash::{DefaultHasher, Hash, Hasher};
+
+/// IDs kept as exact hashes, about 150 MiB of set at most.
+pub const EXACT_ID_LIMIT: usize = 1 << 23;
+
+/// Size of the Bloom filter used past `EXACT_ID_LIMIT`: 1 Gibit, 128 MiB.
+const FILTER_BITS: usize = 1 << 30;
+
+/// Bits set per ID in the Bloom filter.
+const FILTER_HASHES: u64 = 4;
+
+/// Duplicated IDs named in the report.
+pub const MAX_EXAMPLES: usize = 5;
+
+/// Duplicate IDs found in one file.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DuplicateIds {
+    /// Records whose ID was already used by an earlier record.
+    pub count: usize,
+    /// Up to `MAX_EXAMPLES` distinct duplicated IDs, in order of discovery.
+    pub examples: Vec<String>,
+    /// The file had too many IDs to track exactly; `count` includes false
+    /// positives, at the rates given in the module documentation.
+    pub approximate: bool,
+}
+
+enum Seen {
+    Exact(HashSet<u64>),
+    Filter(Vec<u64>),
+}
+
+/// The ID of a header whose text may arrive in several pieces.
+#[derive(Default)]
+pub struct HeaderId {
+    bytes: Vec<u8>,
+    /// Whitespace ended the ID.
+    complete: bool,
+}
+
+impl HeaderId {
+    pub fn clear(&mut self) {
+        self.bytes.clear();
+        self.complete = false;
+    }
+
+    /// Adds header text following the `>` or `@`, or following earlier pieces.
+    pub fn push(&mut self, text: &[u8]) {
+        if self.complete {
+            return;
+        }
+        match text.iter().position(u8::is_ascii_whitespace) {
+            Some(end) => {
+                self.bytes.extend_from_slice(&text[..end]);
+                self.complete = true;
+            }
+            None => self.bytes.extend_from_slice(text),
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+/// Collects the ID of each record and counts the repeats.
+pub struct IdTracker {
+    seen: Seen,
+    report: DuplicateIds,
+}
+
+impl IdTracker {
+    pub fn new() -> Self {
+        Self {
+            seen: Seen::Exact(HashSet::new()),
+            report: DuplicateIds::default(),
+        }
+    }
+
+    /// Records one ID.
+    pub fn insert(&mut self, id: &[u8]) {
+        let mut hasher = DefaultHasher::new();
+        id.hash(&mut hasher);
+        let hash = hasher.finish();
+        let repeated = match &mut self.seen {
+            Seen::Exact(hashes) => {
+                let repeated = !hashes.insert(hash);
+                if hashes.len() > EXACT_ID_LIMIT {
+                    // Free the set before allocating the filter; the plain list
+                    // of hashes is less than half its size.
+                    let hashes: Vec<u64> = std::mem::take(hashes).into_iter().collect();
+                    let mut filter = vec![0u64; FILTER_BITS / 64];
+                    for hash in hashes {
+                        filter_insert(&mut filter, hash);
+                    }
+                    self.seen = Seen::Filter(filter);
+                    self.report.approximate = true;
+                }
+                repeated
+            }
+            Seen::Filter(filter) => filter_insert(filter, hash),
+        };
+        if repeated {
+            self.report.count += 1;
+            let id = String::from_utf8_lossy(id);
+            if self.report.examples.len() < MAX_EXAMPLES
+                && !self.report.examples.iter().any(|e| *e == id)
+            {
+                self.report.examples.push(id.into_owned());
+            }
+        }
+    }
+
+    pub fn finish(self) -> DuplicateIds {
+        self.report
+    }
+}
+
+/// Sets the bits of `hash` and returns whether they were all set already.
+fn filter_insert(filter: &mut [u64], hash: u64) -> bool {
+    // Double hashing: bit i is h1 + i * h2.
+    let (h1, h2) = (hash & 0xffff_ffff, (hash >> 32) | 1);
+    let mut present = true;
+    for i in 0..FILTER_HASHES {
+        let bit = (h1.wrapping_add(i.wrapping_mul(h2)) as usize) % FILTER_BITS;
+        let (word, mask) = (bit / 64, 1u64 << (bit % 64));
+        present &= filter[word] & mask != 0;
+        filter[word] |= mask;
+    }
+    present
+}
+
+/// Tracks the IDs of the FASTA headers whose `>` is at `header_offsets` in a
+/// fully resident buffer. Used where the records were parsed in independent
+/// chunks, which cannot see each other's IDs.
+pub fn track_ids_at(data: &[u8], header_offsets: &[usize]) -> DuplicateIds {
+    let mut tracker = IdTracker::new();
+    for &offset in header_offsets {
+        let header = &data[offset + 1..];
+        let end = header
+            .iter()
+            .position(u8::is_ascii_whitespace)
+            .unwrap_or(header.len());
+        tracker.insert(&header[..end]);
+    }
+    tracker.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tracks_ids_across_pieces() {
+        let mut tracker = IdTracker::new();
+        let mut id = HeaderId::default();
+        for header in [&["chr1 x"][..], &["ch", "r2"], &["chr", "1\tdup"], &["chr2"], &["chr1"]] {
+            id.clear();
+            for piece in header {
+                id.push(piece.as_bytes());
+            }
+            tracker.insert(id.as_bytes());
+        }
+        let report = tracker.finish();
+        assert_eq!(report.count, 3);
+        assert_eq!(report.examples, vec!["chr1", "chr2"]);
+        assert!(!report.approximate);
+
+        let data = b">chr1 x\nAC\n>chr2\nGT\n>chr1\tdup\n>chr2\n>chr1\n";
+        let offsets: Vec<usize> = (0..data.len()).filter(|&i| data[i] == b'>').collect();
+        assert_eq!(track_ids_at(data, &offsets), report);
+    }
+
+    #[test]
+    fn test_filter_has_no_false_negatives() {
+        let mut filter = vec![0u64; FILTER_BITS / 64];
+        for hash in (0..1000u64).map(|i| i.wrapping_mul(0x9e37_79b9_7f4a_7c15)) {
+            assert!(!filter_insert(&mut filter, hash));
+        }
+        for hash in (0..1000u64).map(|i| i.wrapping_mul(0x9e37_79b9_7f4a_7c15)) {
+            assert!(filter_insert(&mut filter, hash));
+        }
+    }
+}
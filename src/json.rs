@@ -0,0 +1,188 @@
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0>
+// at your option. This file may not be copied, modified,
+// or distributed except according to those terms.
+
+//! JSON output for `--json`.
+//!
+//! One object per file (and per aggregate report), in an array. The layout is
+//! small and fixed, so it is written by hand rather than through a serialisation
+//! framework. Values that do not apply, such as GC% of a protein file, are `null`.
+
+use crate::process_files::AnalysisResults;
+use std::io::{self, Write};
+use std::path::Path;
+
+/// A JSON string literal.
+fn string(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// A number, or `null` where there is none; non-finite floats have no JSON form.
+fn number(value: Option<f64>) -> String {
+    match value {
+        Some(value) if value.is_finite() => format!("{value}"),
+        _ => "null".to_string(),
+    }
+}
+
+fn object(fields: &[(&str, String)]) -> String {
+    let fields: Vec<String> = fields
+        .iter()
+        .map(|(name, value)| format!("{}: {value}", string(name)))
+        .collect();
+    format!("{{{}}}", fields.join(", "))
+}
+
+fn result_object(r: &AnalysisResults, histogram: bool) -> String {
+    let has_sequences = r.sequence_count > 0;
+    // GC and N have no meaning for protein, and are not in a .fai.
+    let composition = r.protein.is_none() && !r.lengths_only && r.total_length > 0;
+    let distribution = &r.length_distribution;
+    let percent = |count: usize| (count as f64 / r.total_length as f64) * 100.0;
+    let mut fields = vec![
+        ("filename", string(&r.filename)),
+        ("total_length", r.total_length.to_string()),
+        ("sequence_count", r.sequence_count.to_string()),
+        (
+            "average_length",
+            number(has_sequences.then(|| r.total_length as f64 / r.sequence_count as f64)),
+        ),
+        (
+            "largest_contig",
+            number(has_sequences.then_some(r.largest_contig as f64)),
+        ),
+        (
+            "shortest_contig",
+            number(has_sequences.then_some(r.shortest_contig as f64)),
+        ),
+        (
+            "median_length",
+            number(has_sequences.then_some(distribution.median)),
+        ),
+        (
+            "lower_quartile",
+            number(has_sequences.then_some(distribution.lower_quartile)),
+        ),
+        (
+            "upper_quartile",
+            number(has_sequences.then_some(distribution.upper_quartile)),
+        ),
+        (
+            "length_std_dev",
+            number(has_sequences.then_some(distribution.std_dev)),
+        ),
+        ("n25", r.n25.to_string()),
+        ("l25", r.n25_sequence_count.to_string()),
+        ("n50", r.n50.to_string()),
+        ("l50", r.n50_sequence_count.to_string()),
+        ("n75", r.n75.to_string()),
+        ("l75", r.n75_sequence_count.to_string()),
+        ("gc_count", number(composition.then_some(r.gc_count as f64))),
+        (
+            "gc_percentage",
+            number(composition.then(|| percent(r.gc_count))),
+        ),
+        ("n_count", number(composition.then_some(r.n_count as f64))),
+        (
+            "n_percentage",
+            number(composition.then(|| percent(r.n_count))),
+        ),
+        ("molecule_type", string(r.molecule_type().name())),
+    ];
+    if let Some(filtered) = r.filtered {
+        fields.push(("filtered_sequences", filtered.records.to_string()));
+        fields.push(("filtered_bases", filtered.bases.to_string()));
+    }
+    if !r.length_thresholds.is_empty() {
+        let thresholds: Vec<String> = r
+            .length_thresholds
+            .iter()
+            .map(|t| {
+                object(&[
+                    ("min_length", t.min_length.to_string()),
+                    ("count", t.count.to_string()),
+                    ("total_length", t.total_length.to_string()),
+                ])
+            })
+            .collect();
+        fields.push(("length_thresholds", format!("[{}]", thresholds.join(", "))));
+    }
+    if histogram {
+        let bins: Vec<String> = distribution
+            .histogram
+            .iter()
+            .map(|bin| {
+                object(&[
+                    ("min_length", bin.min.to_string()),
+                    ("max_length", (bin.max - 1).to_string()),
+                    ("count", bin.count.to_string()),
+                    ("total_length", bin.total_length.to_string()),
+                ])
+            })
+            .collect();
+        fields.push(("length_histogram", format!("[{}]", bins.join(", "))));
+    }
+    object(&fields)
+}
+
+/// Writes `results` as a JSON array, with the length histograms if `histogram`.
+pub fn write_results(results: &[AnalysisResults], path: &Path, histogram: bool) -> io::Result<()> {
+    let mut writer = io::BufWriter::new(std::fs::File::create(path)?);
+    writeln!(writer, "[")?;
+    for (i, result) in results.iter().enumerate() {
+        let separator = if i + 1 < results.len() { "," } else { "" };
+        writeln!(writer, "  {}{separator}", result_object(result, histogram))?;
+    }
+    writeln!(writer, "]")?;
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_result_object() {
+        let mut results = AnalysisResults::new("odd \"name\"\t.fa".to_string());
+        results.gc_count = 3;
+        results.nucleotide_count = 8;
+        results.t_count = 2;
+        results.calculate_stats(vec![6, 2]);
+        results.count_thresholds(&[0, 5]);
+        assert_eq!(
+            result_object(&results, true),
+            concat!(
+                r#"{"filename": "odd \"name\"\t.fa", "total_length": 8, "sequence_count": 2, "#,
+                r#""average_length": 4, "largest_contig": 6, "shortest_contig": 2, "#,
+                r#""median_length": 4, "lower_quartile": 3, "upper_quartile": 5, "length_std_dev": 2, "#,
+                r#""n25": 6, "l25": 1, "n50": 6, "l50": 1, "n75": 6, "l75": 1, "#,
+                r#""gc_count": 3, "gc_percentage": 37.5, "n_count": 0, "n_percentage": 0, "#,
+                r#""molecule_type": "DNA", "length_thresholds": ["#,
+                r#"{"min_length": 0, "count": 2, "total_length": 8}, "#,
+                r#"{"min_length": 5, "count": 1, "total_length": 6}], "length_histogram": ["#,
+                r#"{"min_length": 2, "max_length": 4, "count": 1, "total_length": 2}, "#,
+                r#"{"min_length": 5, "max_length": 9, "count": 1, "total_length": 6}]}"#
+            )
+        );
+
+        let empty = AnalysisResults::new("empty.fa".to_string());
+        let text = result_object(&empty, false);
+        assert!(text.contains(r#""shortest_contig": null"#), "{text}");
+        assert!(text.contains(r#""gc_percentage": null"#), "{text}");
+    }
+}
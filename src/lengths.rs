@@ -0,0 +1,478 @@
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0>
+// at your option. This file may not be copied, modified,
+// or distributed except according to those terms.
+
+//! Storage for per-record sequence lengths.
+//!
+//! Assemblies have few records, so lengths are kept in a plain list. Read sets and
+//! metagenomes can have hundreds of millions, so once a file passes
+//! `LOW_MEMORY_THRESHOLD` records the list is folded into a histogram: a dense
+//! array of counts for lengths below `DENSE_LIMIT` plus a sparse map for the rare
+//! longer ones. Both keep every length exactly, so N50/Nx/L50 are unchanged.
+
+use std::collections::BTreeMap;
+
+/// Records kept as a plain list before switching to the histogram.
+pub const LOW_MEMORY_THRESHOLD: usize = 1 << 20;
+
+/// Lengths below this are counted in a dense array (512 KiB of counters).
+const DENSE_LIMIT: usize = 1 << 16;
+
+/// Records at least `min_length` long, as in QUAST's "# contigs (>= 1000 bp)".
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LengthThreshold {
+    pub min_length: usize,
+    pub count: usize,
+    pub total_length: usize,
+}
+
+/// One bin of the length histogram: records with `min <= length < max`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LengthBin {
+    pub min: usize,
+    pub max: usize,
+    pub count: usize,
+    pub total_length: usize,
+}
+
+/// Spread of the record lengths beyond the N-statistics.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LengthDistribution {
+    pub median: f64,
+    pub lower_quartile: f64,
+    pub upper_quartile: f64,
+    /// Population standard deviation.
+    pub std_dev: f64,
+    /// Log-spaced bins on a 1-2-5 series (1, 2, 5, 10, 20, 50, ...), from the first
+    /// bin holding any record to the last.
+    pub histogram: Vec<LengthBin>,
+}
+
+impl LengthDistribution {
+    /// Computes the distribution from (length, count) groups, longest first, as
+    /// returned by `SequenceLengths::descending`. Quantiles interpolate linearly
+    /// between the records either side, so the median of an even number of
+    /// records is the mean of the middle two.
+    pub fn from_groups(groups: &[(usize, usize)]) -> Self {
+        let count: usize = groups.iter().map(|&(_, records)| records).sum();
+        if count == 0 {
+            return Self::default();
+        }
+        let total: usize = groups.iter().map(|&(length, records)| length * records).sum();
+        let mean = total as f64 / count as f64;
+        let variance = groups
+            .iter()
+            .map(|&(length, records)| (length as f64 - mean).powi(2) * records as f64)
+            .sum::<f64>()
+            / count as f64;
+
+        let mut histogram: Vec<LengthBin> = Vec::new();
+        for &(length, records) in groups.iter().rev() {
+            let (min, max) = bin_bounds(length);
+            match histogram.last_mut() {
+                Some(bin) if bin.min == min => {}
+                _ => {
+                    // Empty bins between occupied ones are listed too.
+                    let mut next = histogram.last().map_or(min, |bin| bin.max);
+                    while next < min {
+                        let (start, end) = bin_bounds(next);
+                        histogram.push(LengthBin { min: start, max: end, count: 0, total_length: 0 });
+                        next = end;
+                    }
+                    histogram.push(LengthBin { min, max, count: 0, total_length: 0 });
+                }
+            }
+            let bin = histogram.last_mut().unwrap();
+            bin.count += records;
+            bin.total_length += length * records;
+        }
+
+        let quantile = |q: f64| {
+            let position = q * (count - 1) as f64;
+            let below = nth_shortest(groups, position.floor() as usize) as f64;
+            let above = nth_shortest(groups, position.ceil() as usize) as f64;
+            below + (above - below) * position.fract()
+        };
+        Self {
+            median: quantile(0.5),
+            lower_quartile: quantile(0.25),
+            upper_quartile: quantile(0.75),
+            std_dev: variance.sqrt(),
+            histogram,
+        }
+    }
+}
+
+/// The `[min, max)` histogram bin holding `length`. Zero has a bin of its own.
+fn bin_bounds(length: usize) -> (usize, usize) {
+    if length == 0 {
+        return (0, 1);
+    }
+    let mut decade = 1usize;
+    while length / decade >= 10 {
+        decade *= 10;
+    }
+    match length / decade {
+        1 => (decade, decade.saturating_mul(2)),
+        2..=4 => (2 * decade, decade.saturating_mul(5)),
+        _ => (5 * decade, decade.saturating_mul(10)),
+    }
+}
+
+/// The `n`th shortest length (from 0) in groups sorted longest first.
+fn nth_shortest(groups: &[(usize, usize)], n: usize) -> usize {
+    let mut seen = 0;
+    for &(length, records) in groups.iter().rev() {
+        seen += records;
+        if n < seen {
+            return length;
+        }
+    }
+    groups.first().map_or(0, |&(length, _)| length)
+}
+
+/// Record lengths kept by `--min-length` and `--max-length`, both inclusive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LengthFilter {
+    pub min: usize,
+    pub max: usize,
+}
+
+impl Default for LengthFilter {
+    fn default() -> Self {
+        Self {
+            min: 0,
+            max: usize::MAX,
+        }
+    }
+}
+
+impl LengthFilter {
+    pub fn keeps(self, length: usize) -> bool {
+        (self.min..=self.max).contains(&length)
+    }
+
+    /// Whether any length is excluded at all.
+    pub fn is_active(self) -> bool {
+        self != Self::default()
+    }
+}
+
+/// Records left out of the statistics by a `LengthFilter`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct FilteredRecords {
+    pub records: usize,
+    pub bases: usize,
+}
+
+impl FilteredRecords {
+    pub fn add(&mut self, length: usize) {
+        self.records += 1;
+        self.bases += length;
+    }
+
+    pub fn absorb(&mut self, other: FilteredRecords) {
+        self.records += other.records;
+        self.bases += other.bases;
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Repr {
+    List(Vec<usize>),
+    Histogram {
+        dense: Vec<usize>,
+        sparse: BTreeMap<usize, usize>,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct SequenceLengths {
+    repr: Repr,
+    count: usize,
+    total: usize,
+}
+
+impl Default for SequenceLengths {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SequenceLengths {
+    pub fn new() -> Self {
+        Self {
+            repr: Repr::List(Vec::with_capacity(250)),
+            count: 0,
+            total: 0,
+        }
+    }
+
+    pub fn push(&mut self, length: usize) {
+        self.count += 1;
+        self.total += length;
+        match &mut self.repr {
+            Repr::List(list) => {
+                list.push(length);
+                if list.len() > LOW_MEMORY_THRESHOLD {
+                    self.fold_into_histogram();
+                }
+            }
+            Repr::Histogram { dense, sparse } => {
+                if length < DENSE_LIMIT {
+                    dense[length] += 1;
+                } else {
+                    *sparse.entry(length).or_insert(0) += 1;
+                }
+            }
+        }
+    }
+
+    /// Adds every length recorded in `other`.
+    pub fn append(&mut self, other: &SequenceLengths) {
+        match &other.repr {
+            Repr::List(list) => {
+                for &length in list {
+                    self.push(length);
+                }
+            }
+            Repr::Histogram { dense, sparse } => {
+                self.fold_into_histogram();
+                let dense_pairs = dense.iter().copied().enumerate().filter(|&(_, c)| c > 0);
+                let sparse_pairs = sparse.iter().map(|(&length, &count)| (length, count));
+                for (length, count) in dense_pairs.chain(sparse_pairs) {
+                    self.push_many(length, count);
+                }
+            }
+        }
+    }
+
+    fn push_many(&mut self, length: usize, count: usize) {
+        self.count += count;
+        self.total += length * count;
+        match &mut self.repr {
+            Repr::List(list) => list.extend(std::iter::repeat_n(length, count)),
+            Repr::Histogram { dense, sparse } => {
+                if length < DENSE_LIMIT {
+                    dense[length] += count;
+                } else {
+                    *sparse.entry(length).or_insert(0) += count;
+                }
+            }
+        }
+    }
+
+    fn fold_into_histogram(&mut self) {
+        if let Repr::List(list) = &mut self.repr {
+            let list = std::mem::take(list);
+            self.repr = Repr::Histogram {
+                dense: vec![0; DENSE_LIMIT],
+                sparse: BTreeMap::new(),
+            };
+            let (count, total) = (self.count, self.total);
+            for length in list {
+                self.push_many(length, 1);
+            }
+            (self.count, self.total) = (count, total);
+        }
+    }
+
+    /// Number of records.
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Sum of all lengths.
+    pub fn total(&self) -> usize {
+        self.total
+    }
+
+    /// Whether the lengths have been folded into a histogram.
+    #[cfg(test)]
+    pub fn is_histogram(&self) -> bool {
+        matches!(self.repr, Repr::Histogram { .. })
+    }
+
+    /// Distinct lengths with their record counts, longest first.
+    pub fn descending(&mut self) -> Vec<(usize, usize)> {
+        match &mut self.repr {
+            Repr::List(list) => {
+                list.sort_unstable_by(|a, b| b.cmp(a));
+                list.chunk_by(|a, b| a == b)
+                    .map(|run| (run[0], run.len()))
+                    .collect()
+            }
+            Repr::Histogram { dense, sparse } => sparse
+                .iter()
+                .rev()
+                .map(|(&length, &count)| (length, count))
+                .chain(
+                    dense
+                        .iter()
+                        .enumerate()
+                        .rev()
+                        .filter(|&(_, &count)| count > 0)
+                        .map(|(length, &count)| (length, count)),
+                )
+                .collect(),
+        }
+    }
+
+    /// Number and total length of the records at or above each of `thresholds`,
+    /// which must be ascending.
+    pub fn at_least(&mut self, thresholds: &[usize]) -> Vec<LengthThreshold> {
+        let groups = self.descending();
+        let mut result: Vec<LengthThreshold> = thresholds
+            .iter()
+            .map(|&min_length| LengthThreshold {
+                min_length,
+                count: 0,
+                total_length: 0,
+            })
+            .collect();
+        // Walk the thresholds from the highest down as the lengths fall.
+        let mut below = result.len();
+        let (mut count, mut total_length) = (0, 0);
+        for (length, records) in groups {
+            while below > 0 && length < thresholds[below - 1] {
+                below -= 1;
+                (result[below].count, result[below].total_length) = (count, total_length);
+            }
+            count += records;
+            total_length += length * records;
+        }
+        for threshold in &mut result[..below] {
+            (threshold.count, threshold.total_length) = (count, total_length);
+        }
+        result
+    }
+}
+
+impl From<Vec<usize>> for SequenceLengths {
+    fn from(list: Vec<usize>) -> Self {
+        let mut lengths = SequenceLengths::new();
+        for length in list {
+            lengths.push(length);
+        }
+        lengths
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_histogram_matches_list() {
+        let values: Vec<usize> = (0..5000)
+            .map(|i| (i * 7919) % 3001 + (i % 13) * 70_000)
+            .collect();
+        let mut list = SequenceLengths::from(values.clone());
+        let mut histogram = SequenceLengths::from(values);
+        histogram.fold_into_histogram();
+
+        assert!(!list.is_histogram());
+        assert!(histogram.is_histogram());
+        assert_eq!(list.len(), histogram.len());
+        assert_eq!(list.total(), histogram.total());
+        assert_eq!(list.descending(), histogram.descending());
+    }
+
+    #[test]
+    fn test_switches_fold_into_histogram_above_threshold() {
+        let mut lengths = SequenceLengths::new();
+        for i in 0..=LOW_MEMORY_THRESHOLD {
+            lengths.push(i % 150);
+        }
+        assert!(lengths.is_histogram());
+        assert_eq!(lengths.len(), LOW_MEMORY_THRESHOLD + 1);
+        assert_eq!(
+            lengths.descending()[0],
+            (149, (LOW_MEMORY_THRESHOLD + 1) / 150)
+        );
+    }
+
+    #[test]
+    fn test_at_least_thresholds() {
+        let mut lengths = SequenceLengths::from(vec![500, 1000, 4999, 12_000, 0, 60_000]);
+        let found: Vec<_> = lengths
+            .at_least(&[0, 1000, 5000, 10_000, 25_000, 50_000, 100_000])
+            .iter()
+            .map(|t| (t.min_length, t.count, t.total_length))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (0, 6, 78_499),
+                (1000, 4, 77_999),
+                (5000, 2, 72_000),
+                (10_000, 2, 72_000),
+                (25_000, 1, 60_000),
+                (50_000, 1, 60_000),
+                (100_000, 0, 0),
+            ]
+        );
+        assert!(SequenceLengths::new().at_least(&[0])[0].count == 0);
+    }
+
+    #[test]
+    fn test_length_distribution() {
+        let mut lengths = SequenceLengths::from(vec![1, 3, 3, 7, 150, 0, 4, 1999, 3]);
+        let distribution = LengthDistribution::from_groups(&lengths.descending());
+        // Sorted: 0 1 3 3 3 4 7 150 1999
+        assert_eq!(distribution.median, 3.0);
+        assert_eq!(distribution.lower_quartile, 3.0);
+        assert_eq!(distribution.upper_quartile, 7.0);
+        let mean = 2170.0 / 9.0;
+        let variance = [0.0, 1.0, 3.0, 3.0, 3.0, 4.0, 7.0, 150.0, 1999.0]
+            .iter()
+            .map(|l: &f64| (l - mean).powi(2))
+            .sum::<f64>()
+            / 9.0;
+        assert!((distribution.std_dev - variance.sqrt()).abs() < 1e-9);
+
+        let bins: Vec<_> = distribution
+            .histogram
+            .iter()
+            .map(|b| (b.min, b.max, b.count, b.total_length))
+            .collect();
+        assert_eq!(
+            bins,
+            vec![
+                (0, 1, 1, 0),
+                (1, 2, 1, 1),
+                (2, 5, 4, 13),
+                (5, 10, 1, 7),
+                (10, 20, 0, 0),
+                (20, 50, 0, 0),
+                (50, 100, 0, 0),
+                (100, 200, 1, 150),
+                (200, 500, 0, 0),
+                (500, 1000, 0, 0),
+                (1000, 2000, 1, 1999),
+            ]
+        );
+
+        let even = LengthDistribution::from_groups(&SequenceLengths::from(vec![10, 20, 30, 40]).descending());
+        assert_eq!((even.lower_quartile, even.median, even.upper_quartile), (17.5, 25.0, 32.5));
+        assert_eq!(LengthDistribution::from_groups(&[]), LengthDistribution::default());
+        assert_eq!(bin_bounds(usize::MAX).1, usize::MAX);
+    }
+
+    #[test]
+    fn test_append_mixed_representations() {
+        let mut histogram = SequenceLengths::from(vec![5, 100_000, 5]);
+        histogram.fold_into_histogram();
+        let mut list = SequenceLengths::from(vec![7, 5]);
+        list.append(&histogram);
+        assert!(list.is_histogram());
+        assert_eq!(list.len(), 5);
+        assert_eq!(list.total(), 100_022);
+        assert_eq!(list.descending(), vec![(100_000, 1), (7, 1), (5, 3)]);
+    }
+}
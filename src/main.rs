@@ -10,17 +10,44 @@
 //! - **Low memory usage**: Processes files in chunks to minimize footprint.
 //! - **Zero-copy reads**: Leverages memory mapping and buffered I/O to avoid unnecessary data duplication.
 //! - **One file per core**: Utilizes parallel processing with Rayon, scaling efficiently across available CPUs.
+//!   Large uncompressed files are additionally split at record boundaries so a single genome can use every core,
+//!   and files are started largest first so a big genome listed last does not become a long tail.
 //! - **Efficient I/O**: Optimizes OS-level read-ahead and sequential access patterns.
-//! - **SIMD optimizations**: Employs AVX2 instructions for rapid sequence analysis and statistics calculation.
+//! - **SIMD optimizations**: Picks an SSE2, AVX2, AVX-512 or NEON kernel at runtime for rapid sequence analysis.
 
 use clap::{CommandFactory, FromArgMatches, Parser};
-use rayon::prelude::*;
 use std::cmp::min;
 use std::io::{self, Write};
-use std::path::{Path, PathBuf};
+use std::num::NonZeroUsize;
+use std::path::PathBuf;
+use std::sync::Arc;
 
+use combine::GroupBy;
+use gc_track::GcWindow;
+use lengths::LengthFilter;
+use molecule::MoleculeType;
+use process_files::{Alphabet, ProcessOptions};
+use simd::SimdLevel;
+
+mod checksum;
+mod combine;
+mod duplicates;
+mod fai;
+mod fastq;
+mod gc_track;
+mod gzi;
+mod ids;
+mod json;
+mod lengths;
+mod molecule;
+mod parallel_decode;
 mod process_files;
+mod protein;
+mod schedule;
+mod select;
 mod simd;
+mod sink;
+mod validate;
 
 #[derive(Parser, Debug)]
 #[clap(
@@ -32,9 +59,12 @@ mod simd;
 This tool calculates assembly statistics (N50, GC%, total length, etc.) for FASTA files, supporting a wide range of compression formats. It leverages multi-threading (Rayon) and vectorization (AVX2/AVX512/NEON) to process data at maximum speed.
 
 SUPPORTED FORMATS:
-  • Uncompressed: .fa, .fasta, .fna
+  • Uncompressed: .fa, .fasta, .fna, .faa, .fq, .fastq
   • Compressed:   .gz, .bgz, .bgzip (Block GZIP), .xz, .bz2, .naf (Nucleotide Archive)
   • Archives:     .zip (processes all valid FASTA files inside)
+  FASTQ is recognised by its leading '@' in any of these, and also reports mean/median
+  base quality and Q20/Q30 percentages. Protein FASTA is recognised from its residues
+  (or forced with --alphabet protein) and reports amino-acid composition instead of GC.
 
 TUTORIAL & EXAMPLES:
 
@@ -55,14 +85,29 @@ TUTORIAL & EXAMPLES:
      $ count-fasta-rs -c results.csv -d ./genomes
    (Note: If 'results.csv' exists, new rows are appended. If not, it's created with a header.)
 
-5. Performance Tuning
+5. Combined Reports
+   Add a report over the union of all files (e.g. one FASTA per chromosome):
+     $ count-fasta-rs --combine chr*.fa
+   Or one report per directory, or per regex match (first capture group if any):
+     $ count-fasta-rs --group-by dir -d ./asm1 -d ./asm2
+     $ count-fasta-rs --group-by '(GCA_[0-9]+)' *.fna.gz
+
+6. Validation
+   Check FASTA input strictly (invalid characters, empty records, empty or duplicate IDs,
+   text before the first header, NUL bytes, mixed line endings). Problems are listed with
+   file, line and byte offset, and the exit status is non-zero:
+     $ count-fasta-rs --validate assembly.fa.gz
+
+7. Performance Tuning
    - Threads: By default, it uses all available cores. Limit this with -t:
      $ count-fasta-rs -t 4 genome.fna
-   - SIMD: If you encounter issues or want to compare scalar performance:
+   - SIMD: The fastest kernel for the CPU is picked at runtime (see --version). To force one,
+     or to compare against scalar performance:
+     $ count-fasta-rs --simd-level avx2 genome.fna
      $ count-fasta-rs --no-simd genome.fna
 
 NOTES:
-  - Gzip (.gz) files are assumed to be standard gzip. For random access optimized BGZIP, use .bgz or .bgzip extensions if possible, though .gz works for sequential reading.
+  - Gzip (.gz) files written by bgzip are recognised as BGZF from their first block header, as are .bgz and .bgzip files.
   - The tool uses zero-copy reading where possible (mmap) to keep memory usage low, even for huge files."
 )]
 struct Args {
@@ -73,6 +118,10 @@ struct Args {
     #[clap(short, long, value_hint = clap::ValueHint::FilePath)]
     csv: Option<String>,
 
+    /// Also write the results as a JSON array to this file.
+    #[clap(long, value_name = "FILE", value_hint = clap::ValueHint::FilePath)]
+    json: Option<PathBuf>,
+
     /// Directory to process (non-recursive).
     ///
     /// The program will process all valid FASTA files found in these directories.
@@ -82,7 +131,7 @@ struct Args {
     /// Number of threads to use.
     ///
     /// If not specified, the program will automatically determine the number of threads based on
-    /// available CPUs and file count.
+    /// available CPUs. Large uncompressed files are split across threads as well.
     #[clap(short, long)]
     threads: Option<usize>,
 
@@ -94,27 +143,252 @@ struct Args {
 
     /// FASTA file(s) to process.
     ///
-    /// Supports wildcards. Inside a zip file, only .fa, .fasta, and .fna files will be processed.
-    /// Gzip (.gz) files are assumed to be standard gzip; bgzip files should ideally use .bgz or .bgzip.
+    /// Supports wildcards. Inside a zip file, only .fa, .fasta, .fna, .faa, .fq and .fastq files will be processed.
+    /// Gzip (.gz) files written by bgzip are read as BGZF, like .bgz and .bgzip files.
     #[clap(name = "FASTA FILE", value_hint = clap::ValueHint::FilePath)]
     files: Vec<String>,
 
     /// Disable SIMD optimizations (force scalar fallback).
     ///
     /// Useful for debugging or if SIMD causes issues on specific hardware.
-    #[clap(long)]
+    #[clap(long, conflicts_with = "simd_level")]
     no_simd: bool,
+
+    /// SIMD kernel used for nucleotide counting.
+    ///
+    /// By default the fastest kernel supported by the running CPU is chosen at startup.
+    /// Forcing a kernel the CPU lacks is an error.
+    #[clap(long, value_enum)]
+    simd_level: Option<SimdLevel>,
+
+    /// Also report statistics over all input files together, named "combined".
+    ///
+    /// Repeats are only tracked within a file, so --duplicate-ids and
+    /// --duplicate-sequences cannot be used with it.
+    #[clap(long, conflicts_with_all = ["duplicate_ids", "duplicate_sequences", "list_duplicates"])]
+    combine: bool,
+
+    /// Also report statistics per group of files.
+    ///
+    /// `dir` groups files by parent directory. Any other value is a regular expression
+    /// matched against the file path; files are grouped by the first capture group, or
+    /// by the whole match if there is none. Files that do not match are not grouped.
+    /// Like --combine, it cannot be used with --duplicate-ids or --duplicate-sequences.
+    #[clap(
+        long,
+        value_name = "dir|REGEX",
+        value_parser = GroupBy::parse,
+        conflicts_with_all = ["duplicate_ids", "duplicate_sequences", "list_duplicates"]
+    )]
+    group_by: Option<GroupBy>,
+
+    /// Sequence alphabet of the input.
+    ///
+    /// `auto` detects protein FASTA from its residues. Protein files report amino-acid
+    /// composition, X and stop (`*`) counts and molecular weights instead of GC and N.
+    #[clap(long, value_enum, default_value_t = Alphabet::Auto)]
+    alphabet: Alphabet,
+
+    /// Check FASTA input strictly and exit non-zero if anything is wrong.
+    ///
+    /// Reports invalid sequence characters, empty sequences, empty or duplicate IDs, text
+    /// before the first header, NUL bytes and mixed line endings, each with its file, line
+    /// and byte offset (in the decompressed stream). FASTQ and NAF input is not checked.
+    #[clap(long)]
+    validate: bool,
+
+    /// Count records whose ID repeats an earlier one, and name a few.
+    ///
+    /// The ID is the header up to the first whitespace. IDs are held as 64-bit hashes;
+    /// past about 8 million per file they move into a 128 MiB Bloom filter and the
+    /// count is marked approximate, with false repeats growing from about 1% of new
+    /// IDs at 100 million to 90% at a billion.
+    #[clap(long)]
+    duplicate_ids: bool,
+
+    /// Count records whose sequence repeats an earlier one, on either strand.
+    ///
+    /// Sequences are compared by a canonical hash of their upper-cased bases, ignoring
+    /// line wrapping and gaps, so a contig and its reverse complement match. FASTA only.
+    #[clap(long)]
+    duplicate_sequences: bool,
+
+    /// Like --duplicate-sequences, and also list each duplicate with the record it repeats.
+    #[clap(long)]
+    list_duplicates: bool,
+
+    /// Compute MD5 and refget (sha512t24u) digests of every sequence.
+    ///
+    /// Sequences are upper-cased and stripped of whitespace, headers are ignored. Each
+    /// file also gets order-independent digests over its sequences. FASTA only.
+    #[clap(long)]
+    checksums: bool,
+
+    /// Write the per-sequence digests to this TSV file (implies --checksums).
+    #[clap(long, value_name = "FILE", value_hint = clap::ValueHint::FilePath)]
+    checksums_out: Option<PathBuf>,
+
+    /// Write a samtools-compatible index next to each input, as `<file>.fai`.
+    ///
+    /// Only plain and BGZF-compressed FASTA can be indexed. Files whose line widths
+    /// vary within a record are reported as errors instead.
+    #[clap(long)]
+    write_fai: bool,
+
+    /// Write the htslib block index of each BGZF input, as `<file>.gzi`.
+    ///
+    /// Built from the block headers while the file is decompressed, so random
+    /// access tools (samtools faidx, htslib) can use the file without re-reading it.
+    #[clap(long)]
+    write_gzi: bool,
+
+    /// Report how sequence lines are wrapped.
+    ///
+    /// Gives the most common line width, the number of records wrapped irregularly
+    /// (lines of differing width before the last), the longest line and whether
+    /// `samtools faidx` can index the file. FASTA only.
+    #[clap(long)]
+    line_widths: bool,
+
+    /// Read record lengths from the existing `<file>.fai` instead of scanning.
+    ///
+    /// Gives length statistics (counts, N50, largest/shortest) for plain and BGZF
+    /// FASTA without decoding any sequence; BGZF also needs its `.gzi`. GC and N
+    /// are reported as unavailable. Files without a usable index are scanned.
+    /// A `.fai` holds only the IDs, so --header-regex cannot be used with it.
+    #[clap(
+        long,
+        conflicts_with_all = ["validate", "duplicate_sequences", "list_duplicates", "checksums", "checksums_out", "write_fai", "write_gzi", "line_widths", "gc_window", "header_regex"]
+    )]
+    from_index: bool,
+
+    /// Count the sequences, and their total length, at or above each length.
+    ///
+    /// Like QUAST's "# contigs (>= 1000 bp)" rows. Give the lengths as
+    /// `--thresholds=1000,5000`; a bare `--thresholds` uses 0,1000,5000,10000,25000,50000.
+    #[clap(
+        long,
+        value_name = "LENGTHS",
+        num_args = 0..=1,
+        require_equals = true,
+        value_delimiter = ',',
+        default_missing_value = "0,1000,5000,10000,25000,50000"
+    )]
+    thresholds: Option<Vec<usize>>,
+
+    /// Leave sequences shorter than this out of every statistic.
+    ///
+    /// Filtered sequences count towards no length, GC or N figure; how many there
+    /// were, and their total length, is reported separately.
+    #[clap(long, value_name = "LENGTH")]
+    min_length: Option<usize>,
+
+    /// Leave sequences longer than this out of every statistic, like --min-length.
+    #[clap(long, value_name = "LENGTH")]
+    max_length: Option<usize>,
+
+    /// Only count the sequences whose ID is listed in this file.
+    ///
+    /// The ID is the header up to the first whitespace. The file holds one ID per
+    /// line; anything after the first whitespace, and a leading `>`, is ignored,
+    /// so a list of headers works too. Other sequences are reported as filtered out.
+    #[clap(long, value_name = "FILE", value_hint = clap::ValueHint::FilePath)]
+    include_ids: Option<PathBuf>,
+
+    /// Leave out the sequences whose ID is listed in this file, like --include-ids.
+    #[clap(long, value_name = "FILE", value_hint = clap::ValueHint::FilePath)]
+    exclude_ids: Option<PathBuf>,
+
+    /// Only count the sequences whose header line matches this regular expression.
+    ///
+    /// The header is matched without its leading `>` or `@`. For `--from-index`
+    /// only the ID is known and matched.
+    #[clap(long, value_name = "REGEX", value_parser = parse_header_regex)]
+    header_regex: Option<regex::bytes::Regex>,
+
+    /// Write the GC % of windows of this many bases along each sequence, as bedGraph.
+    ///
+    /// The track of each input is written next to it, as `<file>.gc.bedgraph`, with
+    /// one line per window: sequence ID, 0-based start, end and GC %. Windows are
+    /// tiled unless --step is given; the last window of a sequence is cut short at
+    /// its end. Nucleotide FASTA and NAF only.
+    #[clap(long, value_name = "SIZE")]
+    gc_window: Option<NonZeroUsize>,
+
+    /// Start a --gc-window window every this many bases, for sliding windows.
+    #[clap(long, value_name = "STEP", requires = "gc_window")]
+    step: Option<NonZeroUsize>,
+
+    /// Also compute the GC skew, (G - C) / (G + C), of the --gc-window windows.
+    ///
+    /// The skew of each window and the cumulative skew, G - C from the start of
+    /// the sequence to the window end, are written next to each input as
+    /// `<file>.gc_skew.tsv`. Each sequence's overall skew and the window ends with
+    /// the lowest and highest cumulative skew, near the origin and terminus of
+    /// replication of a bacterial chromosome, are printed with the results.
+    #[clap(long, requires = "gc_window")]
+    gc_skew: bool,
+
+    /// Also print a histogram of the sequence lengths, in log-spaced bins.
+    ///
+    /// Bins follow a 1-2-5 series (1, 2, 5, 10, 20, 50 ... bp), each with its
+    /// sequence count, total length and a bar. Also added to the JSON output.
+    #[clap(long)]
+    histogram: bool,
+
+    /// Print the selected SIMD kernel and thread count to stderr.
+    #[clap(short, long)]
+    verbose: bool,
 }
 
 fn main() {
-    let cmd = Args::command().after_help(format!(
-        "SIMD Support: Enabled (std::simd)\nDefault max threads: {}",
-        determine_threads(None, None)
-    ));
+    let detected = SimdLevel::detect();
+    let version = format!("{} (SIMD kernel: {})", env!("CARGO_PKG_VERSION"), detected.name());
+    let cmd = Args::command()
+        .version(version.leak() as &str)
+        .after_help(format!(
+            "SIMD kernel: {} (detected at runtime)\nDefault max threads: {}",
+            detected.name(),
+            determine_threads(None)
+        ));
 
     let matches = cmd.get_matches();
     let args = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
 
+    let simd = match (args.no_simd, args.simd_level) {
+        (true, _) => SimdLevel::Scalar,
+        (false, Some(level)) if !level.is_supported() => {
+            eprintln!("SIMD kernel '{}' is not supported on this CPU", level.name());
+            std::process::exit(1);
+        }
+        (false, Some(level)) => level,
+        (false, None) => detected,
+    };
+    if args.verbose {
+        eprintln!(
+            "SIMD kernel: {}\nThreads: {}",
+            simd.name(),
+            determine_threads(args.threads)
+        );
+    }
+
+    let length_filter = LengthFilter {
+        min: args.min_length.unwrap_or(0),
+        max: args.max_length.unwrap_or(usize::MAX),
+    };
+    if length_filter.min > length_filter.max {
+        eprintln!("--min-length must not be greater than --max-length");
+        std::process::exit(1);
+    }
+
+    let header_filter = match header_filter(&args) {
+        Ok(filter) => filter,
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+    };
+
     let mut files_to_process = Vec::new();
     for dir in args.directory {
         match get_fasta_files_from_directory(&dir) {
@@ -127,7 +401,60 @@ fn main() {
     }
     files_to_process.extend(args.files.into_iter().map(PathBuf::from));
 
-    let results = process_files(files_to_process, args.threads, args.no_simd);
+    let options = ProcessOptions {
+        simd,
+        alphabet: args.alphabet,
+        validate: args.validate,
+        duplicate_ids: args.duplicate_ids,
+        duplicate_sequences: args.duplicate_sequences || args.list_duplicates,
+        list_duplicates: args.list_duplicates,
+        checksums: args.checksums || args.checksums_out.is_some(),
+        write_fai: args.write_fai,
+        line_widths: args.line_widths,
+        from_index: args.from_index,
+        write_gzi: args.write_gzi,
+        length_filter,
+        header_filter,
+        gc_window: args.gc_window.map(|size| GcWindow {
+            size: size.get(),
+            step: args.step.unwrap_or(size).get(),
+        }),
+        gc_skew: args.gc_skew,
+    };
+    let keep_lengths = args.combine || args.group_by.is_some();
+    let mut thresholds = args.thresholds.unwrap_or_default();
+    thresholds.sort_unstable();
+    thresholds.dedup();
+    let per_file = process_files(
+        &files_to_process,
+        args.threads,
+        &options,
+        &thresholds,
+        keep_lengths,
+    );
+    let valid = !args.validate || report_validation(&files_to_process, &per_file);
+    let mut reports = combine::aggregate(
+        &files_to_process,
+        &per_file,
+        args.group_by.as_ref(),
+        args.combine,
+    );
+    if !thresholds.is_empty() {
+        reports.iter_mut().for_each(|r| r.count_thresholds(&thresholds));
+    }
+    if let Some(path) = &args.checksums_out
+        && let Err(e) = write_checksums(per_file.iter().flatten(), path)
+    {
+        eprintln!("Failed to write checksums to {:?}: {}", path, e);
+        std::process::exit(1);
+    }
+    let results: Vec<_> = per_file.into_iter().flatten().chain(reports).collect();
+    if let Some(path) = &args.json
+        && let Err(e) = json::write_results(&results, path, args.histogram)
+    {
+        eprintln!("Failed to write JSON to {:?}: {}", path, e);
+        std::process::exit(1);
+    }
 
     if let Some(csv_file) = args.csv {
         if let Err(e) = append_to_csv(&results, &csv_file) {
@@ -136,9 +463,78 @@ fn main() {
         }
     } else {
         for result in results {
-            print_results(&result, args.legacy);
+            print_results(&result, args.legacy, args.histogram, args.gc_skew);
+        }
+    }
+    if !valid {
+        std::process::exit(1);
+    }
+}
+
+fn parse_header_regex(value: &str) -> Result<regex::bytes::Regex, String> {
+    regex::bytes::Regex::new(value).map_err(|e| format!("invalid --header-regex: {e}"))
+}
+
+/// The header filter given on the command line, if any.
+fn header_filter(args: &Args) -> Result<Option<Arc<select::HeaderFilter>>, String> {
+    if args.include_ids.is_none() && args.exclude_ids.is_none() && args.header_regex.is_none() {
+        return Ok(None);
+    }
+    let read = |path: &PathBuf| {
+        select::read_ids(path).map_err(|e| format!("Error reading ID list {:?}: {}", path, e))
+    };
+    let filter = select::HeaderFilter {
+        include: args.include_ids.as_ref().map(read).transpose()?,
+        exclude: args.exclude_ids.as_ref().map(read).transpose()?.unwrap_or_default(),
+        regex: args.header_regex.clone(),
+    };
+    Ok(Some(Arc::new(filter)))
+}
+
+/// Writes one line per sequence: file, ID, length, MD5 and refget digest.
+fn write_checksums<'a>(
+    results: impl Iterator<Item = &'a process_files::AnalysisResults>,
+    path: &std::path::Path,
+) -> io::Result<()> {
+    let mut writer = io::BufWriter::new(std::fs::File::create(path)?);
+    writeln!(writer, "file\tid\tlength\tmd5\trefget")?;
+    for result in results {
+        let Some(checksums) = &result.checksums else {
+            continue;
+        };
+        for sequence in &checksums.sequences {
+            writeln!(
+                writer,
+                "{}\t{}\t{}\t{}\t{}",
+                result.filename, sequence.id, sequence.length, sequence.md5, sequence.refget
+            )?;
         }
     }
+    writer.flush()
+}
+
+/// Prints the `--validate` findings of every file to stderr and returns whether
+/// all checked files were valid.
+fn report_validation(files: &[PathBuf], per_file: &[Vec<process_files::AnalysisResults>]) -> bool {
+    let mut valid = true;
+    for (path, results) in files.iter().zip(per_file) {
+        for result in results {
+            let source = result.source_name(path);
+            let Some(report) = &result.validation else {
+                eprintln!("{source}: not validated (only FASTA input is checked)");
+                continue;
+            };
+            for issue in &report.issues {
+                eprintln!("{source}: line {}, byte {}: {}", issue.line, issue.offset, issue.kind);
+            }
+            let unlisted = report.total - report.issues.len();
+            if unlisted > 0 {
+                eprintln!("{source}: {unlisted} more issues not listed");
+            }
+            valid &= report.is_valid();
+        }
+    }
+    valid
 }
 
 fn get_fasta_files_from_directory(dir: &str) -> std::io::Result<Vec<PathBuf>> {
@@ -155,96 +551,393 @@ fn get_fasta_files_from_directory(dir: &str) -> std::io::Result<Vec<PathBuf>> {
     Ok(files)
 }
 
+/// Returns the results of each input file, in input order. `thresholds` are
+/// counted before the record lengths are dropped; those are only kept when
+/// `keep_lengths` is set, as merging files needs them.
 fn process_files(
-    files: Vec<PathBuf>,
+    files: &[PathBuf],
     threads: Option<usize>,
-    no_simd: bool,
-) -> Vec<process_files::AnalysisResults> {
-    let available_threads = determine_threads(Some(&files), threads);
+    options: &ProcessOptions,
+    thresholds: &[usize],
+    keep_lengths: bool,
+) -> Vec<Vec<process_files::AnalysisResults>> {
+    let available_threads = determine_threads(threads);
     let pool = rayon::ThreadPoolBuilder::new()
         .num_threads(available_threads)
         .build()
         .unwrap();
-    pool.install(|| {
-        files
-            .par_iter()
-            .flat_map(
-                |file| match process_files::process_any_file(file, no_simd) {
-                    Ok(v) => v,
-                    Err(e) => {
-                        eprintln!("Error processing file {:?}: {}", file, e);
-                        Vec::new()
+    schedule::run_largest_first(
+        files,
+        available_threads,
+        available_threads,
+        schedule::MAX_HEAVY_DECOMPRESSIONS,
+        |file, decoder_threads| {
+            pool.install(|| match process_files::process_any_file(file, options, decoder_threads) {
+                Ok(mut v) => {
+                    if !thresholds.is_empty() {
+                        v.iter_mut().for_each(|r| r.count_thresholds(thresholds));
+                    }
+                    if !keep_lengths {
+                        v.iter_mut().for_each(|r| r.lengths = Default::default());
                     }
-                },
-            )
-            .collect()
-    })
+                    v
+                }
+                Err(e) => {
+                    eprintln!("Error processing file {:?}: {}", file, e);
+                    Vec::new()
+                }
+            })
+        },
+    )
 }
 
-fn determine_threads(files: Option<&[PathBuf]>, threads: Option<usize>) -> usize {
+/// The pool is not capped by the number of files: large uncompressed inputs are split
+/// across threads, so even a single file can keep every worker busy.
+fn determine_threads(threads: Option<usize>) -> usize {
     let available_threads;
     if let Some(threads) = threads {
         available_threads = threads;
     } else {
         let usable_threads_logical = (num_cpus::get() as f32 * 0.9).round() as usize;
         let usable_physical_threads = (num_cpus::get_physical() as f32 * 0.75).round() as usize;
-        let usable_threads = min(usable_threads_logical, usable_physical_threads);
-
-        available_threads = min(usable_threads, files.map_or(usize::MAX, |f| f.len()));
+        available_threads = min(usable_threads_logical, usable_physical_threads).max(1);
     }
     available_threads
 }
 
-fn print_results(results: &process_files::AnalysisResults, legacy: bool) {
+fn print_results(results: &process_files::AnalysisResults, legacy: bool, histogram: bool, gc_skew: bool) {
+    let unit = if results.protein.is_some() { "aa" } else { "bp" };
     if !legacy {
         println!("\nFile name:\t{} ", results.filename);
     } else {
         println!();
     }
-    println!("Total length of sequence:\t{} bp", results.total_length);
+    println!("Total length of sequence:\t{} {unit}", results.total_length);
     println!("Total number of sequences:\t{}", results.sequence_count);
     let avg_len = results
         .total_length
         .checked_div(results.sequence_count)
         .unwrap_or(0);
-    println!("Average contig length is:\t{} bp", avg_len);
-    println!("Largest contig:\t\t{} bp", results.largest_contig);
-    println!("Shortest contig:\t\t{} bp", results.shortest_contig);
+    println!("Average contig length is:\t{} {unit}", avg_len);
+    println!("Largest contig:\t\t{} {unit}", results.largest_contig);
+    println!("Shortest contig:\t\t{} {unit}", results.shortest_contig);
+    if !legacy {
+        let distribution = &results.length_distribution;
+        println!("Median contig length:\t\t{} {unit}", distribution.median);
+        println!(
+            "Length quartiles (Q1, Q3):\t{} {unit}, {} {unit}",
+            distribution.lower_quartile, distribution.upper_quartile
+        );
+        println!("Length standard deviation:\t{:.2} {unit}", distribution.std_dev);
+    }
     println!(
-        "N25 stats:\t\t\t25% of total sequence length is contained in the {} sequences >= {} bp",
+        "N25 stats:\t\t\t25% of total sequence length is contained in the {} sequences >= {} {unit}",
         results.n25_sequence_count, results.n25
     );
     println!(
-        "N50 stats:\t\t\t50% of total sequence length is contained in the {} sequences >= {} bp",
+        "N50 stats:\t\t\t50% of total sequence length is contained in the {} sequences >= {} {unit}",
         results.n50_sequence_count, results.n50
     );
     println!(
-        "N75 stats:\t\t\t75% of total sequence length is contained in the {} sequences >= {} bp",
+        "N75 stats:\t\t\t75% of total sequence length is contained in the {} sequences >= {} {unit}",
         results.n75_sequence_count, results.n75
     );
-    println!("Total GC count:\t\t\t{} bp", results.gc_count);
-    println!(
-        "GC %:\t\t\t\t{:.2} %",
-        (results.gc_count as f64 / results.total_length as f64) * 100.0
-    );
-    println!("Number of Ns:\t\t\t{}", results.n_count);
+    if let Some(protein) = &results.protein {
+        print_protein(protein);
+    } else if results.lengths_only {
+        println!("Total GC count:\t\t\tunavailable (lengths read from .fai index)");
+        println!("Number of Ns:\t\t\tunavailable (lengths read from .fai index)");
+    } else {
+        println!("Total GC count:\t\t\t{} bp", results.gc_count);
+        println!(
+            "GC %:\t\t\t\t{:.2} %",
+            (results.gc_count as f64 / results.total_length as f64) * 100.0
+        );
+        println!("Number of Ns:\t\t\t{}", results.n_count);
+        println!(
+            "Ns %:\t\t\t\t{:.2} %",
+            (results.n_count as f64 / results.total_length as f64) * 100.0
+        );
+    }
+    if !legacy && !results.lengths_only {
+        println!("Molecule type:\t\t\t{}", results.molecule_type().name());
+    }
+    if let Some(filtered) = &results.filtered {
+        println!(
+            "Filtered out:\t\t\t{} sequences ({} {unit})",
+            filtered.records, filtered.bases
+        );
+    }
+    if let Some(duplicates) = &results.duplicate_ids {
+        print_duplicate_ids(duplicates);
+    }
+    if !results.length_thresholds.is_empty() {
+        println!("Length thresholds:");
+        for t in &results.length_thresholds {
+            println!(
+                "  >= {} {unit}:\t\t{} sequences, {} {unit}",
+                t.min_length, t.count, t.total_length
+            );
+        }
+    }
+    if histogram {
+        print_length_histogram(&results.length_distribution.histogram, unit);
+    }
+    if let Some(line_widths) = results.line_widths() {
+        print_line_widths(line_widths, unit);
+    }
+    if gc_skew && let Some(track) = &results.gc_track {
+        print_gc_skew(track);
+    }
+    if let Some(checksums) = &results.checksums {
+        println!("Sequence MD5 (any order):\t{}", checksums.file_md5);
+        println!("Sequence refget (any order):\t{}", checksums.file_refget);
+    }
+    if let Some(duplicates) = &results.duplicate_sequences {
+        println!(
+            "Duplicate sequences:\t\t{} ({} {unit})",
+            duplicates.count, duplicates.bases
+        );
+        for (id, original, reverse) in &duplicates.listed {
+            let relation = if *reverse { "reverse complement of" } else { "same as" };
+            println!("  {id}\t{relation} {original}");
+        }
+    }
+    if let Some(quality) = &results.quality {
+        println!("Mean base quality:\t\t{:.2}", quality.mean());
+        println!("Median base quality:\t\t{}", quality.median());
+        println!("Q20 bases %:\t\t\t{:.2} %", quality.percent_at_least(20));
+        println!("Q30 bases %:\t\t\t{:.2} %", quality.percent_at_least(30));
+    }
+}
+
+/// Width of the longest bar in the length histogram.
+const HISTOGRAM_BAR_WIDTH: usize = 40;
+
+fn print_length_histogram(bins: &[lengths::LengthBin], unit: &str) {
+    println!("Length histogram:");
+    println!("  {:>21}  {:>12}  {:>15}", format!("length ({unit})"), "sequences", format!("total {unit}"));
+    let most = bins.iter().map(|bin| bin.count).max().unwrap_or(0);
+    for bin in bins {
+        let range = if bin.max - bin.min == 1 {
+            bin.min.to_string()
+        } else {
+            format!("{}-{}", bin.min, bin.max - 1)
+        };
+        let bar = "#".repeat((bin.count * HISTOGRAM_BAR_WIDTH).div_ceil(most.max(1)));
+        println!("  {range:>21}  {:>12}  {:>15}  {bar}", bin.count, bin.total_length);
+    }
+}
+
+fn print_gc_skew(track: &gc_track::GcTrack) {
+    println!("GC skew (cumulative G - C minimum, maximum):");
+    for record in &track.records {
+        let summary = &record.skew_summary;
+        println!(
+            "  {}\t{:.4}\tmin {} at {} bp\tmax {} at {} bp",
+            record.name,
+            summary.skew,
+            summary.min,
+            summary.min_position,
+            summary.max,
+            summary.max_position
+        );
+    }
+}
+
+fn print_line_widths(line_widths: &fai::LineWidths, unit: &str) {
+    let mut line = match line_widths.mode() {
+        Some(width) => format!("Line width:\t\t\t{width} {unit}"),
+        None => "Line width:\t\t\tunwrapped".to_string(),
+    };
+    if line_widths.irregular_records > 0 {
+        line.push_str(&format!(
+            ", {} sequences irregularly wrapped",
+            line_widths.irregular_records
+        ));
+    }
+    if line_widths.faidx_compatible() {
+        line.push_str(" (faidx-compatible)");
+    } else {
+        line.push_str(" (not faidx-compatible)");
+    }
+    println!("{line}");
+    println!("Longest line:\t\t\t{} {unit}", line_widths.max_line);
+}
+
+fn print_duplicate_ids(duplicates: &ids::DuplicateIds) {
+    let mut line = format!("Duplicate IDs:\t\t\t{}", duplicates.count);
+    if duplicates.approximate {
+        line.push_str(" (approximate)");
+    }
+    if !duplicates.examples.is_empty() {
+        line.push_str(&format!(", e.g. {}", duplicates.examples.join(", ")));
+    }
+    println!("{line}");
+}
+
+fn print_protein(protein: &protein::ProteinStats) {
+    let residues = protein.residues().max(1) as f64;
+    println!("Unknown residues (X):\t\t{}", protein.x_count());
+    println!("Stop codons (*):\t\t{}", protein.stop_count);
     println!(
-        "Ns %:\t\t\t\t{:.2} %",
-        (results.n_count as f64 / results.total_length as f64) * 100.0
+        "Molecular weight:\t\tmin {:.1} Da, mean {:.1} Da, max {:.1} Da",
+        protein.min_weight,
+        protein.mean_weight(),
+        protein.max_weight
     );
+    let composition: Vec<String> = (b'A'..=b'Z')
+        .zip(protein.composition)
+        .filter(|&(_, count)| count > 0)
+        .map(|(letter, count)| format!("{} {:.2} %", letter as char, count as f64 / residues * 100.0))
+        .collect();
+    println!("Amino-acid composition:\t\t{}", composition.join(", "));
 }
 
+/// Columns appended to the CSV only when a result needs them, so plain FASTA CSVs
+/// keep their layout. `needed` tells whether a result calls for the group, and
+/// `values` returns the fields, without the leading `;`, or `None` to leave them
+/// empty. `header` names the fields after a result that needs them, as some
+/// depend on the options, such as the `--thresholds` lengths.
+struct OptionalColumns {
+    header: fn(&process_files::AnalysisResults) -> String,
+    needed: fn(&process_files::AnalysisResults) -> bool,
+    values: fn(&process_files::AnalysisResults) -> Option<String>,
+}
+
+const OPTIONAL_COLUMNS: &[OptionalColumns] = &[
+    OptionalColumns {
+        header: |_| "mean_quality;median_quality;Q20_percentage;Q30_percentage".to_string(),
+        needed: |r| r.quality.is_some(),
+        values: |r| {
+            r.quality.as_ref().map(|q| {
+                format!(
+                    "{:.7};{};{:.7};{:.7}",
+                    q.mean(),
+                    q.median(),
+                    q.percent_at_least(20),
+                    q.percent_at_least(30)
+                )
+            })
+        },
+    },
+    OptionalColumns {
+        header: |_| "X_count;stop_count;min_MW;mean_MW;max_MW".to_string(),
+        needed: |r| r.protein.is_some(),
+        values: |r| {
+            r.protein.as_ref().map(|p| {
+                format!(
+                    "{};{};{:.2};{:.2};{:.2}",
+                    p.x_count(),
+                    p.stop_count,
+                    p.min_weight,
+                    p.mean_weight(),
+                    p.max_weight
+                )
+            })
+        },
+    },
+    OptionalColumns {
+        header: |_| "duplicate_ids".to_string(),
+        needed: |r| r.duplicate_ids.as_ref().is_some_and(|d| d.count > 0),
+        values: |r| r.duplicate_ids.as_ref().map(|d| d.count.to_string()),
+    },
+    OptionalColumns {
+        header: |_| "duplicate_sequences;duplicated_bases".to_string(),
+        needed: |r| r.duplicate_sequences.is_some(),
+        values: |r| {
+            r.duplicate_sequences
+                .as_ref()
+                .map(|d| format!("{};{}", d.count, d.bases))
+        },
+    },
+    OptionalColumns {
+        header: |_| "sequence_md5;sequence_refget".to_string(),
+        needed: |r| r.checksums.is_some(),
+        values: |r| {
+            r.checksums
+                .as_ref()
+                .map(|c| format!("{};{}", c.file_md5, c.file_refget))
+        },
+    },
+    OptionalColumns {
+        header: |_| "line_width;irregular_wrapping;max_line_length;faidx_compatible".to_string(),
+        needed: |r| r.line_widths().is_some(),
+        values: |r| {
+            r.line_widths().map(|l| {
+                format!(
+                    "{};{};{};{}",
+                    l.mode().map_or(String::new(), |width| width.to_string()),
+                    l.irregular_records,
+                    l.max_line,
+                    l.faidx_compatible()
+                )
+            })
+        },
+    },
+    OptionalColumns {
+        header: |r| {
+            let names: Vec<String> = r
+                .length_thresholds
+                .iter()
+                .map(|t| format!("sequences_ge_{0};length_ge_{0}", t.min_length))
+                .collect();
+            names.join(";")
+        },
+        needed: |r| !r.length_thresholds.is_empty(),
+        values: |r| {
+            let fields: Vec<String> = r
+                .length_thresholds
+                .iter()
+                .map(|t| format!("{};{}", t.count, t.total_length))
+                .collect();
+            (!fields.is_empty()).then(|| fields.join(";"))
+        },
+    },
+    OptionalColumns {
+        header: |_| "filtered_sequences;filtered_bases".to_string(),
+        needed: |r| r.filtered.is_some(),
+        values: |r| r.filtered.map(|f| format!("{};{}", f.records, f.bases)),
+    },
+    OptionalColumns {
+        header: |_| "molecule_type".to_string(),
+        // DNA is what the fixed columns assume.
+        needed: |r| !matches!(r.molecule_type(), MoleculeType::Dna | MoleculeType::Unknown),
+        values: |r| Some(r.molecule_type().name().to_string()),
+    },
+];
+
+/// When appending, the existing header decides which optional columns are written.
 fn append_to_csv(results: &[process_files::AnalysisResults], csv_filename: &str) -> io::Result<()> {
-    let csv_exists = Path::new(csv_filename).exists();
+    let existing_header = match std::fs::File::open(csv_filename) {
+        Ok(f) => Some(io::BufRead::lines(io::BufReader::new(f)).next().transpose()?.unwrap_or_default()),
+        Err(_) => None,
+    };
+    let optional: Vec<(&OptionalColumns, String)> = OPTIONAL_COLUMNS
+        .iter()
+        .filter_map(|columns| {
+            let needing = results.iter().find(|r| (columns.needed)(r));
+            let names = (columns.header)(needing.or(results.first())?);
+            let wanted = match &existing_header {
+                Some(header) => !names.is_empty() && header.contains(&names),
+                None => needing.is_some(),
+            };
+            wanted.then_some((columns, names))
+        })
+        .collect();
     let file = std::fs::OpenOptions::new()
         .create(true)
         .append(true)
         .open(csv_filename)?;
     let mut writer = std::io::BufWriter::new(file);
 
-    if !csv_exists {
-        let header = "filename;assembly_length;number_of_sequences;average_length;largest_contig;shortest_contig;N50;GC_percentage;total_N;N_percentage\n";
-        writer.write_all(header.as_bytes())?;
+    if existing_header.is_none() {
+        let mut header = "filename;assembly_length;number_of_sequences;average_length;largest_contig;shortest_contig;N50;GC_percentage;total_N;N_percentage".to_string();
+        for (_, names) in &optional {
+            header.push(';');
+            header.push_str(names);
+        }
+        writeln!(writer, "{header}")?;
     }
 
     for result in results {
@@ -264,9 +957,9 @@ fn append_to_csv(results: &[process_files::AnalysisResults], csv_filename: &str)
             0.0
         };
 
-        writeln!(
+        write!(
             writer,
-            "{};{};{};{};{};{};{};{:.7};{};{:.7}",
+            "{};{};{};{};{};{};{};",
             result.filename,
             result.total_length,
             result.sequence_count,
@@ -274,10 +967,20 @@ fn append_to_csv(results: &[process_files::AnalysisResults], csv_filename: &str)
             result.largest_contig,
             result.shortest_contig,
             result.n50,
-            gc_pct,
-            result.n_count,
-            n_pct,
         )?;
+        // GC and N have no meaning for protein, and are not in a .fai.
+        if result.protein.is_some() || result.lengths_only {
+            write!(writer, ";;")?;
+        } else {
+            write!(writer, "{:.7};{};{:.7}", gc_pct, result.n_count, n_pct)?;
+        }
+        for (columns, names) in &optional {
+            match (columns.values)(result) {
+                Some(values) => write!(writer, ";{values}")?,
+                None => write!(writer, "{}", ";".repeat(names.split(';').count()))?,
+            }
+        }
+        writeln!(writer)?;
     }
 
     writer.flush()?;
@@ -287,6 +990,7 @@ fn append_to_csv(results: &[process_files::AnalysisResults], csv_filename: &str)
 #[cfg(test)]
 mod tests {
     use std::fs;
+    use std::path::Path;
 
     use super::*;
     use std::iter::zip as zip_things;
@@ -294,18 +998,18 @@ mod tests {
     fn it_works() {
         let mut files_to_process = Vec::new();
 
-        if let Ok(files) = get_fasta_files_from_directory(&"./test/") {
+        if let Ok(files) = get_fasta_files_from_directory("./test/") {
             files_to_process.extend(files);
         }
 
-        let results = process_files(files_to_process, None, false);
+        let results = process_files(&files_to_process, None, &ProcessOptions::default(), &[], false).concat();
 
         let csv_file = "test/attempt.csv";
         if Path::new(csv_file).exists() {
             let _ = fs::remove_file(csv_file);
         }
 
-        append_to_csv(&results, &csv_file).expect("Failed to write CSV");
+        append_to_csv(&results, csv_file).expect("Failed to write CSV");
         let mut thing: Vec<String> = fs::read_to_string("test/test.csv")
             .unwrap()
             .lines()
@@ -0,0 +1,127 @@
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0>
+// at your option. This file may not be copied, modified,
+// or distributed except according to those terms.
+
+//! Molecule-type detection.
+//!
+//! Every file is classified from the composition gathered while it was parsed:
+//! nucleotide files from their T, U and nucleotide counts, protein files from
+//! their residue composition. The result is reported alongside the statistics and
+//! checked against the parser that was used, so a proteome read as DNA (or an
+//! assembly forced through `--alphabet protein`) does not go unnoticed.
+
+use crate::process_files::Alphabet;
+use crate::protein::ProteinStats;
+
+/// Share of residues, in percent, that may fall outside the expected alphabet
+/// before a file stops counting as that alphabet.
+const TOLERANCE_PERCENT: usize = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MoleculeType {
+    Dna,
+    Rna,
+    Protein,
+    /// Too many non-nucleotide letters for DNA or RNA, or both T and U.
+    Mixed,
+    /// No residues to judge from.
+    Unknown,
+}
+
+/// `part` is more than `TOLERANCE_PERCENT` percent of `whole`.
+fn significant(part: usize, whole: usize) -> bool {
+    part * 100 > whole * TOLERANCE_PERCENT
+}
+
+impl MoleculeType {
+    /// Classifies nucleotide-parsed residues. `residues` counts every sequence
+    /// character, `nucleotides` those that are A, C, G, T, U or N.
+    pub fn classify(residues: usize, nucleotides: usize, t: usize, u: usize) -> Self {
+        if residues == 0 {
+            return MoleculeType::Unknown;
+        }
+        if significant(residues.saturating_sub(nucleotides), residues) {
+            return MoleculeType::Mixed;
+        }
+        let thymine_or_uracil = t + u;
+        if !significant(u, thymine_or_uracil) {
+            MoleculeType::Dna
+        } else if !significant(t, thymine_or_uracil) {
+            MoleculeType::Rna
+        } else {
+            MoleculeType::Mixed
+        }
+    }
+
+    /// Classifies protein-parsed residues. Records made almost entirely of
+    /// nucleotide letters were nucleotides after all.
+    pub fn from_protein(stats: &ProteinStats) -> Self {
+        let residues = stats.residues();
+        if residues == 0 {
+            return MoleculeType::Unknown;
+        }
+        let count = |letter: u8| stats.composition[(letter - b'A') as usize];
+        let nucleotides: usize = b"ACGTUN".iter().map(|&l| count(l)).sum();
+        if significant(residues - nucleotides, residues) {
+            return MoleculeType::Protein;
+        }
+        Self::classify(residues, nucleotides, count(b'T'), count(b'U'))
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            MoleculeType::Dna => "DNA",
+            MoleculeType::Rna => "RNA",
+            MoleculeType::Protein => "protein",
+            MoleculeType::Mixed => "mixed/invalid",
+            MoleculeType::Unknown => "unknown",
+        }
+    }
+
+    /// Whether a file of this type contradicts being parsed as `parsed_as`.
+    /// RNA is read fine by the nucleotide parser, and an empty file contradicts
+    /// nothing.
+    pub fn contradicts(self, parsed_as: Alphabet) -> bool {
+        match self {
+            MoleculeType::Dna | MoleculeType::Rna => parsed_as == Alphabet::Protein,
+            MoleculeType::Protein | MoleculeType::Mixed => parsed_as == Alphabet::Dna,
+            MoleculeType::Unknown => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classify_nucleotides() {
+        assert_eq!(MoleculeType::classify(0, 0, 0, 0), MoleculeType::Unknown);
+        assert_eq!(MoleculeType::classify(100, 100, 25, 0), MoleculeType::Dna);
+        assert_eq!(MoleculeType::classify(100, 100, 0, 25), MoleculeType::Rna);
+        assert_eq!(MoleculeType::classify(100, 100, 25, 25), MoleculeType::Mixed);
+        // IUPAC ambiguity codes are tolerated up to the threshold.
+        assert_eq!(MoleculeType::classify(100, 95, 25, 1), MoleculeType::Dna);
+        assert_eq!(MoleculeType::classify(100, 90, 25, 0), MoleculeType::Mixed);
+    }
+
+    #[test]
+    fn test_classify_protein_composition() {
+        let mut stats = ProteinStats::default();
+        for &letter in b"MVLSPADKTNVKAAWGKVGAHAGEYGAEALERMF" {
+            stats.composition[(letter - b'A') as usize] += 1;
+        }
+        assert_eq!(MoleculeType::from_protein(&stats), MoleculeType::Protein);
+
+        let mut stats = ProteinStats::default();
+        for &letter in b"ACGUACGUACGUNNACGU" {
+            stats.composition[(letter - b'A') as usize] += 1;
+        }
+        assert_eq!(MoleculeType::from_protein(&stats), MoleculeType::Rna);
+        assert!(MoleculeType::Rna.contradicts(Alphabet::Protein));
+        assert!(!MoleculeType::Rna.contradicts(Alphabet::Dna));
+        assert!(MoleculeType::Mixed.contradicts(Alphabet::Dna));
+        assert!(!MoleculeType::Unknown.contradicts(Alphabet::Protein));
+    }
+}
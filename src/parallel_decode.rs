@@ -0,0 +1,342 @@
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0>
+// at your option. This file may not be copied, modified,
+// or distributed except according to those terms.
+
+//! Parallel decompression for compressed inputs made of independent pieces.
+//!
+//! - **xz**: files written with `xz -T` contain several blocks whose sizes are
+//!   recorded in the stream index. liblzma's multithreaded decoder inflates them
+//!   concurrently; single-block files gain nothing from it and stay sequential.
+//! - **gzip**: multi-member files (`cat a.gz b.gz`, per-chromosome files joined
+//!   into one) carry no index, so member starts are found by scanning for
+//!   plausible gzip headers. Runs of members are inflated speculatively in
+//!   parallel and only accepted when they chain exactly onto the previous run;
+//!   anything that does not line up is re-decoded sequentially. A member too
+//!   large to buffer is not thrown away: its first part is kept and the rest is
+//!   streamed from where the worker stopped.
+
+use flate2::bufread::GzDecoder;
+use liblzma::stream::{MtStreamBuilder, Stream};
+use memchr::memmem;
+use rayon::prelude::*;
+use std::io::Read;
+
+/// Compressed bytes handed to one worker when inflating gzip members.
+pub const GZIP_RUN_SIZE: usize = 8 * 1024 * 1024;
+
+/// A worker stops a run once it has buffered this much decoded output, so memory
+/// stays bounded by roughly `threads * GZIP_RUN_OUTPUT_LIMIT`. The member it
+/// stopped in is then finished while streaming.
+const GZIP_RUN_OUTPUT_LIMIT: usize = 64 * 1024 * 1024;
+
+/// liblzma lowers its thread count rather than exceed this much memory.
+const XZ_THREADING_MEMLIMIT: u64 = 1024 * 1024 * 1024;
+
+const DECODE_BUFFER_SIZE: usize = 2 * 1024 * 1024;
+
+/// Returns the number of blocks in the last stream of an xz file, read from the
+/// stream index, or `None` if the footer or index cannot be parsed.
+pub fn xz_block_count(data: &[u8]) -> Option<u64> {
+    // Stream padding is a multiple of four NUL bytes after the footer.
+    let mut end = data.len();
+    while end >= 4 && data[end - 4..end] == [0, 0, 0, 0] {
+        end -= 4;
+    }
+    let footer = data.get(end.checked_sub(12)?..end)?;
+    if &footer[10..12] != b"YZ" {
+        return None;
+    }
+    let backward_size = u32::from_le_bytes(footer[4..8].try_into().ok()?) as usize;
+    let index_size = (backward_size + 1) * 4;
+    let index_start = (end - 12).checked_sub(index_size)?;
+    let index = &data[index_start..end - 12];
+    if index[0] != 0 {
+        return None;
+    }
+
+    // Number of records, stored as a multibyte integer.
+    let mut count = 0u64;
+    for (i, &byte) in index[1..].iter().take(9).enumerate() {
+        count |= u64::from(byte & 0x7F) << (i * 7);
+        if byte & 0x80 == 0 {
+            return Some(count);
+        }
+    }
+    None
+}
+
+/// Builds a multithreaded xz decoder stream for use with `XzDecoder::new_stream`.
+pub fn xz_parallel_stream(threads: usize) -> std::io::Result<Stream> {
+    MtStreamBuilder::new()
+        .threads(threads.min(u32::MAX as usize) as u32)
+        .memlimit_threading(XZ_THREADING_MEMLIMIT)
+        .memlimit_stop(u64::MAX)
+        .decoder()
+        .map_err(std::io::Error::other)
+}
+
+/// Offsets that look like the start of a gzip member: magic, deflate method,
+/// no reserved flag bits, a known XFL value and a known OS byte.
+pub fn gzip_member_candidates(data: &[u8]) -> Vec<usize> {
+    memmem::find_iter(data, &[0x1f, 0x8b, 0x08])
+        .filter(|&pos| match data.get(pos..pos + 10) {
+            Some(header) => {
+                header[3] & 0xE0 == 0
+                    && matches!(header[8], 0 | 2 | 4)
+                    && (header[9] <= 13 || header[9] == 255)
+            }
+            None => false,
+        })
+        .collect()
+}
+
+struct Run {
+    start: usize,
+    boundary: usize,
+}
+
+enum RunOutcome<'a> {
+    /// Whole members, up to `end`.
+    Decoded { end: usize, data: Vec<u8> },
+    /// Whole members and the first part of the one at `start`, which was cut at
+    /// the output limit; `rest` inflates the remainder of it.
+    Cut {
+        start: usize,
+        data: Vec<u8>,
+        rest: Box<GzDecoder<&'a [u8]>>,
+    },
+    Failed,
+}
+
+/// Inflates every member of the gzip file in `data`, passing decoded bytes to
+/// `sink` in file order. Members are grouped into runs of about `run_size`
+/// compressed bytes and up to `threads` runs are inflated at a time.
+pub fn decode_gzip_members<F: FnMut(&[u8])>(
+    data: &[u8],
+    threads: usize,
+    run_size: usize,
+    sink: F,
+) -> std::io::Result<()> {
+    decode_runs(data, threads, run_size, GZIP_RUN_OUTPUT_LIMIT, sink)
+}
+
+fn decode_runs<F: FnMut(&[u8])>(
+    data: &[u8],
+    threads: usize,
+    run_size: usize,
+    output_limit: usize,
+    mut sink: F,
+) -> std::io::Result<()> {
+    let mut runs: Vec<Run> = Vec::new();
+    for pos in gzip_member_candidates(data) {
+        match runs.last() {
+            Some(last) if pos - last.start < run_size => {}
+            _ => runs.push(Run {
+                start: pos,
+                boundary: data.len(),
+            }),
+        }
+    }
+    for i in 1..runs.len() {
+        runs[i - 1].boundary = runs[i].start;
+    }
+
+    let mut expected = 0;
+    for window in runs.chunks(threads.max(1)) {
+        let outcomes: Vec<RunOutcome> = window
+            .par_iter()
+            .map(|run| decode_run(data, run, output_limit))
+            .collect();
+        for (run, outcome) in window.iter().zip(outcomes) {
+            if run.start > expected {
+                expected = stream_members(data, expected, run.start, &mut sink)?;
+            }
+            if run.start != expected {
+                continue;
+            }
+            match outcome {
+                RunOutcome::Decoded { end, data } => {
+                    sink(&data);
+                    expected = end;
+                }
+                RunOutcome::Cut { start, data: head, rest } => {
+                    sink(&head);
+                    expected = start + stream_member(data.len() - start, *rest, &mut sink)?;
+                }
+                RunOutcome::Failed => {}
+            }
+        }
+    }
+    if expected < data.len() {
+        stream_members(data, expected, data.len(), &mut sink)?;
+    }
+    Ok(())
+}
+
+/// Inflates whole members starting at `run.start` until the boundary is reached
+/// or `output_limit` is hit, keeping the decoder of a member cut at the limit. A
+/// start that turns out not to be a member header simply fails; the caller never
+/// accepts a run that does not chain.
+fn decode_run<'a>(data: &'a [u8], run: &Run, output_limit: usize) -> RunOutcome<'a> {
+    let mut out = Vec::new();
+    let mut pos = run.start;
+    while pos < run.boundary && out.len() < output_limit {
+        let mut decoder = GzDecoder::new(&data[pos..]);
+        let limit = (output_limit - out.len()) as u64;
+        match decoder.by_ref().take(limit + 1).read_to_end(&mut out) {
+            Ok(_) if out.len() <= output_limit => {}
+            Ok(_) => {
+                return RunOutcome::Cut {
+                    start: pos,
+                    data: out,
+                    rest: Box::new(decoder),
+                };
+            }
+            Err(_) => return RunOutcome::Failed,
+        }
+        let consumed = data.len() - pos - decoder.into_inner().len();
+        if consumed == 0 {
+            return RunOutcome::Failed;
+        }
+        pos += consumed;
+    }
+    RunOutcome::Decoded { end: pos, data: out }
+}
+
+/// Sequentially inflates members from `from` until at least `until`, streaming
+/// the output. Returns the offset just past the last member decoded.
+fn stream_members<F: FnMut(&[u8])>(
+    data: &[u8],
+    from: usize,
+    until: usize,
+    sink: &mut F,
+) -> std::io::Result<usize> {
+    let mut pos = from;
+    while pos < until {
+        let consumed = stream_member(data.len() - pos, GzDecoder::new(&data[pos..]), sink)?;
+        if consumed == 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("no gzip member at offset {pos}"),
+            ));
+        }
+        pos += consumed;
+    }
+    Ok(pos)
+}
+
+/// Streams the rest of one member from `decoder`, which was given `available`
+/// compressed bytes, and returns how many of them the member took.
+fn stream_member<F: FnMut(&[u8])>(
+    available: usize,
+    mut decoder: GzDecoder<&[u8]>,
+    sink: &mut F,
+) -> std::io::Result<usize> {
+    let mut buf = vec![0u8; DECODE_BUFFER_SIZE];
+    loop {
+        let n = decoder.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        sink(&buf[..n]);
+    }
+    Ok(available - decoder.into_inner().len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::Compression;
+    use flate2::write::GzEncoder;
+    use std::io::Write;
+
+    fn gzip(data: &[u8], level: Compression) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), level);
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn test_decode_gzip_members_in_order() {
+        let mut plain = Vec::new();
+        let mut compressed = Vec::new();
+        for i in 0..50 {
+            let mut member = format!(">m{i}\n").into_bytes();
+            member.extend(std::iter::repeat_n(b"ACGTN"[i % 5], i * 37));
+            // A stored member that embeds a gzip header, which the scanner
+            // will report as a false member start.
+            if i % 7 == 0 {
+                member.extend_from_slice(&[0x1f, 0x8b, 0x08, 0, 0, 0, 0, 0, 0, 3]);
+            }
+            member.push(b'\n');
+            let level = if i % 7 == 0 { Compression::none() } else { Compression::default() };
+            compressed.extend(gzip(&member, level));
+            plain.extend(member);
+        }
+        assert!(gzip_member_candidates(&compressed).len() > 50);
+
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(4).build().unwrap();
+        for run_size in [1, 64, 500, GZIP_RUN_SIZE] {
+            let mut decoded = Vec::new();
+            pool.install(|| {
+                decode_gzip_members(&compressed, 4, run_size, |chunk| {
+                    decoded.extend_from_slice(chunk)
+                })
+            })
+            .unwrap();
+            assert_eq!(decoded, plain, "run size {run_size}");
+        }
+        // Members larger than the output limit are finished while streaming.
+        for output_limit in [1, 100, 1000] {
+            let mut decoded = Vec::new();
+            pool.install(|| {
+                decode_runs(&compressed, 4, 1, output_limit, |chunk| {
+                    decoded.extend_from_slice(chunk)
+                })
+            })
+            .unwrap();
+            assert_eq!(decoded, plain, "output limit {output_limit}");
+        }
+    }
+
+    #[test]
+    fn test_decode_gzip_members_trailing_garbage() {
+        let mut compressed = gzip(b">a\nACGT\n", Compression::default());
+        compressed.extend(gzip(b">b\nACGT\n", Compression::default()));
+        compressed.extend_from_slice(b"garbage");
+        let res = decode_gzip_members(&compressed, 2, 1, |_| {});
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn test_xz_block_count() {
+        let data: Vec<u8> = (0..600_000u32).map(|i| b"ACGT\n"[(i % 5) as usize]).collect();
+
+        let single = {
+            let mut encoder = liblzma::write::XzEncoder::new(Vec::new(), 1);
+            encoder.write_all(&data).unwrap();
+            encoder.finish().unwrap()
+        };
+        assert_eq!(xz_block_count(&single), Some(1));
+
+        let stream = MtStreamBuilder::new()
+            .threads(2)
+            .block_size(100_000)
+            .preset(1)
+            .encoder()
+            .unwrap();
+        let mut encoder = liblzma::write::XzEncoder::new_stream(Vec::new(), stream);
+        encoder.write_all(&data).unwrap();
+        let multi = encoder.finish().unwrap();
+        assert_eq!(xz_block_count(&multi), Some(6));
+
+        let mut decoded = Vec::new();
+        liblzma::read::XzDecoder::new_stream(&multi[..], xz_parallel_stream(4).unwrap())
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, data);
+
+        assert_eq!(xz_block_count(b"not an xz file"), None);
+    }
+}
@@ -3,15 +3,35 @@
 // at your option. This file may not be copied, modified,
 // or distributed except according to those terms.
 
+use crate::checksum::{ChecksumBuilder, Checksums};
+use crate::duplicates::{DuplicateSequences, SequenceDigests};
+use crate::fai::{self, FaiBuilder, FaiIndex, LineWidths};
+use crate::fastq::{FastqParser, QualityStats};
+use crate::gc_track::{self, GcTrack, GcTrackBuilder, GcWindow};
+use crate::gzi::{self, BlockIndexer, GziBuilder};
+use crate::ids::{self, DuplicateIds, HeaderId, IdTracker};
+use crate::lengths::{
+    FilteredRecords, LengthDistribution, LengthFilter, LengthThreshold, SequenceLengths,
+};
+use crate::parallel_decode;
+use crate::protein::{self, ProteinParser, ProteinStats};
+use crate::select::{HeaderFilter, HeaderSelector};
+use crate::sink::RecordSinks;
+use crate::molecule::MoleculeType;
+use crate::simd::{BaseCounts, SimdLevel};
+use crate::validate::{ValidationReport, Validator};
 use bzip2::read::BzDecoder;
-use flate2::read::GzDecoder;
+use flate2::read::MultiGzDecoder;
 use liblzma::read::XzDecoder;
-use memchr::memchr;
+use memchr::{memchr, memmem};
 use memmap2::Mmap;
 use noodles::bgzf as bgzf;
+use rayon::prelude::*;
 use std::fs::{File, OpenOptions};
 use std::io::{BufRead, BufReader, Read};
+use std::num::NonZeroUsize;
 use std::path::Path;
+use std::sync::{Arc, Mutex};
 use zip::read::ZipArchive;
 
 #[cfg(target_os = "macos")]
@@ -21,7 +41,85 @@ use std::os::windows::fs::OpenOptionsExt;
 
 const DECODER_BUFFER_SIZE: usize = 2 * 1024 * 1024;
 
-pub const VALID_FILES: [&str; 3] = ["fa", "fasta", "fna"];
+/// Smallest slice of a memory-mapped file worth handing to its own thread.
+/// Below this, scheduling overhead eats the gain from splitting.
+const PARALLEL_CHUNK_MIN_SIZE: usize = 64 * 1024 * 1024;
+
+pub const VALID_FILES: [&str; 6] = ["fa", "fasta", "fna", "faa", "fq", "fastq"];
+
+/// Sequence alphabet requested with `--alphabet`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum Alphabet {
+    /// Guess from the residues near the start of each file.
+    #[default]
+    Auto,
+    /// Nucleotides: GC and N content.
+    Dna,
+    /// Amino acids: composition, X and stop counts, molecular weights.
+    Protein,
+}
+
+/// Settings shared by every file of a run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProcessOptions {
+    pub simd: SimdLevel,
+    pub alphabet: Alphabet,
+    /// Check FASTA input strictly and report problems in the results.
+    pub validate: bool,
+    /// Look for records whose ID repeats an earlier one.
+    pub duplicate_ids: bool,
+    /// Look for records with the same sequence, on either strand (FASTA only).
+    pub duplicate_sequences: bool,
+    /// Name the duplicated records too.
+    pub list_duplicates: bool,
+    /// Compute MD5 and refget digests of every sequence (FASTA only).
+    pub checksums: bool,
+    /// Build a `.fai` index and write it next to plain or BGZF input.
+    pub write_fai: bool,
+    /// Report how sequence lines are wrapped.
+    pub line_widths: bool,
+    /// Take record lengths from an existing `.fai` instead of scanning.
+    pub from_index: bool,
+    /// Write the `.gzi` block index of BGZF input.
+    pub write_gzi: bool,
+    /// Records outside these lengths are left out of every statistic.
+    pub length_filter: LengthFilter,
+    /// Records whose header does not pass are left out of every statistic.
+    pub header_filter: Option<Arc<HeaderFilter>>,
+    /// Write the GC content of windows along each sequence as bedGraph.
+    pub gc_window: Option<GcWindow>,
+    /// Also write the GC skew of the same windows as a table.
+    pub gc_skew: bool,
+}
+
+impl Default for ProcessOptions {
+    fn default() -> Self {
+        Self {
+            simd: SimdLevel::detect(),
+            alphabet: Alphabet::Auto,
+            validate: false,
+            duplicate_ids: false,
+            duplicate_sequences: false,
+            list_duplicates: false,
+            checksums: false,
+            write_fai: false,
+            line_widths: false,
+            from_index: false,
+            write_gzi: false,
+            length_filter: LengthFilter::default(),
+            header_filter: None,
+            gc_window: None,
+            gc_skew: false,
+        }
+    }
+}
+
+impl ProcessOptions {
+    /// Whether a length or header filter may leave records out.
+    pub fn filters_records(&self) -> bool {
+        self.length_filter.is_active() || self.header_filter.is_some()
+    }
+}
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum FileFormat {
@@ -48,50 +146,297 @@ impl FileFormat {
             _ => FileFormat::Unknown,
         }
     }
+
+    /// The format of `path` by its extension, except that a `.gz` file written
+    /// by `bgzip` is BGZF.
+    pub fn detect(path: &Path) -> Self {
+        match Self::from_path(path) {
+            FileFormat::Gzip if gzi::is_bgzf(path) => FileFormat::Bgzip,
+            format => format,
+        }
+    }
+
+    /// Typical ratio of decoded FASTA text to on-disk size, used to estimate how
+    /// much parsing work a file represents before opening it.
+    pub fn expansion_factor(self) -> u64 {
+        match self {
+            FileFormat::Fasta => 1,
+            FileFormat::Gzip | FileFormat::Bgzip | FileFormat::Zip => 4,
+            FileFormat::Bzip2 => 4,
+            FileFormat::Xz | FileFormat::Naf => 5,
+            FileFormat::Unknown => 0,
+        }
+    }
 }
 
+/// Processes one file of any supported format. Decoders that start threads of
+/// their own start at most `decoder_threads`.
 pub fn process_any_file(
     file: &Path,
-    no_simd: bool,
+    options: &ProcessOptions,
+    decoder_threads: usize,
 ) -> std::io::Result<Vec<AnalysisResults>> {
-    match FileFormat::from_path(file) {
-        FileFormat::Gzip => process_gz_file(file, no_simd),
-        FileFormat::Zip => process_zip_file(file, no_simd),
-        FileFormat::Xz => process_xz_file(file, no_simd),
-        FileFormat::Bzip2 => process_bz2_file(file, no_simd),
-        FileFormat::Bgzip => process_bgzip_file(file, no_simd),
-        FileFormat::Naf => process_naf_file(file, no_simd),
-        FileFormat::Fasta => process_fasta_file(file, no_simd),
+    let format = FileFormat::detect(file);
+    if options.from_index {
+        match format {
+            FileFormat::Fasta | FileFormat::Bgzip => match process_from_index(file, format, options) {
+                Ok(results) => return Ok(vec![results]),
+                Err(e) => eprintln!("Warning: not using the index of {:?} ({e}); scanning it instead", file),
+            },
+            _ => eprintln!(
+                "Warning: not using an index for {:?}: only plain or BGZF FASTA can be indexed; scanning it instead",
+                file
+            ),
+        }
+    }
+    if options.write_gzi && format != FileFormat::Bgzip {
+        eprintln!("Warning: not writing a .gzi for {:?}: only BGZF files are indexed", file);
+    }
+    let results = match format {
+        FileFormat::Gzip => process_gz_file(file, options),
+        FileFormat::Zip => process_zip_file(file, options),
+        FileFormat::Xz => process_xz_file(file, options),
+        FileFormat::Bzip2 => process_bz2_file(file, options),
+        FileFormat::Bgzip => process_bgzip_file(file, options, decoder_threads),
+        FileFormat::Naf => process_naf_file(file, options),
+        FileFormat::Fasta => process_fasta_file(file, options),
         FileFormat::Unknown => Ok(Vec::new()),
+    }?;
+    for result in &results {
+        check_molecule_type(file, result, options.alphabet)?;
+    }
+    if options.write_fai {
+        write_fai_index(file, format, &results)?;
+    }
+    if options.gc_window.is_some() {
+        write_gc_track(file, &results, options.gc_skew)?;
+    }
+    Ok(results)
+}
+
+/// Length statistics from the `.fai` next to `file`, without reading any sequence.
+/// An index older than the file, or describing more data than it holds, is stale
+/// and rejected. BGZF input also needs its `.gzi`, which gives the decompressed
+/// size from the last block alone.
+fn process_from_index(
+    file: &Path,
+    format: FileFormat,
+    options: &ProcessOptions,
+) -> std::io::Result<AnalysisResults> {
+    let index_path = fai::fai_path(file);
+    let index = FaiIndex::read(&index_path)?;
+    if std::fs::metadata(&index_path)?.modified()? < std::fs::metadata(file)?.modified()? {
+        return Err(std::io::Error::other("the index is older than the file"));
+    }
+    let size = match format {
+        FileFormat::Bgzip => gzi::uncompressed_size(file)?,
+        _ => std::fs::metadata(file)?.len(),
+    };
+    if index.data_end() > size {
+        return Err(std::io::Error::other(
+            "the index describes more data than the file holds",
+        ));
+    }
+
+    let mut results = AnalysisResults::for_path(file);
+    results.lengths_only = true;
+    let mut ids = options.duplicate_ids.then(IdTracker::new);
+    let mut lengths = SequenceLengths::new();
+    let mut filtered = FilteredRecords::default();
+    for record in &index.records {
+        let length = record.length as usize;
+        // A .fai holds only the ID; --header-regex, which may need the rest of
+        // the header, is rejected with --from-index.
+        let name = record.name.as_bytes();
+        let selected = options.header_filter.as_ref().is_none_or(|filter| filter.selects(name, name));
+        if selected && options.length_filter.keeps(length) {
+            if let Some(ids) = &mut ids {
+                ids.insert(name);
+            }
+            lengths.push(length);
+        } else {
+            filtered.add(length);
+        }
+    }
+    results.filtered = options.filters_records().then_some(filtered);
+    results.duplicate_ids = ids.map(IdTracker::finish);
+    results.calculate_stats(lengths);
+    Ok(results)
+}
+
+/// Writes the `.fai` built while scanning next to the input. Only plain and BGZF
+/// FASTA can be indexed; other inputs are skipped with a warning.
+fn write_fai_index(
+    file: &Path,
+    format: FileFormat,
+    results: &[AnalysisResults],
+) -> std::io::Result<()> {
+    let index = match (format, results) {
+        (FileFormat::Fasta | FileFormat::Bgzip, [result]) => result.fai.as_ref(),
+        _ => None,
+    };
+    let Some(index) = index else {
+        eprintln!(
+            "Warning: not writing a .fai for {:?}: only plain or BGZF-compressed FASTA can be indexed",
+            file
+        );
+        return Ok(());
+    };
+    if let Some(inconsistency) = &index.inconsistency {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("cannot index {:?}: {inconsistency}", file),
+        ));
+    }
+    index.write(&fai::fai_path(file))
+}
+
+/// Writes the windowed GC content of every nucleotide record of `file` to one
+/// bedGraph next to it, and their GC skew to a table if `gc_skew`. Protein and
+/// FASTQ input have none and are skipped with a warning.
+fn write_gc_track(file: &Path, results: &[AnalysisResults], gc_skew: bool) -> std::io::Result<()> {
+    let tracks: Vec<&GcTrack> = results.iter().filter_map(|r| r.gc_track.as_ref()).collect();
+    if tracks.is_empty() {
+        eprintln!(
+            "Warning: not writing a GC track for {:?}: only nucleotide FASTA and NAF have one",
+            file
+        );
+        return Ok(());
+    }
+    let name = file.file_name().map_or_else(|| file.display().to_string(), |n| n.to_string_lossy().into_owned());
+    gc_track::write_bedgraph(tracks.iter().copied(), &name, &gc_track::gc_track_path(file))?;
+    if gc_skew {
+        gc_track::write_skew_table(tracks, &gc_track::gc_skew_path(file))?;
+    }
+    Ok(())
+}
+
+/// Compares the detected molecule type with the parser that read the records.
+/// A contradiction is an error when the alphabet was given explicitly and a
+/// warning when it was guessed.
+fn check_molecule_type(
+    file: &Path,
+    result: &AnalysisResults,
+    requested: Alphabet,
+) -> std::io::Result<()> {
+    let parsed_as = if result.protein.is_some() {
+        Alphabet::Protein
+    } else {
+        Alphabet::Dna
+    };
+    let detected = result.molecule_type();
+    if !detected.contradicts(parsed_as) {
+        return Ok(());
+    }
+    let source = result.source_name(file);
+    let message = format!(
+        "{source} looks like {} but was read as {}",
+        detected.name(),
+        if parsed_as == Alphabet::Protein { "protein" } else { "nucleotides" },
+    );
+    if requested == Alphabet::Auto {
+        eprintln!("Warning: {message}");
+        Ok(())
+    } else {
+        Err(std::io::Error::new(std::io::ErrorKind::InvalidData, message))
     }
 }
 
 struct FastaParser {
-    lengths: Vec<usize>,
-    current_sequence_length: usize,
+    lengths: SequenceLengths,
+    /// The outputs asked for, including duplicate IDs except in chunk parsers.
+    sinks: RecordSinks,
+    /// Where each record's `>` is, for chunk parsers with `--duplicate-ids`.
+    header_offsets: Option<Vec<usize>>,
+    /// Newline offsets of the last sequence scan, if a sink wants them.
+    line_ends: Option<Vec<usize>>,
+    /// Offset of the next byte fed, from the start of the buffer the chunk was
+    /// split from.
+    position: usize,
+    /// Offset of the open record's `>`.
+    record_start: usize,
+    /// ID of the open record.
+    id: HeaderId,
+    /// Composition of the open record, added to `kept` once its length is known
+    /// to pass the filter.
+    current_counts: BaseCounts,
+    kept: BaseCounts,
+    filter: LengthFilter,
+    /// Tests each header, with `--include-ids`, `--exclude-ids` or `--header-regex`.
+    selector: Option<HeaderSelector>,
+    /// Whether the open record's header passed the selector.
+    selected: bool,
+    filtered: FilteredRecords,
     in_header: bool,
     last_char_was_newline: bool,
     started: bool,
 }
 
 impl FastaParser {
-    fn new() -> Self {
+    fn new(options: &ProcessOptions) -> Self {
+        let mut parser = Self::for_chunk(options, 0);
+        if parser.header_offsets.take().is_some() {
+            parser.sinks.push(IdTracker::new());
+        }
+        parser
+    }
+
+    /// A parser for the chunk at offset `start` of a split buffer. It notes where
+    /// the headers are, and IDs are checked over the whole buffer afterwards, as
+    /// chunks cannot see each other's.
+    fn for_chunk(options: &ProcessOptions, start: usize) -> Self {
+        let mut sinks = RecordSinks::default();
+        if options.duplicate_sequences {
+            sinks.push(SequenceDigests::new(options.list_duplicates));
+        }
+        if options.checksums {
+            sinks.push(ChecksumBuilder::new());
+        }
+        if options.write_fai || options.line_widths {
+            sinks.push(FaiBuilder::new(options.write_fai, options.line_widths));
+        }
+        if let Some(window) = options.gc_window {
+            sinks.push(GcTrackBuilder::new(window, options.gc_skew, options.simd));
+        }
+        let line_ends = sinks.wants_line_ends().then(Vec::new);
         Self {
-            lengths: Vec::with_capacity(250),
-            current_sequence_length: 0,
+            lengths: SequenceLengths::new(),
+            sinks,
+            header_offsets: options.duplicate_ids.then(Vec::new),
+            line_ends,
+            position: start,
+            record_start: start,
+            id: HeaderId::default(),
+            current_counts: BaseCounts::default(),
+            kept: BaseCounts::default(),
+            filter: options.length_filter,
+            selector: options.header_filter.clone().map(HeaderSelector::new),
+            selected: true,
+            filtered: FilteredRecords::default(),
             in_header: false,
             last_char_was_newline: true, // To catch the very first '>'
             started: false,
         }
     }
 
-    fn feed(&mut self, data: &[u8], results: &mut AnalysisResults, no_simd: bool) {
+    fn feed(&mut self, data: &[u8], results: &mut AnalysisResults, simd: SimdLevel) {
         let mut consumed = 0;
         let len = data.len();
         while consumed < len {
             if self.in_header {
                 // Find end of header
-                match memchr(b'\n', &data[consumed..]) {
+                let end = memchr(b'\n', &data[consumed..]);
+                let header = &data[consumed..end.map_or(len, |pos| consumed + pos)];
+                self.id.push(header);
+                self.sinks
+                    .header_bytes(&data[consumed..end.map_or(len, |pos| consumed + pos + 1)]);
+                if let Some(selector) = &mut self.selector {
+                    selector.header_bytes(header);
+                    if end.is_some() {
+                        self.selected = selector.end_header(self.id.as_bytes());
+                    }
+                }
+                match end {
                     Some(pos) => {
                         consumed += pos + 1;
                         self.in_header = false;
@@ -108,55 +453,218 @@ impl FastaParser {
                 if self.last_char_was_newline && data[consumed] == b'>' {
                     // Start of a new header
                     if self.started {
-                        self.lengths.push(self.current_sequence_length);
-                        self.current_sequence_length = 0;
+                        self.end_record(false);
                     }
                     results.sequence_count += 1;
+                    self.record_start = self.position + consumed;
+                    self.id.clear();
+                    self.sinks.begin_record();
+                    if let Some(selector) = &mut self.selector {
+                        selector.begin_header();
+                    }
                     self.started = true;
                     self.in_header = true;
                     consumed += 1;
                     self.last_char_was_newline = false;
                 } else {
-                    // Still in sequence, look for the next '\n>'
-                    let mut next_header_start = None;
-                    let mut search_pos = consumed;
-                    while let Some(pos) = memchr(b'\n', &data[search_pos..]) {
-                        let actual_pos = search_pos + pos;
-                        if actual_pos + 1 < len {
-                            if data[actual_pos + 1] == b'>' {
-                                next_header_start = Some(actual_pos);
-                                break;
-                            }
-                        } else {
-                            // \n is the last char, need to check next buffer
-                            break;
-                        }
-                        search_pos = actual_pos + 1;
+                    // Still in sequence: one fused pass finds the next '\n>' and
+                    // counts the bases before it
+                    if let Some(line_ends) = &mut self.line_ends {
+                        line_ends.clear();
                     }
-
-                    let (chunk, chunk_end, new_last_newline) = match next_header_start {
-                        Some(pos) => (&data[consumed..pos], pos + 1, true),
-                        None => (&data[consumed..len], len, data[len - 1] == b'\n'),
-                    };
-
+                    let scan = crate::simd::scan_sequence(
+                        &data[consumed..],
+                        simd,
+                        self.line_ends.as_mut(),
+                    );
+                    let end = scan.header_newline.map_or(len, |pos| consumed + pos + 1);
+                    self.sinks.sequence_bytes(
+                        &data[consumed..end],
+                        self.line_ends.as_deref().unwrap_or_default(),
+                    );
                     if self.started {
-                        self.current_sequence_length += update_stats(chunk, results, no_simd);
+                        self.current_counts += scan.counts;
+                    }
+                    match scan.header_newline {
+                        Some(pos) => {
+                            consumed += pos + 1;
+                            self.last_char_was_newline = true;
+                        }
+                        None => {
+                            // A trailing '\n' is checked against the next buffer
+                            consumed = len;
+                            self.last_char_was_newline = data[len - 1] == b'\n';
+                        }
                     }
-                    consumed = chunk_end;
-                    self.last_char_was_newline = new_last_newline;
                 }
             }
         }
+        self.position += len;
     }
 
-    fn finish(mut self, results: &mut AnalysisResults) {
-        if self.current_sequence_length > 0 {
-            self.lengths.push(self.current_sequence_length);
+    /// Closes the open record, keeping it or counting it as filtered out. A kept
+    /// record without sequence at the end of the input has no length to report.
+    fn end_record(&mut self, at_end: bool) {
+        let counts = std::mem::take(&mut self.current_counts);
+        let keep = self.keeps(counts.seq_chars);
+        if !keep {
+            self.filtered.add(counts.seq_chars);
+        } else if counts.seq_chars > 0 || !at_end {
+            self.lengths.push(counts.seq_chars);
+            self.kept += counts;
+        }
+        if let Some(offsets) = &mut self.header_offsets
+            && keep
+        {
+            offsets.push(self.record_start);
+        }
+        self.sinks.end_record(self.id.as_bytes(), keep);
+    }
+
+    fn keeps(&self, length: usize) -> bool {
+        self.selected && self.filter.keeps(length)
+    }
+
+    /// Appends the state of a parser that consumed the bytes directly following
+    /// the ones seen by `self`. `next` must have started at a record boundary.
+    fn merge(&mut self, next: FastaParser) {
+        if !next.started {
+            return;
+        }
+        // `next` saw the header that closes our last record, but could not push it.
+        if self.started {
+            self.end_record(false);
+        }
+        self.lengths.append(&next.lengths);
+        self.kept += next.kept;
+        self.filtered.absorb(next.filtered);
+        self.sinks.append(next.sinks);
+        if let (Some(offsets), Some(next_offsets)) = (&mut self.header_offsets, next.header_offsets) {
+            offsets.extend(next_offsets);
+        }
+        self.position = next.position;
+        self.record_start = next.record_start;
+        self.id = next.id;
+        self.current_counts = next.current_counts;
+        self.selector = next.selector;
+        self.selected = next.selected;
+        self.in_header = next.in_header;
+        self.last_char_was_newline = next.last_char_was_newline;
+        self.started = true;
+    }
+
+    /// Ends the record still open at the end of the input.
+    fn close(&mut self) {
+        // The last header may end without a newline.
+        if std::mem::take(&mut self.in_header)
+            && let Some(selector) = &self.selector
+        {
+            self.selected = selector.end_header(self.id.as_bytes());
+        }
+        if std::mem::take(&mut self.started) {
+            self.end_record(true);
         }
+    }
+
+    fn finish(mut self, results: &mut AnalysisResults) {
+        self.close();
+        results.add_bases(self.kept);
+        results.sequence_count -= self.filtered.records;
+        let filtering = self.filter.is_active() || self.selector.is_some();
+        results.filtered = filtering.then_some(self.filtered);
+        self.sinks.finish(results);
         results.calculate_stats(self.lengths);
     }
 }
 
+/// Parses FASTA, protein FASTA or FASTQ, decided from the start of the input.
+struct RecordParser {
+    records: Records,
+    /// Checks FASTA input alongside the statistics when `--validate` is set.
+    validator: Option<Validator>,
+}
+
+enum Records {
+    Undecided,
+    Fasta(Box<FastaParser>),
+    Fastq(Box<FastqParser>),
+    Protein(Box<ProteinParser>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RecordKind {
+    Fasta,
+    Fastq,
+    Protein,
+}
+
+/// Picks the parser for input starting with `data`. FASTQ is recognised by its
+/// leading `@`; protein by `--alphabet protein` or, in auto mode, by its residues.
+fn sniff(data: &[u8], alphabet: Alphabet) -> RecordKind {
+    if looks_like_fastq(data) {
+        return RecordKind::Fastq;
+    }
+    match alphabet {
+        Alphabet::Protein => RecordKind::Protein,
+        Alphabet::Auto if protein::looks_like_protein(data) => RecordKind::Protein,
+        _ => RecordKind::Fasta,
+    }
+}
+
+impl RecordParser {
+    fn new(options: &ProcessOptions) -> Self {
+        Self {
+            records: Records::Undecided,
+            validator: options.validate.then(|| Validator::new(false)),
+        }
+    }
+
+    fn feed(&mut self, data: &[u8], results: &mut AnalysisResults, options: &ProcessOptions) {
+        let simd = options.simd;
+        if let Records::Undecided = self.records {
+            if data.is_empty() {
+                return;
+            }
+            let kind = sniff(data, options.alphabet);
+            match kind {
+                RecordKind::Fastq => self.validator = None,
+                RecordKind::Protein if options.validate => {
+                    self.validator = Some(Validator::new(true));
+                }
+                _ => {}
+            }
+            self.records = match kind {
+                RecordKind::Fasta => Records::Fasta(Box::new(FastaParser::new(options))),
+                RecordKind::Fastq => Records::Fastq(Box::new(FastqParser::new(options))),
+                RecordKind::Protein => Records::Protein(Box::new(ProteinParser::new(options))),
+            };
+        }
+        if let Some(validator) = &mut self.validator {
+            validator.feed(data);
+        }
+        match &mut self.records {
+            Records::Fasta(parser) => parser.feed(data, results, simd),
+            Records::Fastq(parser) => parser.feed(data, results, simd),
+            Records::Protein(parser) => parser.feed(data, results),
+            Records::Undecided => unreachable!(),
+        }
+    }
+
+    fn finish(self, results: &mut AnalysisResults) {
+        results.validation = self.validator.map(Validator::finish);
+        match self.records {
+            Records::Fasta(parser) => parser.finish(results),
+            Records::Fastq(parser) => parser.finish(results),
+            Records::Protein(parser) => parser.finish(results),
+            Records::Undecided => FastaParser::new(&ProcessOptions::default()).finish(results),
+        }
+    }
+}
+
+fn looks_like_fastq(data: &[u8]) -> bool {
+    data.iter().find(|b| !b.is_ascii_whitespace()) == Some(&b'@')
+}
+
 #[derive(Default, Clone, Debug)]
 pub struct AnalysisResults {
     pub filename: String,
@@ -164,6 +672,10 @@ pub struct AnalysisResults {
     pub sequence_count: usize,
     pub gc_count: usize,
     pub n_count: usize,
+    /// T, U and nucleotide counts, from which the molecule type is detected.
+    pub t_count: usize,
+    pub u_count: usize,
+    pub nucleotide_count: usize,
     pub n25: usize,
     pub n25_sequence_count: usize,
     pub n50: usize,
@@ -172,6 +684,33 @@ pub struct AnalysisResults {
     pub n75_sequence_count: usize,
     pub largest_contig: usize,
     pub shortest_contig: usize,
+    /// Median, quartiles, spread and histogram of the record lengths.
+    pub length_distribution: LengthDistribution,
+    /// Record lengths the stats were computed from, kept so results can be merged.
+    pub lengths: SequenceLengths,
+    /// Base qualities, for FASTQ input only.
+    pub quality: Option<QualityStats>,
+    /// Residue composition and weights, for protein input only.
+    pub protein: Option<ProteinStats>,
+    /// Repeated record IDs.
+    pub duplicate_ids: Option<DuplicateIds>,
+    /// Repeated record sequences, with `--duplicate-sequences`.
+    pub duplicate_sequences: Option<DuplicateSequences>,
+    /// Sequence digests, with `--checksums`.
+    pub checksums: Option<Checksums>,
+    /// Index entries with `--write-fai`, line wrapping with `--line-widths`.
+    pub fai: Option<FaiIndex>,
+    /// Windowed GC content with `--gc-window`, for nucleotide FASTA and NAF.
+    pub gc_track: Option<GcTrack>,
+    /// Problems found by `--validate`, for FASTA input only.
+    pub validation: Option<ValidationReport>,
+    /// Records and bases at or above each `--thresholds` length.
+    pub length_thresholds: Vec<LengthThreshold>,
+    /// Lengths were read from a `.fai` with `--from-index`; GC, N and the rest of
+    /// the composition were not counted.
+    pub lengths_only: bool,
+    /// Records left out by `--min-length`/`--max-length`, when either is given.
+    pub filtered: Option<FilteredRecords>,
 }
 
 impl AnalysisResults {
@@ -191,33 +730,163 @@ impl AnalysisResults {
         Self::new(filename)
     }
 
-    pub fn calculate_stats(&mut self, mut lengths: Vec<usize>) {
+    /// Names where these results came from for messages: the path of `file`, plus
+    /// the member name for archive members.
+    pub fn source_name(&self, file: &Path) -> String {
+        let source = file.display().to_string();
+        if file.file_name().is_some_and(|name| name.to_string_lossy() == self.filename) {
+            source
+        } else {
+            format!("{source}: {}", self.filename)
+        }
+    }
+
+    /// Adds the composition of a run of sequence bytes and returns its length.
+    pub fn add_bases(&mut self, counts: BaseCounts) -> usize {
+        self.gc_count += counts.gc();
+        self.n_count += counts.n;
+        self.t_count += counts.t;
+        self.u_count += counts.u;
+        self.nucleotide_count += counts.nucleotides;
+        counts.seq_chars
+    }
+
+    pub fn line_widths(&self) -> Option<&LineWidths> {
+        self.fai.as_ref()?.line_widths.as_ref()
+    }
+
+    /// The molecule type the residues of these results look like.
+    pub fn molecule_type(&self) -> MoleculeType {
+        if self.lengths_only {
+            return MoleculeType::Unknown;
+        }
+        match &self.protein {
+            Some(protein) => MoleculeType::from_protein(protein),
+            None => MoleculeType::classify(
+                self.total_length,
+                self.nucleotide_count,
+                self.t_count,
+                self.u_count,
+            ),
+        }
+    }
+
+    /// Fills in totals, extremes and N25/N50/N75 from the record lengths. Lengths
+    /// are walked as (length, count) groups, longest first, so a histogram gives
+    /// the same answer as the full sorted list.
+    pub fn calculate_stats(&mut self, lengths: impl Into<SequenceLengths>) {
+        let mut lengths = lengths.into();
         if lengths.is_empty() {
+            self.lengths = lengths;
             return;
         }
-        let total_length: usize = lengths.iter().sum();
+        let total_length = lengths.total();
         self.total_length = total_length;
         self.sequence_count = lengths.len();
+        let groups = lengths.descending();
+        self.largest_contig = groups.first().map_or(0, |&(length, _)| length);
+        self.shortest_contig = groups.last().map_or(usize::MAX, |&(length, _)| length);
+
+        let thresholds = [total_length / 4, total_length / 2, total_length * 3 / 4];
+        let mut found = [None; 3];
         let mut cumulative_length = 0;
-        lengths.sort_unstable_by(|a, b| b.cmp(a)); // Sort in-place
-        self.largest_contig = *lengths.first().unwrap_or(&0);
-        self.shortest_contig = *lengths.last().unwrap_or(&usize::MAX);
-
-        for (i, &length) in lengths.iter().enumerate() {
-            cumulative_length += length;
-            if self.n25 == 0 && cumulative_length >= total_length / 4 {
-                self.n25 = length;
-                self.n25_sequence_count = i + 1;
-            }
-            if self.n50 == 0 && cumulative_length >= total_length / 2 {
-                self.n50 = length;
-                self.n50_sequence_count = i + 1;
+        let mut records_before = 0;
+        for &(length, count) in &groups {
+            let group_end = cumulative_length + length * count;
+            for (slot, &threshold) in found.iter_mut().zip(&thresholds) {
+                if slot.is_none() && group_end >= threshold {
+                    // Records of this group needed to reach the threshold.
+                    let needed = if length == 0 {
+                        1
+                    } else {
+                        threshold.saturating_sub(cumulative_length).div_ceil(length).max(1)
+                    };
+                    *slot = Some((length, records_before + needed));
+                }
             }
-            if self.n75 == 0 && cumulative_length >= total_length * 3 / 4 {
-                self.n75 = length;
-                self.n75_sequence_count = i + 1;
+            if found[2].is_some() {
                 break;
             }
+            cumulative_length = group_end;
+            records_before += count;
+        }
+        let [n25, n50, n75] = found.map(|f| f.unwrap_or((0, 0)));
+        self.length_distribution = LengthDistribution::from_groups(&groups);
+        (self.n25, self.n25_sequence_count) = n25;
+        (self.n50, self.n50_sequence_count) = n50;
+        (self.n75, self.n75_sequence_count) = n75;
+        self.lengths = lengths;
+    }
+
+    /// Fills in `length_thresholds` from the record lengths.
+    pub fn count_thresholds(&mut self, thresholds: &[usize]) {
+        self.length_thresholds = self.lengths.at_least(thresholds);
+    }
+
+    /// Merges many results into one named `filename`, computing the length
+    /// statistics and the checksum file digests once at the end. Duplicate IDs
+    /// and sequences are per file and are not merged.
+    pub fn combine<'a>(filename: String, parts: impl IntoIterator<Item = &'a AnalysisResults>) -> Self {
+        let mut combined = Self::new(filename);
+        for part in parts {
+            combined.absorb(part);
+        }
+        let lengths = std::mem::take(&mut combined.lengths);
+        combined.calculate_stats(lengths);
+        if let Some(checksums) = combined.checksums.take() {
+            combined.checksums = Some(Checksums::new(checksums.sequences));
+        }
+        combined
+    }
+
+    /// Adds the records of `other`, as if both had been read from one file. The
+    /// length statistics and checksum file digests are stale until `combine`
+    /// recomputes them.
+    fn absorb(&mut self, other: &AnalysisResults) {
+        self.total_length += other.total_length;
+        self.sequence_count += other.sequence_count;
+        self.gc_count += other.gc_count;
+        self.n_count += other.n_count;
+        self.t_count += other.t_count;
+        self.u_count += other.u_count;
+        self.nucleotide_count += other.nucleotide_count;
+        self.largest_contig = self.largest_contig.max(other.largest_contig);
+        self.shortest_contig = self.shortest_contig.min(other.shortest_contig);
+        self.lengths.append(&other.lengths);
+        self.lengths_only |= other.lengths_only;
+        if let Some(other_filtered) = other.filtered {
+            self.filtered
+                .get_or_insert_with(FilteredRecords::default)
+                .absorb(other_filtered);
+        }
+        if let Some(other_quality) = &other.quality {
+            self.quality
+                .get_or_insert_with(QualityStats::default)
+                .absorb(other_quality);
+        }
+        if let Some(other_protein) = &other.protein {
+            self.protein
+                .get_or_insert_with(ProteinStats::default)
+                .absorb(other_protein);
+        }
+        if let Some(other_checksums) = &other.checksums {
+            self.checksums
+                .get_or_insert_with(Checksums::default)
+                .sequences
+                .extend_from_slice(&other_checksums.sequences);
+        }
+        // Index entries hold offsets into their own file; only the wrapping merges.
+        if let Some(other_widths) = other.line_widths() {
+            self.fai
+                .get_or_insert_with(FaiIndex::default)
+                .line_widths
+                .get_or_insert_with(LineWidths::default)
+                .absorb(other_widths);
+        }
+        if let Some(other_validation) = &other.validation {
+            self.validation
+                .get_or_insert_with(ValidationReport::default)
+                .absorb(other_validation);
         }
     }
 }
@@ -252,30 +921,71 @@ pub fn open_file<P: AsRef<Path>>(path: P) -> std::io::Result<File> {
     Ok(file)
 }
 
+/// Multi-block streams (as written by `xz -T`) are decoded on the pool's threads;
+/// single-block streams cannot be split and keep the sequential decoder.
 pub fn process_xz_file(
     file: &Path,
-    no_simd: bool,
+    options: &ProcessOptions,
 ) -> std::io::Result<Vec<AnalysisResults>> {
-    process_decoded_stream(file, XzDecoder::new, no_simd)
+    let threads = rayon::current_num_threads();
+    if threads > 1
+        && let Ok(mmap) = unsafe { Mmap::map(&open_file(file)?) }
+        && parallel_decode::xz_block_count(&mmap).is_some_and(|blocks| blocks > 1)
+    {
+        let stream = parallel_decode::xz_parallel_stream(threads)?;
+        return process_decoded_stream(file, |f| XzDecoder::new_stream(f, stream), options);
+    }
+    process_decoded_stream(file, XzDecoder::new, options)
 }
 
 pub fn process_bz2_file(
     file: &Path,
-    no_simd: bool,
+    options: &ProcessOptions,
 ) -> std::io::Result<Vec<AnalysisResults>> {
-    process_decoded_stream(file, BzDecoder::new, no_simd)
+    process_decoded_stream(file, BzDecoder::new, options)
 }
 
+/// BGZF blocks are independent deflate streams, so they are inflated on
+/// `decoder_threads`, this file's share of the pool's threads (`-t`), and handed to
+/// the parser in file order.
 pub fn process_bgzip_file(
     file: &Path,
-    no_simd: bool,
+    options: &ProcessOptions,
+    decoder_threads: usize,
 ) -> std::io::Result<Vec<AnalysisResults>> {
-    process_decoded_stream(file, bgzf::io::Reader::new, no_simd)
+    let workers = NonZeroUsize::new(decoder_threads);
+    if !options.write_gzi {
+        return match workers {
+            Some(workers) if workers.get() > 1 => process_decoded_stream(
+                file,
+                |f| bgzf::io::MultithreadedReader::with_worker_count(workers, f),
+                options,
+            ),
+            _ => process_decoded_stream(file, bgzf::io::Reader::new, options),
+        };
+    }
+    let builder = Arc::new(Mutex::new(GziBuilder::new()));
+    let indexer = |f| BlockIndexer::new(f, Arc::clone(&builder));
+    let results = match workers {
+        Some(workers) if workers.get() > 1 => process_decoded_stream(
+            file,
+            |f| bgzf::io::MultithreadedReader::with_worker_count(workers, indexer(f)),
+            options,
+        ),
+        _ => process_decoded_stream(file, |f| bgzf::io::Reader::new(indexer(f)), options),
+    }?;
+    // The decoder has been dropped, so the builder has seen the whole file.
+    let builder = Arc::into_inner(builder).expect("the decoder is gone");
+    let index = builder.into_inner().unwrap().finish().map_err(|e| {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, format!("cannot index {:?}: {e}", file))
+    })?;
+    gzi::write(file, &index)?;
+    Ok(results)
 }
 
 pub fn process_fasta_file(
     file: &Path,
-    no_simd: bool,
+    options: &ProcessOptions,
 ) -> std::io::Result<Vec<AnalysisResults>> {
     let mut results = AnalysisResults::for_path(file);
     let file = open_file(file)?;
@@ -291,25 +1001,30 @@ pub fn process_fasta_file(
             #[cfg(target_os = "linux")]
             mmap.advise(memmap2::Advice::HugePage)?;
 
-            process_buffer(&mmap, &mut results, no_simd)?;
+            process_buffer_parallel(&mmap, &mut results, options, PARALLEL_CHUNK_MIN_SIZE)?;
         }
         Err(_) => {
             println!("Failed to mmap file: {:?}", file);
             let reader = BufReader::with_capacity(DECODER_BUFFER_SIZE, file);
-            process_reader(reader, &mut results, no_simd)?;
+            process_reader(reader, &mut results, options)?;
         }
     }
 
     Ok(vec![results])
 }
 
-pub fn process_naf_file(file: &Path, no_simd: bool) -> std::io::Result<Vec<AnalysisResults>> {
+pub fn process_naf_file(file: &Path, options: &ProcessOptions) -> std::io::Result<Vec<AnalysisResults>> {
     let mut results = AnalysisResults::for_path(file);
     let decoder = nafcodec::Decoder::from_path(file)
         .map_err(|e| std::io::Error::other(format!("failed to open nucleotide archive: {e}")))?;
 
     // Process naf file
-    let mut lengths = Vec::with_capacity(250);
+    let mut lengths = SequenceLengths::new();
+    let mut ids = options.duplicate_ids.then(IdTracker::new);
+    let mut filtered = FilteredRecords::default();
+    let mut gc_track = options
+        .gc_window
+        .map(|window| GcTrackBuilder::new(window, options.gc_skew, options.simd));
 
     for may_seq in decoder {
         let seq = may_seq.map_err(|e| std::io::Error::other(format!("{file:?} had bad data: {e}")))?;
@@ -318,6 +1033,20 @@ pub fn process_naf_file(file: &Path, no_simd: bool) -> std::io::Result<Vec<Analy
                 .ok_or_else(|| std::io::Error::other(format!("naf file had empty seq on {file:?}?")))?,
         )
         .map_err(|e| std::io::Error::other(format!("failed to turn u64 to usize on {file:?}: {e}")))?;
+        let selected = options.header_filter.as_ref().is_none_or(|filter| {
+            let id = seq.id.as_deref().unwrap_or_default();
+            match &seq.comment {
+                Some(comment) => filter.selects(id.as_bytes(), format!("{id} {comment}").as_bytes()),
+                None => filter.selects(id.as_bytes(), id.as_bytes()),
+            }
+        });
+        if !(selected && options.length_filter.keeps(seq_length)) {
+            filtered.add(seq_length);
+            continue;
+        }
+        if let (Some(ids), Some(id)) = (&mut ids, &seq.id) {
+            ids.insert(id.as_bytes());
+        }
         results.total_length += seq_length;
         results.largest_contig = results.largest_contig.max(seq_length);
         results.shortest_contig = results.shortest_contig.min(seq_length);
@@ -325,41 +1054,67 @@ pub fn process_naf_file(file: &Path, no_simd: bool) -> std::io::Result<Vec<Analy
         let line = seq
             .sequence
             .ok_or_else(|| std::io::Error::other(format!("naf sequence had bad data {file:?}")))?;
-        update_stats(line.as_bytes(), &mut results, no_simd);
+        update_stats(line.as_bytes(), &mut results, options.simd);
+        if let Some(gc_track) = &mut gc_track {
+            gc_track.begin_record();
+            gc_track.sequence_bytes(line.as_bytes());
+            gc_track.end_record(seq.id.as_deref().unwrap_or_default().as_bytes(), true);
+        }
     }
     results.sequence_count = lengths.len();
+    results.gc_track = gc_track.map(GcTrackBuilder::finish);
+    results.filtered = options.filters_records().then_some(filtered);
+    results.duplicate_ids = ids.map(IdTracker::finish);
     results.calculate_stats(lengths);
 
     Ok(vec![results])
 }
 
+/// Multi-member files are inflated member-run by member-run on the pool's threads;
+/// a single member has to be decoded sequentially.
 pub fn process_gz_file(
     file: &Path,
-    no_simd: bool,
+    options: &ProcessOptions,
 ) -> std::io::Result<Vec<AnalysisResults>> {
-    process_decoded_stream(file, GzDecoder::new, no_simd)
+    let threads = rayon::current_num_threads();
+    if threads > 1
+        && let Ok(mmap) = unsafe { Mmap::map(&open_file(file)?) }
+        && parallel_decode::gzip_member_candidates(&mmap).len() > 1
+    {
+        let mut results = AnalysisResults::for_path(file);
+        let mut parser = RecordParser::new(options);
+        parallel_decode::decode_gzip_members(
+            &mmap,
+            threads,
+            parallel_decode::GZIP_RUN_SIZE,
+            |chunk| parser.feed(chunk, &mut results, options),
+        )?;
+        parser.finish(&mut results);
+        return Ok(vec![results]);
+    }
+    process_decoded_stream(file, MultiGzDecoder::new, options)
 }
 
 fn process_decoded_stream<D, F>(
     file: &Path,
     decoder_factory: F,
-    no_simd: bool,
+    options: &ProcessOptions,
 ) -> std::io::Result<Vec<AnalysisResults>>
 where
     D: Read,
-    F: Fn(File) -> D,
+    F: FnOnce(File) -> D,
 {
     let mut results = AnalysisResults::for_path(file);
     let file = open_file(file)?;
     let decoder = decoder_factory(file);
     let reader = BufReader::with_capacity(DECODER_BUFFER_SIZE, decoder);
-    process_reader(reader, &mut results, no_simd)?;
+    process_reader(reader, &mut results, options)?;
     Ok(vec![results])
 }
 
 pub fn process_zip_file(
     file: &Path,
-    no_simd: bool,
+    options: &ProcessOptions,
 ) -> std::io::Result<Vec<AnalysisResults>> {
     let file = open_file(file)?;
     let buf_reader = BufReader::with_capacity(DECODER_BUFFER_SIZE, file);
@@ -379,7 +1134,7 @@ pub fn process_zip_file(
                         .to_string(),
                 );
                 let reader = BufReader::with_capacity(DECODER_BUFFER_SIZE, zip_file);
-                if let Err(e) = process_reader(reader, &mut result, no_simd) {
+                if let Err(e) = process_reader(reader, &mut result, options) {
                     eprintln!("Error processing {file_name}: {e}");
                     continue; // Skip this file but continue processing others
                 };
@@ -394,16 +1149,16 @@ pub fn process_zip_file(
 fn process_reader<R: Read>(
     mut reader: BufReader<R>,
     results: &mut AnalysisResults,
-    no_simd: bool,
+    options: &ProcessOptions,
 ) -> std::io::Result<()> {
-    let mut parser = FastaParser::new();
+    let mut parser = RecordParser::new(options);
 
     loop {
         let buf = reader.fill_buf()?;
         if buf.is_empty() {
             break;
         }
-        parser.feed(buf, results, no_simd);
+        parser.feed(buf, results, options);
         let consumed = buf.len();
         reader.consume(consumed);
     }
@@ -412,27 +1167,94 @@ fn process_reader<R: Read>(
     Ok(())
 }
 
-fn update_stats(line: &[u8], results: &mut AnalysisResults, no_simd: bool) -> usize {
-    let (gc, n, seq_chars) = crate::simd::update_stats(line, no_simd);
-    results.gc_count += gc;
-    results.n_count += n;
-    seq_chars
+fn update_stats(line: &[u8], results: &mut AnalysisResults, simd: SimdLevel) -> usize {
+    results.add_bases(crate::simd::update_stats(line, simd))
 }
 
 fn process_buffer(
     data: &[u8],
     results: &mut AnalysisResults,
-    no_simd: bool,
+    options: &ProcessOptions,
 ) -> std::io::Result<()> {
-    let mut parser = FastaParser::new();
-    parser.feed(data, results, no_simd);
+    let mut parser = RecordParser::new(options);
+    parser.feed(data, results, options);
     parser.finish(results);
     Ok(())
 }
 
+/// Parses a fully resident buffer on the current rayon pool. The buffer is split
+/// at `\n>` record boundaries, each piece is parsed independently and the partial
+/// parser states are merged in order, so the result matches `process_buffer`.
+/// FASTQ has no unambiguous record start and, like protein FASTA, is parsed
+/// sequentially.
+fn process_buffer_parallel(
+    data: &[u8],
+    results: &mut AnalysisResults,
+    options: &ProcessOptions,
+    min_chunk_size: usize,
+) -> std::io::Result<()> {
+    let parts = rayon::current_num_threads().min(data.len() / min_chunk_size.max(1));
+    // The validator tracks lines and offsets from the start, so it needs one pass.
+    if parts <= 1 || options.validate || sniff(data, options.alphabet) != RecordKind::Fasta {
+        return process_buffer(data, results, options);
+    }
+
+    let chunks: Vec<(usize, &[u8])> = split_at_record_boundaries(data, parts)
+        .into_iter()
+        .scan(0, |start, chunk| {
+            let chunk_start = *start;
+            *start += chunk.len();
+            Some((chunk_start, chunk))
+        })
+        .collect();
+    let partials: Vec<(FastaParser, AnalysisResults)> = chunks
+        .into_par_iter()
+        .map(|(start, chunk)| {
+            let mut parser = FastaParser::for_chunk(options, start);
+            let mut partial = AnalysisResults::default();
+            parser.feed(chunk, &mut partial, options.simd);
+            (parser, partial)
+        })
+        .collect();
+
+    let mut parser = FastaParser::for_chunk(options, 0);
+    for (next, partial) in partials {
+        parser.merge(next);
+        results.sequence_count += partial.sequence_count;
+    }
+    parser.close();
+    let header_offsets = parser.header_offsets.take();
+    parser.finish(results);
+    if let Some(offsets) = header_offsets {
+        results.duplicate_ids = Some(ids::track_ids_at(data, &offsets));
+    }
+    Ok(())
+}
+
+/// Splits `data` into at most `parts` roughly equal slices. Every slice but the first
+/// begins with the `>` of a header line.
+fn split_at_record_boundaries(data: &[u8], parts: usize) -> Vec<&[u8]> {
+    let mut chunks = Vec::with_capacity(parts);
+    let mut start = 0;
+    for i in 1..parts {
+        let target = (data.len() / parts * i).max(start);
+        match memmem::find(&data[target..], b"\n>") {
+            Some(pos) => {
+                let end = target + pos + 1;
+                chunks.push(&data[start..end]);
+                start = end;
+            }
+            None => break,
+        }
+    }
+    chunks.push(&data[start..]);
+    chunks
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::collections::HashSet;
     use std::fs;
 
     #[test]
@@ -453,10 +1275,77 @@ mod tests {
         assert_eq!(results.n75_sequence_count, 3);
     }
 
+    #[test]
+    fn test_calculate_stats_matches_sorted_list() {
+        // Reference: walk every record of the sorted list.
+        fn reference(mut lengths: Vec<usize>) -> [(usize, usize); 3] {
+            lengths.sort_unstable_by(|a, b| b.cmp(a));
+            let total: usize = lengths.iter().sum();
+            let thresholds = [total / 4, total / 2, total * 3 / 4];
+            let mut cumulative = 0;
+            let mut found = [(0, 0); 3];
+            for (i, &length) in lengths.iter().enumerate() {
+                cumulative += length;
+                for (slot, &threshold) in found.iter_mut().zip(&thresholds) {
+                    if slot.1 == 0 && cumulative >= threshold {
+                        *slot = (length, i + 1);
+                    }
+                }
+            }
+            found
+        }
+
+        let mut seed = 12345u64;
+        let mut cases = vec![vec![0, 0], vec![1], vec![3, 0, 0], vec![5, 5, 5, 5]];
+        for n in [2, 7, 50, 1000] {
+            cases.push(
+                (0..n)
+                    .map(|_| {
+                        seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+                        (seed >> 33) as usize % 40
+                    })
+                    .collect(),
+            );
+        }
+        for lengths in cases {
+            let mut results = AnalysisResults::new("test.fa".to_string());
+            results.calculate_stats(lengths.clone());
+            let got = [
+                (results.n25, results.n25_sequence_count),
+                (results.n50, results.n50_sequence_count),
+                (results.n75, results.n75_sequence_count),
+            ];
+            assert_eq!(got, reference(lengths.clone()), "{lengths:?}");
+        }
+    }
+
+    #[test]
+    fn test_calculate_stats_low_memory() {
+        // Enough reads to switch to the histogram: 150 bp reads plus a few long ones.
+        let reads = crate::lengths::LOW_MEMORY_THRESHOLD + 10;
+        let mut lengths = SequenceLengths::new();
+        for i in 0..reads {
+            lengths.push(if i % 100_000 == 0 { 1_000_000 } else { 150 });
+        }
+        assert!(lengths.is_histogram());
+        let mut results = AnalysisResults::new("reads.fq".to_string());
+        results.calculate_stats(lengths);
+
+        let long = reads.div_ceil(100_000);
+        let total = long * 1_000_000 + (reads - long) * 150;
+        assert_eq!(results.sequence_count, reads);
+        assert_eq!(results.total_length, total);
+        assert_eq!(results.largest_contig, 1_000_000);
+        assert_eq!(results.shortest_contig, 150);
+        assert_eq!(results.n25, 150);
+        let needed = (total / 4 - long * 1_000_000).div_ceil(150);
+        assert_eq!(results.n25_sequence_count, long + needed);
+    }
+
     #[test]
     fn test_update_stats() {
         let mut results = AnalysisResults::default();
-        update_stats(b"ATGCatgcNNnn", &mut results, false);
+        update_stats(b"ATGCatgcNNnn", &mut results, SimdLevel::detect());
         assert_eq!(results.gc_count, 4);
         assert_eq!(results.n_count, 4);
     }
@@ -465,7 +1354,7 @@ mod tests {
     fn test_process_buffer() {
         let data = b">seq1\nATGC\n>seq2\nAAAAA\n";
         let mut results = AnalysisResults::new("buffer".to_string());
-        process_buffer(data, &mut results, false).unwrap();
+        process_buffer(data, &mut results, &ProcessOptions::default()).unwrap();
 
         assert_eq!(results.total_length, 9);
         assert_eq!(results.sequence_count, 2);
@@ -475,6 +1364,200 @@ mod tests {
         assert_eq!(results.shortest_contig, 4);
     }
 
+    #[test]
+    fn test_process_buffer_parallel_matches_sequential() {
+        let mut data = b"noise before\n>empty\n>seq1 GC in header\nACGTN\nAC\n>seq2\n".to_vec();
+        for i in 0..200 {
+            data.extend_from_slice(format!(">r{i}\n").as_bytes());
+            data.extend(std::iter::repeat_n(b"GATTACAN"[i % 8], i * 7 % 97));
+            data.push(b'\n');
+        }
+        data.extend_from_slice(b">r7 again\nAC\n>seq2\n>last\nGGCC\n>rc9\n");
+        data.extend_from_slice(&[b'T'; 63]);
+
+        let options = ProcessOptions {
+            duplicate_ids: true,
+            duplicate_sequences: true,
+            list_duplicates: true,
+            checksums: true,
+            write_fai: true,
+            line_widths: true,
+            gc_window: Some(GcWindow { size: 10, step: 3 }),
+            ..Default::default()
+        };
+        let mut sequential = AnalysisResults::new("seq".to_string());
+        process_buffer(&data, &mut sequential, &options).unwrap();
+        let duplicates = sequential.duplicate_ids.as_ref().unwrap();
+        assert_eq!((duplicates.count, duplicates.examples.clone()), (2, vec!["r7".into(), "seq2".into()]));
+        let repeats = sequential.duplicate_sequences.as_ref().unwrap();
+        assert!(repeats.listed.contains(&("rc9".to_string(), "r9".to_string(), true)));
+
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(4).build().unwrap();
+        for min_chunk_size in [1, 16, 256, 4096] {
+            let mut parallel = AnalysisResults::new("seq".to_string());
+            pool.install(|| process_buffer_parallel(&data, &mut parallel, &options, min_chunk_size))
+                .unwrap();
+            assert_eq!(format!("{sequential:?}"), format!("{parallel:?}"));
+            // Debug shows only the size of the GC track's lines.
+            assert_eq!(gc_lines(&sequential), gc_lines(&parallel));
+        }
+    }
+
+    fn gc_lines(results: &AnalysisResults) -> String {
+        let mut lines = Vec::new();
+        results.gc_track.as_ref().unwrap().write_lines(&mut lines).unwrap();
+        String::from_utf8(lines).unwrap()
+    }
+
+    #[test]
+    fn test_process_length_filter() {
+        let options = ProcessOptions {
+            length_filter: LengthFilter { min: 4, max: 6 },
+            duplicate_ids: true,
+            duplicate_sequences: true,
+            checksums: true,
+            line_widths: true,
+            ..Default::default()
+        };
+        let unfiltered = ProcessOptions {
+            length_filter: LengthFilter::default(),
+            ..options.clone()
+        };
+        // Records left out repeat the IDs and sequences of kept ones.
+        let cases: [(&[u8], &[u8]); 3] = [
+            (
+                b">short\nGC\n>a\nACGTN\n>long\nGGGGGGGGGG\n>b\nAT\nGC\n>a\nAT\nGC\nATGC\n>empty\n",
+                b">a\nACGTN\n>b\nAT\nGC\n",
+            ),
+            (
+                b"@short\nGC\n+\n!!\n@a\nACGTN\n+\nIIIII\n@long\nGGGGGGGGGG\n+\n##########\n@a\nGC\n+\n!!\n",
+                b"@a\nACGTN\n+\nIIIII\n",
+            ),
+            (
+                b">p1\nMKV*\n>p2\nMKVLLE\n>p3\nMKVLLEFFPW*\n>p2\nMK\n",
+                b">p2\nMKVLLE\n",
+            ),
+        ];
+        for (data, kept) in cases {
+            let mut filtered = AnalysisResults::new("f".to_string());
+            process_buffer(data, &mut filtered, &options).unwrap();
+            let mut expected = AnalysisResults::new("f".to_string());
+            process_buffer(kept, &mut expected, &unfiltered).unwrap();
+
+            assert_eq!(filtered.total_length, expected.total_length);
+            assert_eq!(filtered.sequence_count, expected.sequence_count);
+            assert_eq!(filtered.gc_count, expected.gc_count);
+            assert_eq!(filtered.n_count, expected.n_count);
+            assert_eq!(filtered.shortest_contig, expected.shortest_contig);
+            assert_eq!(filtered.quality, expected.quality);
+            assert_eq!(filtered.protein, expected.protein);
+            assert_eq!(filtered.duplicate_ids, expected.duplicate_ids);
+            assert_eq!(filtered.duplicate_sequences, expected.duplicate_sequences);
+            assert_eq!(filtered.checksums, expected.checksums);
+            assert_eq!(filtered.line_widths(), expected.line_widths());
+            let dropped = filtered.filtered.unwrap();
+            let records = data.iter().filter(|&&b| b == b'>' || b == b'@').count();
+            assert_eq!(dropped.records + filtered.sequence_count, records);
+            assert_eq!(expected.filtered, None);
+        }
+
+        // Chunks that each drop records still merge to the sequential result.
+        let mut data = Vec::new();
+        for i in 0..300 {
+            data.extend_from_slice(format!(">r{i}\n").as_bytes());
+            data.extend(std::iter::repeat_n(b"GATTACAN"[i % 8], i * 7 % 11));
+            data.push(b'\n');
+        }
+        let mut sequential = AnalysisResults::new("seq".to_string());
+        process_buffer(&data, &mut sequential, &options).unwrap();
+        assert!(sequential.filtered.unwrap().records > 0);
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(4).build().unwrap();
+        for min_chunk_size in [1, 64, 1024] {
+            let mut parallel = AnalysisResults::new("seq".to_string());
+            pool.install(|| process_buffer_parallel(&data, &mut parallel, &options, min_chunk_size))
+                .unwrap();
+            assert_eq!(format!("{sequential:?}"), format!("{parallel:?}"));
+        }
+    }
+
+    #[test]
+    fn test_process_header_filter() {
+        let filter = HeaderFilter {
+            exclude: HashSet::from([b"chrM".to_vec()]),
+            regex: Some(regex::bytes::Regex::new("^chr").unwrap()),
+            ..Default::default()
+        };
+        let options = ProcessOptions {
+            header_filter: Some(Arc::new(filter)),
+            duplicate_ids: true,
+            ..Default::default()
+        };
+        let cases: [(&[u8], &[u8]); 3] = [
+            (
+                b">chr1 nuclear\nACGTN\n>plasmid1\nGGGG\n>chrM\nGC\n>chr2\nAT\nGC\n>unplaced",
+                b">chr1 nuclear\nACGTN\n>chr2\nAT\nGC\n",
+            ),
+            (
+                b"@chr1\nACGTN\n+\nIIIII\n@plasmid\nGG\n+\n!!\n",
+                b"@chr1\nACGTN\n+\nIIIII\n",
+            ),
+            (
+                b">chrM\nMKV*\n>chr9 p\nMKVLLE\n>other\nMKVLLEFFPW*\n",
+                b">chr9 p\nMKVLLE\n",
+            ),
+        ];
+        for (data, kept) in cases {
+            let mut filtered = AnalysisResults::new("f".to_string());
+            process_buffer(data, &mut filtered, &options).unwrap();
+            let mut expected = AnalysisResults::new("f".to_string());
+            process_buffer(kept, &mut expected, &ProcessOptions::default()).unwrap();
+
+            assert_eq!(filtered.total_length, expected.total_length);
+            assert_eq!(filtered.sequence_count, expected.sequence_count);
+            assert_eq!(filtered.gc_count, expected.gc_count);
+            assert_eq!(filtered.quality, expected.quality);
+            assert_eq!(filtered.protein, expected.protein);
+            let records = data.iter().filter(|&&b| b == b'>' || b == b'@').count();
+            assert_eq!(filtered.filtered.unwrap().records + filtered.sequence_count, records);
+        }
+
+        // Headers split across chunks are still tested whole.
+        let mut data = Vec::new();
+        for i in 0..300 {
+            let header = match i % 3 {
+                0 => format!(">chr{i} description\n"),
+                1 => ">chrM mitochondrion\n".to_string(),
+                _ => format!(">scaffold{i}\n"),
+            };
+            data.extend_from_slice(header.as_bytes());
+            data.extend(std::iter::repeat_n(b"GATTACAN"[i % 8], i % 13));
+            data.push(b'\n');
+        }
+        let mut sequential = AnalysisResults::new("seq".to_string());
+        process_buffer(&data, &mut sequential, &options).unwrap();
+        assert_eq!(sequential.filtered.unwrap().records, 200);
+        // The excluded chrM records are not duplicates either.
+        assert_eq!(sequential.duplicate_ids.as_ref().unwrap().count, 0);
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(4).build().unwrap();
+        for min_chunk_size in [1, 64, 1024] {
+            let mut parallel = AnalysisResults::new("seq".to_string());
+            pool.install(|| process_buffer_parallel(&data, &mut parallel, &options, min_chunk_size))
+                .unwrap();
+            assert_eq!(format!("{sequential:?}"), format!("{parallel:?}"));
+        }
+    }
+
+    #[test]
+    fn test_split_at_record_boundaries() {
+        let data = b">a\nAAAA\n>b\nCCCC\n>c\nGGGG\n";
+        let chunks = split_at_record_boundaries(data, 3);
+        assert_eq!(chunks.concat(), data.to_vec());
+        assert!(chunks.len() > 1);
+        for chunk in &chunks[1..] {
+            assert_eq!(chunk[0], b'>');
+        }
+    }
+
     #[test]
     fn test_update_stats_exhaustive() {
         let mut results = AnalysisResults::default();
@@ -483,7 +1566,7 @@ mod tests {
         // Ambiguity: RrYyWwSsMmKkHhBbVvDd
         // Gaps/Noise: - . [space]
         let input = b"AaCcGgTtNnRrYyWwSsMmKkHhBbVvDd-. \t";
-        let seq_len = update_stats(input, &mut results, false);
+        let seq_len = update_stats(input, &mut results, SimdLevel::detect());
         
         // G, g, C, c are the only 4 counted as GC
         assert_eq!(results.gc_count, 4);
@@ -499,7 +1582,7 @@ mod tests {
         let data = b">seq1\nATGC\r\n>seq2\r\nAAAAA\n";
         let mut results = AnalysisResults::new("mixed".to_string());
         let reader = BufReader::new(&data[..]);
-        process_reader(reader, &mut results, false).unwrap();
+        process_reader(reader, &mut results, &ProcessOptions::default()).unwrap();
 
         assert_eq!(results.total_length, 9);
         assert_eq!(results.sequence_count, 2);
@@ -509,7 +1592,7 @@ mod tests {
     fn test_process_buffer_headers_with_gc() {
         let data = b">seq_with_GC_and_N\nATGC\n>next\nNNNN\n";
         let mut results = AnalysisResults::new("headers".to_string());
-        process_buffer(data, &mut results, false).unwrap();
+        process_buffer(data, &mut results, &ProcessOptions::default()).unwrap();
 
         // Header content should NOT be counted
         assert_eq!(results.gc_count, 2); 
@@ -521,7 +1604,7 @@ mod tests {
     fn test_process_with_gaps_and_whitespace() {
         let data = b">seq1\nAT GC\n-..-\nATGC\n";
         let mut results = AnalysisResults::new("gaps".to_string());
-        process_buffer(data, &mut results, false).unwrap();
+        process_buffer(data, &mut results, &ProcessOptions::default()).unwrap();
 
         // ATGC (4) + ATGC (4) = 8. Gaps and spaces ignored.
         assert_eq!(results.total_length, 8);
@@ -532,7 +1615,7 @@ mod tests {
     fn test_process_buffer_crlf() {
         let data = b">seq1\r\nATGC\r\n>seq2\r\nAAAAA\r\n";
         let mut results = AnalysisResults::new("buffer".to_string());
-        process_buffer(data, &mut results, false).unwrap();
+        process_buffer(data, &mut results, &ProcessOptions::default()).unwrap();
 
         assert_eq!(results.total_length, 9);
         assert_eq!(results.sequence_count, 2);
@@ -542,13 +1625,13 @@ mod tests {
     fn test_process_empty() {
         let data = b"";
         let mut results = AnalysisResults::new("empty".to_string());
-        process_buffer(data, &mut results, false).unwrap();
+        process_buffer(data, &mut results, &ProcessOptions::default()).unwrap();
         assert_eq!(results.total_length, 0);
         assert_eq!(results.sequence_count, 0);
 
         let mut results2 = AnalysisResults::new("empty_reader".to_string());
         let reader = BufReader::new(&data[..]);
-        process_reader(reader, &mut results2, false).unwrap();
+        process_reader(reader, &mut results2, &ProcessOptions::default()).unwrap();
         assert_eq!(results2.total_length, 0);
         assert_eq!(results2.sequence_count, 0);
     }
@@ -557,13 +1640,13 @@ mod tests {
     fn test_process_only_header() {
         let data = b">only_header\n";
         let mut results = AnalysisResults::new("only_header".to_string());
-        process_buffer(data, &mut results, false).unwrap();
+        process_buffer(data, &mut results, &ProcessOptions::default()).unwrap();
         assert_eq!(results.total_length, 0);
         assert_eq!(results.sequence_count, 1);
 
         let mut results2 = AnalysisResults::new("only_header_reader".to_string());
         let reader = BufReader::new(&data[..]);
-        process_reader(reader, &mut results2, false).unwrap();
+        process_reader(reader, &mut results2, &ProcessOptions::default()).unwrap();
         assert_eq!(results2.total_length, 0);
         assert_eq!(results2.sequence_count, 1);
     }
@@ -572,13 +1655,13 @@ mod tests {
     fn test_process_no_trailing_newline() {
         let data = b">seq1\nATGC";
         let mut results = AnalysisResults::new("no_newline".to_string());
-        process_buffer(data, &mut results, false).unwrap();
+        process_buffer(data, &mut results, &ProcessOptions::default()).unwrap();
         assert_eq!(results.total_length, 4);
         assert_eq!(results.sequence_count, 1);
 
         let mut results2 = AnalysisResults::new("no_newline_reader".to_string());
         let reader = BufReader::new(&data[..]);
-        process_reader(reader, &mut results2, false).unwrap();
+        process_reader(reader, &mut results2, &ProcessOptions::default()).unwrap();
         assert_eq!(results2.total_length, 4);
         assert_eq!(results2.sequence_count, 1);
     }
@@ -587,7 +1670,7 @@ mod tests {
     fn test_process_lines_before_header() {
         let data = b"some noise\n>seq1\nATGC\n";
         let mut results = AnalysisResults::new("noise".to_string());
-        process_buffer(data, &mut results, false).unwrap();
+        process_buffer(data, &mut results, &ProcessOptions::default()).unwrap();
         // Noise is now correctly ignored.
         assert_eq!(results.sequence_count, 1);
         assert_eq!(results.total_length, 4);
@@ -597,7 +1680,7 @@ mod tests {
     fn test_real_world_complexities() {
         let data = b"; legacy comment line\n>seq1 with spaces\nATGC\n>seq1\nAAAA\n>  seq2\tmetadata\nGGGG\n";
         let mut results = AnalysisResults::new("complex".to_string());
-        process_buffer(data, &mut results, false).unwrap();
+        process_buffer(data, &mut results, &ProcessOptions::default()).unwrap();
 
         // 1. Comment line is ignored. 3 sequences found.
         // 2. Total length: 4 (ATGC) + 4 (AAAA) + 4 (GGGG) = 12
@@ -608,7 +1691,7 @@ mod tests {
     #[test]
     fn test_process_missing_file() {
         let path = Path::new("non_existent_file.fa");
-        let res = process_fasta_file(path, false);
+        let res = process_fasta_file(path, &ProcessOptions::default());
         assert!(res.is_err());
     }
 
@@ -617,12 +1700,12 @@ mod tests {
         struct FailingReader;
         impl Read for FailingReader {
             fn read(&mut self, _buf: &mut [u8]) -> std::io::Result<usize> {
-                Err(std::io::Error::new(std::io::ErrorKind::Other, "forced failure"))
+                Err(std::io::Error::other("forced failure"))
             }
         }
         let reader = BufReader::new(FailingReader);
         let mut results = AnalysisResults::new("failing".to_string());
-        let res = process_reader(reader, &mut results, false);
+        let res = process_reader(reader, &mut results, &ProcessOptions::default());
         assert!(res.is_err());
     }
 
@@ -631,17 +1714,423 @@ mod tests {
         let mut temp_file = std::env::temp_dir();
         temp_file.push("corrupted.gz");
         fs::write(&temp_file, b"this is not a valid gzip file").unwrap();
-        let res = process_gz_file(&temp_file, false);
+        let res = process_gz_file(&temp_file, &ProcessOptions::default());
         assert!(res.is_err());
         let _ = fs::remove_file(temp_file);
     }
 
+    #[test]
+    fn test_process_bgzip_multithreaded_matches_plain() {
+        use std::io::Write;
+
+        let mut data = Vec::new();
+        for i in 0..5000 {
+            data.extend_from_slice(format!(">r{i}\n").as_bytes());
+            data.extend(std::iter::repeat_n(b"GATTACAN"[i % 8], i % 173));
+            data.push(b'\n');
+        }
+
+        let mut temp_file = std::env::temp_dir();
+        temp_file.push("multithreaded.fna.bgz");
+        let mut writer = bgzf::io::Writer::new(File::create(&temp_file).unwrap());
+        writer.write_all(&data).unwrap();
+        writer.finish().unwrap();
+
+        let mut expected = AnalysisResults::for_path(&temp_file);
+        process_buffer(&data, &mut expected, &ProcessOptions::default()).unwrap();
+
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(4).build().unwrap();
+        let res = pool.install(|| process_bgzip_file(&temp_file, &ProcessOptions::default(), 4)).unwrap();
+        assert_eq!(format!("{expected:?}"), format!("{:?}", res[0]));
+        let _ = fs::remove_file(temp_file);
+    }
+
+    #[test]
+    fn test_process_multi_member_gz_matches_plain() {
+        use flate2::write::GzEncoder;
+        use std::io::Write;
+
+        let mut data = Vec::new();
+        let mut compressed = Vec::new();
+        for i in 0..20 {
+            let mut member = Vec::new();
+            for j in 0..50 {
+                member.extend_from_slice(format!(">m{i}_{j}\n").as_bytes());
+                member.extend(std::iter::repeat_n(b"GATTACAN"[(i + j) % 8], j * 3));
+                member.push(b'\n');
+            }
+            let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(&member).unwrap();
+            compressed.extend(encoder.finish().unwrap());
+            data.extend(member);
+        }
+
+        let mut temp_file = std::env::temp_dir();
+        temp_file.push("multi_member.fna.gz");
+        fs::write(&temp_file, &compressed).unwrap();
+
+        let mut expected = AnalysisResults::for_path(&temp_file);
+        process_buffer(&data, &mut expected, &ProcessOptions::default()).unwrap();
+
+        for threads in [1, 4] {
+            let pool = rayon::ThreadPoolBuilder::new().num_threads(threads).build().unwrap();
+            let res = pool.install(|| process_gz_file(&temp_file, &ProcessOptions::default())).unwrap();
+            assert_eq!(format!("{expected:?}"), format!("{:?}", res[0]));
+        }
+        let _ = fs::remove_file(temp_file);
+    }
+
+    #[test]
+    fn test_process_fastq_plain_and_compressed() {
+        use flate2::write::GzEncoder;
+        use std::io::Write;
+
+        let mut data = Vec::new();
+        for i in 0..300 {
+            let len = 50 + i % 101;
+            data.extend_from_slice(format!("@read{i}\n").as_bytes());
+            data.extend(std::iter::repeat_n(b"GATTACAN"[i % 8], len));
+            data.extend_from_slice(b"\n+\n");
+            data.extend(std::iter::repeat_n(b"5?I"[i % 3], len));
+            data.push(b'\n');
+        }
+        let mut expected = AnalysisResults::new("reads".to_string());
+        process_buffer(&data, &mut expected, &ProcessOptions::default()).unwrap();
+        assert_eq!(expected.sequence_count, 300);
+        assert_eq!(expected.quality.as_ref().unwrap().bases(), expected.total_length);
+
+        let mut plain = std::env::temp_dir();
+        plain.push("reads.fq");
+        fs::write(&plain, &data).unwrap();
+        let mut gz = std::env::temp_dir();
+        gz.push("reads.fastq.gz");
+        let mut encoder = GzEncoder::new(File::create(&gz).unwrap(), flate2::Compression::fast());
+        encoder.write_all(&data).unwrap();
+        encoder.finish().unwrap();
+
+        for path in [&plain, &gz] {
+            let res = process_any_file(path, &ProcessOptions::default(), 1).unwrap();
+            let mut got = res[0].clone();
+            got.filename = expected.filename.clone();
+            assert_eq!(format!("{expected:?}"), format!("{got:?}"));
+        }
+        let _ = fs::remove_file(plain);
+        let _ = fs::remove_file(gz);
+    }
+
+    #[test]
+    fn test_process_protein_alphabet() {
+        let data = b">sp|P01308|INS_HUMAN\nMALWMRLLPLLALLALWGPDPAAAFVNQHLCGSHLVEALYLVCGERGFFYTPKT*\n>frag\nGIVEQCCTSICSLYQLENYCN\n";
+        let mut temp_file = std::env::temp_dir();
+        temp_file.push("insulin.faa");
+        fs::write(&temp_file, data).unwrap();
+
+        let auto = process_any_file(&temp_file, &ProcessOptions::default(), 1).unwrap();
+        let protein = auto[0].protein.as_ref().unwrap();
+        assert_eq!(auto[0].total_length, 75);
+        assert_eq!(auto[0].gc_count, 0);
+        assert_eq!(protein.stop_count, 1);
+
+        let forced = ProcessOptions {
+            alphabet: Alphabet::Dna,
+            ..Default::default()
+        };
+        let mut dna = AnalysisResults::new("insulin.faa".to_string());
+        process_buffer(data, &mut dna, &forced).unwrap();
+        assert!(dna.protein.is_none());
+        assert!(dna.gc_count > 0);
+        assert_eq!(dna.molecule_type(), MoleculeType::Mixed);
+        // Reading a proteome as DNA on request is refused rather than reported.
+        let err = process_any_file(&temp_file, &forced, 1).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        let _ = fs::remove_file(temp_file);
+    }
+
+    #[test]
+    fn test_process_validate() {
+        let validate = ProcessOptions {
+            validate: true,
+            ..Default::default()
+        };
+        let mut results = AnalysisResults::new("bad.fa".to_string());
+        process_buffer(b">a\nACGT\n>a\nAC GT\n", &mut results, &validate).unwrap();
+        let report = results.validation.unwrap();
+        assert_eq!(report.total, 2);
+        assert_eq!((report.issues[1].line, report.issues[1].offset), (4, 13));
+        assert_eq!(results.total_length, 8);
+
+        let mut results = AnalysisResults::new("reads.fq".to_string());
+        process_buffer(b"@r\nACGT\n+\nIIII\n", &mut results, &validate).unwrap();
+        assert!(results.validation.is_none());
+
+        let mut results = AnalysisResults::new("ok.fa".to_string());
+        process_buffer(b">a\nACGT\n", &mut results, &ProcessOptions::default()).unwrap();
+        assert!(results.validation.is_none());
+    }
+
+    #[test]
+    fn test_process_writes_gc_track() {
+        let options = ProcessOptions {
+            gc_window: Some(GcWindow { size: 4, step: 4 }),
+            length_filter: LengthFilter { min: 1, max: usize::MAX },
+            ..Default::default()
+        };
+        let name = format!("count-fasta-{}-windows.fa", std::process::id());
+        let mut path = std::env::temp_dir();
+        path.push(&name);
+        fs::write(&path, b">chr1 first\nGGCC\nAT\nAT\nGCN\n>empty\n>chr2\nAC").unwrap();
+        process_any_file(&path, &options, 1).unwrap();
+        let track_path = gc_track::gc_track_path(&path);
+        assert_eq!(
+            fs::read_to_string(&track_path).unwrap(),
+            format!("track type=bedGraph name=\"{name} GC%\"\n")
+                + concat!(
+                    "chr1\t0\t4\t100.00\n",
+                    "chr1\t4\t8\t0.00\n",
+                    "chr1\t8\t11\t66.67\n",
+                    "chr2\t0\t2\t50.00\n",
+                )
+        );
+        let _ = fs::remove_file(track_path);
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn test_process_writes_gc_skew() {
+        let options = ProcessOptions {
+            gc_window: Some(GcWindow { size: 4, step: 2 }),
+            gc_skew: true,
+            ..Default::default()
+        };
+        let mut path = std::env::temp_dir();
+        path.push(format!("count-fasta-{}-skew.fa", std::process::id()));
+        fs::write(&path, b">ori\nCCCA\nGGGG\n").unwrap();
+        process_any_file(&path, &options, 1).unwrap();
+        let skew_path = gc_track::gc_skew_path(&path);
+        assert_eq!(
+            fs::read_to_string(&skew_path).unwrap(),
+            concat!(
+                "sequence\tstart\tend\tgc_skew\tcumulative_g_minus_c\n",
+                "ori\t0\t4\t-1.0000\t-3\n",
+                "ori\t2\t6\t0.3333\t-1\n",
+                "ori\t4\t8\t1.0000\t1\n",
+            )
+        );
+        let _ = fs::remove_file(skew_path);
+        let _ = fs::remove_file(gc_track::gc_track_path(&path));
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn test_process_writes_fai() {
+        use std::io::Write;
+
+        let write_fai = ProcessOptions {
+            write_fai: true,
+            ..Default::default()
+        };
+        // Wrapped at 60 bases, so the index can be worked out record by record.
+        let mut data = Vec::new();
+        let mut expected = String::new();
+        for i in 0..3000 {
+            let header = format!(">r{i} some description\n");
+            let length = i % 251;
+            data.extend_from_slice(header.as_bytes());
+            let offset = data.len();
+            let sequence: Vec<u8> = b"ACGTNacgt".iter().copied().cycle().take(length).collect();
+            for line in sequence.chunks(60) {
+                data.extend_from_slice(line);
+                data.push(b'\n');
+            }
+            let (bases, width) = if length == 0 {
+                (0, 0)
+            } else {
+                (length.min(60), length.min(60) + 1)
+            };
+            expected.push_str(&format!("r{i}\t{length}\t{offset}\t{bases}\t{width}\n"));
+        }
+
+        let mut plain = std::env::temp_dir();
+        plain.push("indexed.fa");
+        fs::write(&plain, &data).unwrap();
+        let mut bgz = std::env::temp_dir();
+        bgz.push("indexed.fa.bgz");
+        let mut writer = bgzf::io::Writer::new(File::create(&bgz).unwrap());
+        writer.write_all(&data).unwrap();
+        writer.finish().unwrap();
+        // bgzip names its output .gz.
+        let mut bgzip_gz = std::env::temp_dir();
+        bgzip_gz.push("indexed_bgzip.fa.gz");
+        fs::copy(&bgz, &bgzip_gz).unwrap();
+        assert_eq!(FileFormat::detect(&bgzip_gz), FileFormat::Bgzip);
+
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(4).build().unwrap();
+        for file in [&plain, &bgz, &bgzip_gz] {
+            pool.install(|| process_any_file(file, &write_fai, 4)).unwrap();
+            let index_path = fai::fai_path(file);
+            assert_eq!(fs::read_to_string(&index_path).unwrap(), expected, "{file:?}");
+            let _ = fs::remove_file(index_path);
+            let _ = fs::remove_file(file);
+        }
+
+        let mut ragged = std::env::temp_dir();
+        ragged.push("ragged.fa");
+        fs::write(&ragged, b">a\nACGT\nAC\nACGT\n").unwrap();
+        let err = process_any_file(&ragged, &write_fai, 1).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("record 'a'"), "{err}");
+        assert!(!fai::fai_path(&ragged).exists());
+        let _ = fs::remove_file(ragged);
+    }
+
+    #[test]
+    fn test_process_from_index() {
+        use std::io::Write;
+
+        let data = b">chr1 x\nACGTACGTAC\nACG\n>chr2\nNNNN\n>chr1\nA\n";
+        let mut plain = std::env::temp_dir();
+        plain.push("from_index.fa");
+        fs::write(&plain, data).unwrap();
+        let write_fai = ProcessOptions {
+            write_fai: true,
+            ..Default::default()
+        };
+        let scanned = process_any_file(&plain, &write_fai, 1).unwrap().remove(0);
+        let from_index = ProcessOptions {
+            from_index: true,
+            duplicate_ids: true,
+            ..Default::default()
+        };
+        let indexed = process_any_file(&plain, &from_index, 1).unwrap().remove(0);
+        assert!(indexed.lengths_only);
+        assert_eq!(
+            (indexed.total_length, indexed.sequence_count, indexed.n50, indexed.shortest_contig),
+            (scanned.total_length, scanned.sequence_count, scanned.n50, scanned.shortest_contig)
+        );
+        assert_eq!((indexed.gc_count, indexed.n_count), (0, 0));
+        assert_eq!(indexed.duplicate_ids.as_ref().unwrap().examples, vec!["chr1"]);
+        assert_eq!(indexed.molecule_type(), MoleculeType::Unknown);
+
+        // BGZF input needs a .gzi; one with no entries means a single data block.
+        let mut bgz = std::env::temp_dir();
+        bgz.push("from_index.fa.bgz");
+        let mut writer = bgzf::io::Writer::new(File::create(&bgz).unwrap());
+        writer.write_all(data).unwrap();
+        writer.finish().unwrap();
+        assert!(!process_any_file(&bgz, &from_index, 1).unwrap()[0].lengths_only);
+        fs::copy(fai::fai_path(&plain), fai::fai_path(&bgz)).unwrap();
+        fs::write(gzi::gzi_path(&bgz), 0u64.to_le_bytes()).unwrap();
+        let indexed_bgz = process_any_file(&bgz, &from_index, 1).unwrap().remove(0);
+        assert!(indexed_bgz.lengths_only);
+        assert_eq!(indexed_bgz.total_length, scanned.total_length);
+        // Also when bgzip named it .gz.
+        let mut bgzip_gz = std::env::temp_dir();
+        bgzip_gz.push("from_index_bgzip.fa.gz");
+        fs::copy(&bgz, &bgzip_gz).unwrap();
+        fs::copy(fai::fai_path(&bgz), fai::fai_path(&bgzip_gz)).unwrap();
+        fs::copy(gzi::gzi_path(&bgz), gzi::gzi_path(&bgzip_gz)).unwrap();
+        assert!(process_any_file(&bgzip_gz, &from_index, 1).unwrap()[0].lengths_only);
+
+        // Rewriting the file makes the index stale, so it is scanned again.
+        fs::write(&plain, b">chr1\nAC\n").unwrap();
+        let rescanned = process_any_file(&plain, &from_index, 1).unwrap().remove(0);
+        assert!(!rescanned.lengths_only);
+        assert_eq!(rescanned.total_length, 2);
+
+        for path in [fai::fai_path(&plain), plain, fai::fai_path(&bgz), gzi::gzi_path(&bgz), bgz] {
+            let _ = fs::remove_file(path);
+        }
+        for path in [fai::fai_path(&bgzip_gz), gzi::gzi_path(&bgzip_gz), bgzip_gz] {
+            let _ = fs::remove_file(path);
+        }
+    }
+
+    #[test]
+    fn test_process_writes_gzi() {
+        use std::io::Write;
+
+        let mut data = Vec::new();
+        for i in 0..2000 {
+            data.extend_from_slice(format!(">r{i}\n").as_bytes());
+            data.extend(std::iter::repeat_n(b"ACGTN"[i % 5], i % 211));
+            data.push(b'\n');
+        }
+        let mut bgz = std::env::temp_dir();
+        bgz.push("gzi_written.fa.bgz");
+        let mut writer = bgzf::io::Writer::new(File::create(&bgz).unwrap());
+        writer.write_all(&data).unwrap();
+        writer.finish().unwrap();
+
+        let mut expected = GziBuilder::new();
+        expected.update(&fs::read(&bgz).unwrap());
+        let expected = expected.finish().unwrap();
+        assert!(expected.as_ref().len() > 1);
+
+        let options = ProcessOptions {
+            write_gzi: true,
+            write_fai: true,
+            ..Default::default()
+        };
+        for threads in [1, 4] {
+            let pool = rayon::ThreadPoolBuilder::new().num_threads(threads).build().unwrap();
+            let scanned = pool.install(|| process_any_file(&bgz, &options, threads)).unwrap().remove(0);
+            assert_eq!(bgzf::gzi::fs::read(gzi::gzi_path(&bgz)).unwrap(), expected);
+
+            // Both indexes together are what --from-index needs.
+            let from_index = ProcessOptions {
+                from_index: true,
+                ..Default::default()
+            };
+            let indexed = process_any_file(&bgz, &from_index, 1).unwrap().remove(0);
+            assert!(indexed.lengths_only);
+            assert_eq!(indexed.total_length, scanned.total_length);
+        }
+
+        // bgzip names its output .gz, which is indexed all the same.
+        let mut bgzip_gz = std::env::temp_dir();
+        bgzip_gz.push("gzi_written_bgzip.fa.gz");
+        fs::copy(&bgz, &bgzip_gz).unwrap();
+        process_any_file(&bgzip_gz, &options, 1).unwrap();
+        assert_eq!(bgzf::gzi::fs::read(gzi::gzi_path(&bgzip_gz)).unwrap(), expected);
+        for path in [fai::fai_path(&bgz), gzi::gzi_path(&bgz), bgz] {
+            let _ = fs::remove_file(path);
+        }
+        for path in [fai::fai_path(&bgzip_gz), gzi::gzi_path(&bgzip_gz), bgzip_gz] {
+            let _ = fs::remove_file(path);
+        }
+    }
+
+    #[test]
+    fn test_process_detects_molecule_type() {
+        let mut results = AnalysisResults::new("rna.fa".to_string());
+        process_buffer(b">r1\nACGUUAGC\n>r2\nGGAUNNacgu\n", &mut results, &ProcessOptions::default())
+            .unwrap();
+        assert_eq!(results.u_count, 4);
+        assert_eq!(results.t_count, 0);
+        assert_eq!(results.nucleotide_count, 18);
+        assert_eq!(results.molecule_type(), MoleculeType::Rna);
+
+        let mut results = AnalysisResults::new("dna.fa".to_string());
+        process_buffer(b">d\nACGTTAGCNN\n", &mut results, &ProcessOptions::default()).unwrap();
+        assert_eq!(results.molecule_type(), MoleculeType::Dna);
+
+        let forced = ProcessOptions {
+            alphabet: Alphabet::Protein,
+            ..Default::default()
+        };
+        let mut results = AnalysisResults::new("dna.fa".to_string());
+        process_buffer(b">d\nACGTTAGCNN\n", &mut results, &forced).unwrap();
+        assert_eq!(results.molecule_type(), MoleculeType::Dna);
+        assert!(check_molecule_type(Path::new("dna.fa"), &results, Alphabet::Protein).is_err());
+        assert!(check_molecule_type(Path::new("dna.fa"), &results, Alphabet::Auto).is_ok());
+    }
+
     #[test]
     fn test_process_corrupted_xz() {
         let mut temp_file = std::env::temp_dir();
         temp_file.push("corrupted.xz");
         fs::write(&temp_file, b"this is not a valid xz file").unwrap();
-        let res = process_xz_file(&temp_file, false);
+        let res = process_xz_file(&temp_file, &ProcessOptions::default());
         assert!(res.is_err());
         let _ = fs::remove_file(temp_file);
     }
@@ -651,7 +2140,7 @@ mod tests {
         let mut temp_file = std::env::temp_dir();
         temp_file.push("corrupted.zip");
         fs::write(&temp_file, b"this is not a valid zip file").unwrap();
-        let res = process_zip_file(&temp_file, false);
+        let res = process_zip_file(&temp_file, &ProcessOptions::default());
         assert!(res.is_err());
         let _ = fs::remove_file(temp_file);
     }
@@ -661,7 +2150,7 @@ mod tests {
         let mut temp_file = std::env::temp_dir();
         temp_file.push("empty_mmap.fa");
         fs::write(&temp_file, b"").unwrap();
-        let res = process_fasta_file(&temp_file, false);
+        let res = process_fasta_file(&temp_file, &ProcessOptions::default());
         assert!(res.is_ok());
         let results = res.unwrap();
         assert_eq!(results[0].sequence_count, 0);
@@ -673,7 +2162,7 @@ mod tests {
         let mut temp_file = std::env::temp_dir();
         temp_file.push("invalid.naf");
         fs::write(&temp_file, b"this is not a valid naf file").unwrap();
-        let res = process_naf_file(&temp_file, false);
+        let res = process_naf_file(&temp_file, &ProcessOptions::default());
         assert!(res.is_err());
         let _ = fs::remove_file(temp_file);
     }
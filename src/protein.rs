@@ -0,0 +1,399 @@
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0>
+// at your option. This file may not be copied, modified,
+// or distributed except according to those terms.
+
+//! Protein FASTA parsing.
+//!
+//! GC and N content mean nothing for proteomes, so protein records are parsed
+//! separately: every residue letter is tallied for the amino-acid composition,
+//! `*` is counted as a stop, and each record's molecular weight is estimated from
+//! average residue masses.
+
+use crate::checksum::ChecksumBuilder;
+use crate::fai::FaiBuilder;
+use crate::ids::{HeaderId, IdTracker};
+use crate::lengths::{FilteredRecords, LengthFilter, SequenceLengths};
+use crate::process_files::{AnalysisResults, ProcessOptions};
+use crate::select::HeaderSelector;
+use crate::sink::RecordSinks;
+use memchr::memchr;
+
+/// Mass of the water added once per chain, in daltons.
+const WATER_MASS: f64 = 18.01528;
+
+/// Mass used for letters without a defined residue.
+const UNKNOWN_RESIDUE_MASS: f64 = 110.0;
+
+/// Average residue masses in daltons, indexed by `letter - b'A'`. B, Z and J are
+/// the means of their two candidates, X the mass of an average residue.
+const RESIDUE_MASS: [f64; 26] = [
+    71.0788,              // A
+    114.5962,             // B (D or N)
+    103.1388,             // C
+    115.0886,             // D
+    129.1155,             // E
+    147.1766,             // F
+    57.0519,              // G
+    137.1411,             // H
+    113.1594,             // I
+    113.1594,             // J (I or L)
+    128.1741,             // K
+    113.1594,             // L
+    131.1926,             // M
+    114.1038,             // N
+    237.3018,             // O (pyrrolysine)
+    97.1167,              // P
+    128.1307,             // Q
+    156.1875,             // R
+    87.0782,              // S
+    101.1051,             // T
+    150.0388,             // U (selenocysteine)
+    99.1326,              // V
+    186.2132,             // W
+    UNKNOWN_RESIDUE_MASS, // X
+    163.1760,             // Y
+    128.6231,             // Z (E or Q)
+];
+
+/// Residues that are not IUPAC nucleotide codes. Finding enough of them means the
+/// input is protein.
+const PROTEIN_ONLY: &[u8] = b"EFIJLOPQXZefijlopqxz*";
+
+/// Bytes sampled from the start of a file when guessing its alphabet.
+const SNIFF_LEN: usize = 64 * 1024;
+
+/// Guesses from the sequence lines near the start of `data` whether it holds
+/// protein. Nucleotide files contain almost no protein-only letters, so a few
+/// percent of them is decisive. Text before the first header is not sequence and
+/// is ignored.
+pub fn looks_like_protein(data: &[u8]) -> bool {
+    let sample = &data[..data.len().min(SNIFF_LEN)];
+    let mut letters = 0;
+    let mut protein_only = 0;
+    let mut in_record = false;
+    for line in sample.split(|&b| b == b'\n') {
+        if line.first() == Some(&b'>') {
+            in_record = true;
+            continue;
+        }
+        if !in_record {
+            continue;
+        }
+        for &byte in line {
+            if byte.is_ascii_alphabetic() || byte == b'*' {
+                letters += 1;
+                protein_only += usize::from(PROTEIN_ONLY.contains(&byte));
+            }
+        }
+    }
+    letters > 0 && protein_only * 20 > letters
+}
+
+/// Residue composition and molecular weights of a set of protein records.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ProteinStats {
+    /// Count of each residue letter, case-folded, indexed by `letter - b'A'`.
+    pub composition: [usize; 26],
+    pub stop_count: usize,
+    /// Records with at least one residue, over which the weights are taken.
+    pub weighed_records: usize,
+    pub min_weight: f64,
+    pub max_weight: f64,
+    pub total_weight: f64,
+}
+
+impl ProteinStats {
+    pub fn x_count(&self) -> usize {
+        self.composition[(b'X' - b'A') as usize]
+    }
+
+    pub fn residues(&self) -> usize {
+        self.composition.iter().sum()
+    }
+
+    pub fn mean_weight(&self) -> f64 {
+        if self.weighed_records == 0 {
+            return 0.0;
+        }
+        self.total_weight / self.weighed_records as f64
+    }
+
+    fn add_record(&mut self, weight: f64) {
+        if self.weighed_records == 0 {
+            self.min_weight = weight;
+            self.max_weight = weight;
+        } else {
+            self.min_weight = self.min_weight.min(weight);
+            self.max_weight = self.max_weight.max(weight);
+        }
+        self.weighed_records += 1;
+        self.total_weight += weight;
+    }
+
+    pub fn absorb(&mut self, other: &ProteinStats) {
+        for (a, b) in self.composition.iter_mut().zip(&other.composition) {
+            *a += b;
+        }
+        self.stop_count += other.stop_count;
+        if other.weighed_records > 0 {
+            if self.weighed_records == 0 {
+                self.min_weight = other.min_weight;
+                self.max_weight = other.max_weight;
+            } else {
+                self.min_weight = self.min_weight.min(other.min_weight);
+                self.max_weight = self.max_weight.max(other.max_weight);
+            }
+        }
+        self.weighed_records += other.weighed_records;
+        self.total_weight += other.total_weight;
+    }
+}
+
+pub struct ProteinParser {
+    lengths: SequenceLengths,
+    /// Duplicate IDs, checksums and `.fai` entries, when asked for.
+    sinks: RecordSinks,
+    /// ID of the current record.
+    id: HeaderId,
+    stats: ProteinStats,
+    filter: LengthFilter,
+    selector: Option<HeaderSelector>,
+    /// Whether the current record's header passed the selector.
+    selected: bool,
+    filtered: FilteredRecords,
+    at_line_start: bool,
+    in_header: bool,
+    started: bool,
+    current_length: usize,
+    current_weight: f64,
+    /// Residues and stops of the current record, added to `stats` if it passes
+    /// the length filter.
+    current_composition: [usize; 26],
+    current_stops: usize,
+}
+
+impl ProteinParser {
+    pub fn new(options: &ProcessOptions) -> Self {
+        let mut sinks = RecordSinks::default();
+        if options.duplicate_ids {
+            sinks.push(IdTracker::new());
+        }
+        if options.checksums {
+            sinks.push(ChecksumBuilder::new());
+        }
+        if options.write_fai || options.line_widths {
+            sinks.push(FaiBuilder::new(options.write_fai, options.line_widths));
+        }
+        Self {
+            lengths: SequenceLengths::new(),
+            sinks,
+            id: HeaderId::default(),
+            stats: ProteinStats::default(),
+            filter: options.length_filter,
+            selector: options.header_filter.clone().map(HeaderSelector::new),
+            selected: true,
+            filtered: FilteredRecords::default(),
+            at_line_start: true,
+            in_header: false,
+            started: false,
+            current_length: 0,
+            current_weight: 0.0,
+            current_composition: [0; 26],
+            current_stops: 0,
+        }
+    }
+
+    pub fn feed(&mut self, data: &[u8], results: &mut AnalysisResults) {
+        let mut pos = 0;
+        while pos < data.len() {
+            let rest = &data[pos..];
+            let (line, complete) = match memchr(b'\n', rest) {
+                Some(end) => (&rest[..end], true),
+                None => (rest, false),
+            };
+            let raw = &rest[..line.len() + usize::from(complete)];
+            pos += raw.len();
+
+            if self.at_line_start && line.first() == Some(&b'>') {
+                self.end_record(false);
+                results.sequence_count += 1;
+                self.started = true;
+                self.in_header = true;
+                self.id.clear();
+                self.id.push(&line[1..]);
+                self.sinks.begin_record();
+                self.sinks.header_bytes(&raw[1..]);
+                if let Some(selector) = &mut self.selector {
+                    selector.begin_header();
+                    selector.header_bytes(&line[1..]);
+                }
+            } else if self.in_header {
+                self.id.push(line);
+                self.sinks.header_bytes(raw);
+                if let Some(selector) = &mut self.selector {
+                    selector.header_bytes(line);
+                }
+            } else {
+                self.sinks.sequence_bytes(raw, &[line.len()][..usize::from(complete)]);
+            }
+            if !self.in_header && self.started {
+                for &byte in line {
+                    let upper = byte.to_ascii_uppercase();
+                    if upper.is_ascii_uppercase() {
+                        let index = (upper - b'A') as usize;
+                        self.current_composition[index] += 1;
+                        self.current_weight += RESIDUE_MASS[index];
+                        self.current_length += 1;
+                    } else if byte == b'*' {
+                        self.current_stops += 1;
+                    }
+                }
+            }
+            if complete && self.in_header {
+                self.end_header();
+            }
+            self.at_line_start = complete;
+        }
+    }
+
+    fn end_header(&mut self) {
+        if let Some(selector) = &self.selector {
+            self.selected = selector.end_header(self.id.as_bytes());
+        }
+        self.in_header = false;
+    }
+
+    /// Ends the current record. `at_end` is set for the last one, which is left
+    /// out if it has no residues, as for a trailing header in FASTA.
+    fn end_record(&mut self, at_end: bool) {
+        if !self.started {
+            return;
+        }
+        let composition = std::mem::take(&mut self.current_composition);
+        let stops = std::mem::take(&mut self.current_stops);
+        let length = std::mem::take(&mut self.current_length);
+        let weight = std::mem::take(&mut self.current_weight);
+        let keep = self.selected && self.filter.keeps(length);
+        self.sinks.end_record(self.id.as_bytes(), keep);
+        if !keep {
+            self.filtered.add(length);
+            return;
+        }
+        if length == 0 && at_end {
+            return;
+        }
+        self.lengths.push(length);
+        for (total, count) in self.stats.composition.iter_mut().zip(composition) {
+            *total += count;
+        }
+        self.stats.stop_count += stops;
+        if length > 0 {
+            self.stats.add_record(weight + WATER_MASS);
+        }
+    }
+
+    pub fn finish(mut self, results: &mut AnalysisResults) {
+        if self.in_header {
+            self.end_header();
+        }
+        self.end_record(true);
+        results.sequence_count -= self.filtered.records;
+        let filtering = self.filter.is_active() || self.selector.is_some();
+        results.filtered = filtering.then_some(self.filtered);
+        self.sinks.finish(results);
+        results.protein = Some(self.stats);
+        results.calculate_stats(self.lengths);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detects_protein() {
+        assert!(looks_like_protein(
+            b">sp|P69905|HBA_HUMAN\nMVLSPADKTNVKAAWGKVGAHAGEYGAEALERMFLSFPTTKTYFPHF\n"
+        ));
+        assert!(!looks_like_protein(
+            b">chr1 Homo sapiens\nACGTNNNNacgtRYKMSWBDHV\n"
+        ));
+        assert!(!looks_like_protein(b">rna\nACGUACGUACGU\n"));
+        assert!(!looks_like_protein(b">only a header"));
+        assert!(!looks_like_protein(b"Some leading text\n>seq1\nATGC\n"));
+    }
+
+    #[test]
+    fn test_protein_records() {
+        let mut results = AnalysisResults::new("p.faa".to_string());
+        let mut parser = ProteinParser::new(&ProcessOptions::default());
+        let data = b">p1 GGGG in header\nGA\nX*\n>p2\n\n>p3\nwwXe\n";
+        // Feed byte by byte so every line is split across buffers.
+        for byte in data.chunks(1) {
+            parser.feed(byte, &mut results);
+        }
+        parser.finish(&mut results);
+
+        assert_eq!(results.sequence_count, 3);
+        assert_eq!(results.total_length, 7);
+        assert_eq!(results.largest_contig, 4);
+        assert_eq!(results.shortest_contig, 0);
+        assert_eq!(results.gc_count, 0);
+
+        let protein = results.protein.unwrap();
+        assert_eq!(protein.residues(), 7);
+        assert_eq!(protein.x_count(), 2);
+        assert_eq!(protein.stop_count, 1);
+        assert_eq!(protein.composition[(b'W' - b'A') as usize], 2);
+        assert_eq!(protein.weighed_records, 2);
+        let p1 = 57.0519 + 71.0788 + UNKNOWN_RESIDUE_MASS + WATER_MASS;
+        let p3 = 2.0 * 186.2132 + UNKNOWN_RESIDUE_MASS + 129.1155 + WATER_MASS;
+        assert!((protein.min_weight - p1).abs() < 1e-9);
+        assert!((protein.max_weight - p3).abs() < 1e-9);
+        assert!((protein.mean_weight() - (p1 + p3) / 2.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_protein_trailing_empty_record() {
+        let mut results = AnalysisResults::new("p.faa".to_string());
+        let mut parser = ProteinParser::new(&ProcessOptions::default());
+        parser.feed(b">p1\nMKV\n>p2\n\n>p3\nWW\n>trailing\n", &mut results);
+        parser.finish(&mut results);
+
+        // The empty record in the middle counts, the trailing header does not.
+        assert_eq!(results.sequence_count, 3);
+        assert_eq!(results.total_length, 5);
+        assert_eq!(results.shortest_contig, 0);
+
+        let mut results = AnalysisResults::new("p.faa".to_string());
+        let mut parser = ProteinParser::new(&ProcessOptions::default());
+        parser.feed(b">p1\nMKV\n>p2\nWW\n>trailing", &mut results);
+        parser.finish(&mut results);
+        assert_eq!(results.sequence_count, 2);
+        assert_eq!(results.shortest_contig, 2);
+    }
+
+    #[test]
+    fn test_protein_duplicate_ids() {
+        let options = ProcessOptions {
+            duplicate_ids: true,
+            ..Default::default()
+        };
+        let mut results = AnalysisResults::new("p.faa".to_string());
+        let mut parser = ProteinParser::new(&options);
+        let data = b">p1 first\nMK\n>p2\nMKV\n>p1 again\nM\n>p2";
+        for byte in data.chunks(1) {
+            parser.feed(byte, &mut results);
+        }
+        parser.finish(&mut results);
+        let duplicates = results.duplicate_ids.unwrap();
+        assert_eq!((duplicates.count, duplicates.examples), (2, vec!["p1".into(), "p2".into()]));
+
+        let mut results = AnalysisResults::new("p.faa".to_string());
+        let mut parser = ProteinParser::new(&ProcessOptions::default());
+        parser.feed(data, &mut results);
+        parser.finish(&mut results);
+        assert_eq!(results.duplicate_ids, None);
+    }
+}
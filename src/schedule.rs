@@ -0,0 +1,268 @@
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0>
+// at your option. This file may not be copied, modified,
+// or distributed except according to those terms.
+
+//! Size-aware scheduling of input files.
+//!
+//! Files are started largest first (longest-processing-time order), so a huge
+//! genome listed last no longer becomes a long tail after everything else is done.
+//! Work is estimated from the on-disk size scaled by the typical expansion of the
+//! file's compression format. Heavy decompressions buffer far more than plain
+//! files, so only a few of them may run at once; while the cap is reached, idle
+//! workers pick up lighter files instead.
+//!
+//! Decoders that start threads of their own split the pool's threads between the
+//! files that can be in flight together, so several BGZF files at once do not
+//! oversubscribe the CPU.
+
+use crate::process_files::FileFormat;
+use std::path::{Path, PathBuf};
+use std::sync::{Condvar, Mutex};
+
+/// Compressed inputs at least this large count as heavy decompressions.
+const HEAVY_FILE_SIZE: u64 = 256 * 1024 * 1024;
+
+/// Maximum number of heavy decompressions in flight at once.
+pub const MAX_HEAVY_DECOMPRESSIONS: usize = 2;
+
+/// Decoder threads one file may start out of `threads`: an even share between
+/// `files`, and at least one.
+fn share(threads: usize, files: usize) -> usize {
+    (threads / files.max(1)).max(1)
+}
+
+struct Job {
+    index: usize,
+    work: u64,
+    heavy: bool,
+}
+
+struct Queue {
+    pending: Vec<Job>,
+    running: usize,
+    heavy_running: usize,
+}
+
+/// Hands out jobs in descending order of estimated work, skipping heavy jobs
+/// while `heavy_limit` of them are running.
+struct Scheduler {
+    queue: Mutex<Queue>,
+    slot_freed: Condvar,
+    heavy_limit: usize,
+    /// Threads of the pool the jobs run on.
+    threads: usize,
+    /// Jobs that may run at once.
+    workers: usize,
+}
+
+/// A job handed out by `Scheduler::next`. It is running until dropped, also when
+/// dropped by a panic.
+struct Running<'a> {
+    scheduler: &'a Scheduler,
+    job: Job,
+    /// Decoder threads the job may start.
+    decoder_threads: usize,
+}
+
+impl Scheduler {
+    fn new(mut pending: Vec<Job>, heavy_limit: usize, threads: usize, workers: usize) -> Self {
+        pending.sort_by(|a, b| b.work.cmp(&a.work).then(a.index.cmp(&b.index)));
+        Self {
+            queue: Mutex::new(Queue {
+                pending,
+                running: 0,
+                heavy_running: 0,
+            }),
+            slot_freed: Condvar::new(),
+            heavy_limit: heavy_limit.max(1),
+            threads,
+            workers: workers.max(1),
+        }
+    }
+
+    fn next(&self) -> Option<Running<'_>> {
+        let mut queue = self.queue.lock().unwrap();
+        loop {
+            if queue.pending.is_empty() {
+                return None;
+            }
+            let heavy_allowed = queue.heavy_running < self.heavy_limit;
+            if let Some(pos) = queue.pending.iter().position(|j| !j.heavy || heavy_allowed) {
+                let job = queue.pending.remove(pos);
+                if job.heavy {
+                    queue.heavy_running += 1;
+                }
+                queue.running += 1;
+                // Share with every file that can run alongside this one, not only
+                // those already started, so the first file does not take the pool.
+                let alongside = (queue.running + queue.pending.len()).min(self.workers);
+                return Some(Running {
+                    scheduler: self,
+                    decoder_threads: share(self.threads, alongside),
+                    job,
+                });
+            }
+            queue = self.slot_freed.wait(queue).unwrap();
+        }
+    }
+}
+
+impl Drop for Running<'_> {
+    fn drop(&mut self) {
+        let mut queue = self
+            .scheduler
+            .queue
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        queue.running -= 1;
+        if self.job.heavy {
+            queue.heavy_running -= 1;
+            drop(queue);
+            self.scheduler.slot_freed.notify_all();
+        }
+    }
+}
+
+/// Estimated bytes of FASTA text a file expands to, and whether decoding it is heavy.
+fn estimate(path: &Path) -> (u64, bool) {
+    let size = std::fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+    let format = FileFormat::from_path(path);
+    let work = size.saturating_mul(format.expansion_factor());
+    let heavy = format != FileFormat::Fasta && size >= HEAVY_FILE_SIZE;
+    (work, heavy)
+}
+
+/// Runs `process` over `files` on `workers` threads, largest files first, and
+/// returns the outputs in input order. `process` is also given the decoder
+/// threads the file may start, its share of the `threads` of the pool.
+///
+/// The workers are plain threads that only pick files; `process` is expected to
+/// run the actual parsing on a rayon pool, so blocking here on the heavy-job cap
+/// never stalls a pool thread that nested parallel work depends on.
+pub fn run_largest_first<T, F>(
+    files: &[PathBuf],
+    threads: usize,
+    workers: usize,
+    heavy_limit: usize,
+    process: F,
+) -> Vec<T>
+where
+    T: Send + Default,
+    F: Fn(&Path, usize) -> T + Sync,
+{
+    let jobs = files
+        .iter()
+        .enumerate()
+        .map(|(index, path)| {
+            let (work, heavy) = estimate(path);
+            Job { index, work, heavy }
+        })
+        .collect();
+    let workers = workers.max(1).min(files.len());
+    let scheduler = Scheduler::new(jobs, heavy_limit, threads, workers);
+    let outputs: Vec<Mutex<T>> = files.iter().map(|_| Mutex::new(T::default())).collect();
+
+    std::thread::scope(|s| {
+        for _ in 0..workers {
+            s.spawn(|| {
+                while let Some(running) = scheduler.next() {
+                    let index = running.job.index;
+                    let output = process(&files[index], running.decoder_threads);
+                    *outputs[index].lock().unwrap() = output;
+                }
+            });
+        }
+    });
+
+    outputs
+        .into_iter()
+        .map(|o| o.into_inner().unwrap())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn job(index: usize, work: u64, heavy: bool) -> Job {
+        Job { index, work, heavy }
+    }
+
+    #[test]
+    fn test_jobs_start_largest_first() {
+        let jobs = vec![
+            job(0, 10, false),
+            job(1, 300, false),
+            job(2, 20, false),
+            job(3, 300, false),
+        ];
+        let scheduler = Scheduler::new(jobs, 1, 1, 1);
+        let order: Vec<usize> =
+            std::iter::from_fn(|| scheduler.next().map(|r| r.job.index)).collect();
+        assert_eq!(order, vec![1, 3, 2, 0]);
+    }
+
+    #[test]
+    fn test_heavy_jobs_are_capped() {
+        let jobs = vec![job(0, 500, true), job(1, 400, true), job(2, 10, false)];
+        let scheduler = Scheduler::new(jobs, 1, 1, 3);
+        let first = scheduler.next().unwrap();
+        assert_eq!(first.job.index, 0);
+        // The second heavy job must wait, so the light one is handed out instead.
+        assert_eq!(scheduler.next().unwrap().job.index, 2);
+        drop(first);
+        assert_eq!(scheduler.next().unwrap().job.index, 1);
+    }
+
+    #[test]
+    fn test_decoder_threads_are_shared() {
+        assert_eq!(share(8, 0), 8);
+        assert_eq!(share(8, 1), 8);
+        assert_eq!(share(8, 3), 2);
+        assert_eq!(share(2, 4), 1);
+    }
+
+    #[test]
+    fn test_first_job_gets_its_share_only() {
+        let jobs = (0..6).map(|i| job(i, 100 - i as u64, false)).collect();
+        let scheduler = Scheduler::new(jobs, 1, 8, 4);
+        let first = scheduler.next().unwrap();
+        assert_eq!(first.decoder_threads, 2);
+        let running: Vec<_> = (0..3).map(|_| scheduler.next().unwrap()).collect();
+        drop(running);
+        // The remaining two can only run alongside the first one.
+        let fifth = scheduler.next().unwrap();
+        assert_eq!(fifth.decoder_threads, 2);
+        drop(first);
+        assert_eq!(scheduler.next().unwrap().decoder_threads, 4);
+    }
+
+    #[test]
+    fn test_panicking_job_frees_its_slot() {
+        let jobs = vec![job(0, 500, true), job(1, 400, true)];
+        let scheduler = Scheduler::new(jobs, 1, 4, 2);
+        let panicked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let _running = scheduler.next().unwrap();
+            panic!("failed to process");
+        }));
+        assert!(panicked.is_err());
+        let next = scheduler.next().unwrap();
+        assert_eq!((next.job.index, next.decoder_threads), (1, 4));
+    }
+
+    #[test]
+    fn test_run_largest_first_keeps_input_order() {
+        let files: Vec<PathBuf> = (0..32)
+            .map(|i| PathBuf::from(format!("missing_{i}.fa")))
+            .collect();
+        let outputs = run_largest_first(&files, 4, 4, 1, |path, _| {
+            path.to_string_lossy().into_owned()
+        });
+        let expected: Vec<String> = files
+            .iter()
+            .map(|p| p.to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(outputs, expected);
+    }
+}
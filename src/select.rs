@@ -0,0 +1,134 @@
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0>
+// at your option. This file may not be copied, modified,
+// or distributed except according to those terms.
+
+//! Record selection by header, for `--include-ids`, `--exclude-ids` and
+//! `--header-regex`.
+//!
+//! IDs are matched on the header token up to the first whitespace, as in the
+//! duplicate ID check; the regex sees the whole header line after the `>` or `@`.
+//! Header text may arrive in several pieces, so it is collected first and tested
+//! once, when the header ends.
+
+use regex::bytes::Regex;
+use std::collections::HashSet;
+use std::io::{self, BufRead};
+use std::path::Path;
+use std::sync::Arc;
+
+/// Reads an ID list: the first token of each line, so a list of headers works
+/// too. Blank lines are skipped, and a leading `>` or `@` is dropped.
+pub fn read_ids(path: &Path) -> io::Result<HashSet<Vec<u8>>> {
+    let reader = io::BufReader::new(std::fs::File::open(path)?);
+    let mut ids = HashSet::new();
+    for line in reader.split(b'\n') {
+        let line = line?;
+        let line = line.strip_prefix(b">").or_else(|| line.strip_prefix(b"@")).unwrap_or(&line);
+        if let Some(id) = line.split(u8::is_ascii_whitespace).find(|token| !token.is_empty()) {
+            ids.insert(id.to_vec());
+        }
+    }
+    Ok(ids)
+}
+
+/// Which records count towards the statistics.
+#[derive(Debug, Default)]
+pub struct HeaderFilter {
+    /// Only these IDs are kept, when given.
+    pub include: Option<HashSet<Vec<u8>>>,
+    pub exclude: HashSet<Vec<u8>>,
+    /// The header must match, when given.
+    pub regex: Option<Regex>,
+}
+
+/// Regexes are compared by pattern.
+impl PartialEq for HeaderFilter {
+    fn eq(&self, other: &Self) -> bool {
+        self.include == other.include
+            && self.exclude == other.exclude
+            && self.regex.as_ref().map(Regex::as_str) == other.regex.as_ref().map(Regex::as_str)
+    }
+}
+
+impl Eq for HeaderFilter {}
+
+impl HeaderFilter {
+    /// Whether a record with this ID and header line is kept.
+    pub fn selects(&self, id: &[u8], header: &[u8]) -> bool {
+        self.include.as_ref().is_none_or(|include| include.contains(id))
+            && !self.exclude.contains(id)
+            && self.regex.as_ref().is_none_or(|regex| regex.is_match(header))
+    }
+}
+
+/// Collects one header at a time and tests it against a `HeaderFilter`.
+pub struct HeaderSelector {
+    filter: Arc<HeaderFilter>,
+    /// The full header, kept only when the regex needs it.
+    header: Vec<u8>,
+}
+
+impl HeaderSelector {
+    pub fn new(filter: Arc<HeaderFilter>) -> Self {
+        Self {
+            filter,
+            header: Vec::new(),
+        }
+    }
+
+    pub fn begin_header(&mut self) {
+        self.header.clear();
+    }
+
+    /// Adds header text following the `>` or `@`, or following earlier pieces.
+    pub fn header_bytes(&mut self, text: &[u8]) {
+        if self.filter.regex.is_some() {
+            self.header.extend_from_slice(text);
+        }
+    }
+
+    /// Whether the record whose header, with ID `id`, has just ended is kept.
+    pub fn end_header(&self, id: &[u8]) -> bool {
+        let header = self.header.strip_suffix(b"\r").unwrap_or(&self.header);
+        self.filter.selects(id, header)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ids::HeaderId;
+
+    #[test]
+    fn test_selects_split_headers() {
+        let mut path = std::env::temp_dir();
+        path.push("select_ids.txt");
+        std::fs::write(&path, ">chr1 first\n\nchr2\n  chrM\t mito\n").unwrap();
+        let ids = read_ids(&path).unwrap();
+        let _ = std::fs::remove_file(path);
+        assert_eq!(ids.len(), 3);
+
+        let filter = Arc::new(HeaderFilter {
+            include: Some(ids),
+            exclude: HashSet::from([b"chrM".to_vec()]),
+            regex: Some(Regex::new("^chr[0-9]+ ").unwrap()),
+        });
+        let mut selector = HeaderSelector::new(filter);
+        let mut id = HeaderId::default();
+        let mut select = |pieces: &[&[u8]]| {
+            selector.begin_header();
+            id.clear();
+            for piece in pieces {
+                selector.header_bytes(piece);
+                id.push(piece);
+            }
+            selector.end_header(id.as_bytes())
+        };
+        assert!(select(&[b"ch", b"r1 Homo", b" sapiens\r"]));
+        assert!(!select(&[b"chr2"]), "the regex needs a description");
+        assert!(!select(&[b"chrM mito"]));
+        assert!(!select(&[b"chr3 not listed"]));
+        assert!(!select(&[b"chr1_alt variant"]));
+    }
+}
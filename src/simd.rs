@@ -2,19 +2,35 @@ use wide::*;
 
 const LOOKUP: [u8; 256] = {
     let mut table = [0u8; 256];
-    // Bit 0: GC
+    // Bit 0: G
     // Bit 1: N
     // Bit 2: Skip (whitespace, gaps)
+    // Bit 3: T
+    // Bit 4: U
+    // Bit 5: Nucleotide (A, C, G, T, U, N)
+    // Bit 6: C
     
-    // GC
+    // G and C, apart for GC skew
     table[b'G' as usize] = 1;
     table[b'g' as usize] = 1;
-    table[b'C' as usize] = 1;
-    table[b'c' as usize] = 1;
+    table[b'C' as usize] = 64;
+    table[b'c' as usize] = 64;
     
     // N
     table[b'N' as usize] = 2;
     table[b'n' as usize] = 2;
+
+    // T and U tell DNA from RNA
+    table[b'T' as usize] = 8;
+    table[b't' as usize] = 8;
+    table[b'U' as usize] = 16;
+    table[b'u' as usize] = 16;
+
+    let mut i = 0;
+    while i < 12 {
+        table[b"ACGTUNacgtun"[i] as usize] |= 32;
+        i += 1;
+    }
     
     // Skip (whitespace and gaps)
     table[b' ' as usize] = 4;
@@ -27,31 +43,160 @@ const LOOKUP: [u8; 256] = {
     table
 };
 
-pub fn update_stats(line: &[u8], no_simd: bool) -> (usize, usize, usize) {
-    if no_simd {
-        return update_stats_scalar(line);
+/// A nucleotide-counting kernel. Every level produces identical counts; they only
+/// differ in how many bytes are compared per instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum SimdLevel {
+    /// Table lookup, one byte at a time.
+    Scalar,
+    /// `wide` vectors, sized by the target features the binary was compiled with.
+    Portable,
+    /// 16 bytes per step, on x86-64 CPUs with POPCNT.
+    Sse2,
+    /// 32 bytes per step.
+    Avx2,
+    /// 64 bytes per step using AVX-512BW mask registers.
+    Avx512,
+    /// 16 bytes per step on AArch64.
+    Neon,
+}
+
+impl SimdLevel {
+    /// The fastest kernel the running CPU supports.
+    pub fn detect() -> Self {
+        #[cfg(target_arch = "x86_64")]
+        {
+            // The x86 kernels count mask bits with POPCNT, which SSE2 alone does
+            // not guarantee.
+            if !std::arch::is_x86_feature_detected!("popcnt") {
+                return SimdLevel::Portable;
+            }
+            if std::arch::is_x86_feature_detected!("avx512bw") {
+                return SimdLevel::Avx512;
+            }
+            if std::arch::is_x86_feature_detected!("avx2") {
+                return SimdLevel::Avx2;
+            }
+            SimdLevel::Sse2
+        }
+        #[cfg(target_arch = "aarch64")]
+        {
+            if std::arch::is_aarch64_feature_detected!("neon") {
+                return SimdLevel::Neon;
+            }
+            SimdLevel::Portable
+        }
+        #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+        {
+            SimdLevel::Portable
+        }
+    }
+
+    /// Whether this kernel can run on the current CPU.
+    pub fn is_supported(self) -> bool {
+        match self {
+            SimdLevel::Scalar | SimdLevel::Portable => true,
+            #[cfg(target_arch = "x86_64")]
+            SimdLevel::Sse2 => std::arch::is_x86_feature_detected!("popcnt"),
+            #[cfg(target_arch = "x86_64")]
+            SimdLevel::Avx2 => {
+                std::arch::is_x86_feature_detected!("popcnt")
+                    && std::arch::is_x86_feature_detected!("avx2")
+            }
+            #[cfg(target_arch = "x86_64")]
+            SimdLevel::Avx512 => {
+                std::arch::is_x86_feature_detected!("popcnt")
+                    && std::arch::is_x86_feature_detected!("avx512bw")
+            }
+            #[cfg(target_arch = "aarch64")]
+            SimdLevel::Neon => std::arch::is_aarch64_feature_detected!("neon"),
+            #[allow(unreachable_patterns)]
+            _ => false,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            SimdLevel::Scalar => "scalar",
+            SimdLevel::Portable => "portable",
+            SimdLevel::Sse2 => "sse2",
+            SimdLevel::Avx2 => "avx2",
+            SimdLevel::Avx512 => "avx512",
+            SimdLevel::Neon => "neon",
+        }
+    }
+}
+
+/// Base composition of a run of sequence bytes. Besides GC and N, the T, U and
+/// nucleotide counts are enough to tell DNA from RNA from anything else. G and C
+/// are counted apart for the GC skew.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct BaseCounts {
+    pub g: usize,
+    pub c: usize,
+    pub n: usize,
+    /// Bytes that are not whitespace or gaps.
+    pub seq_chars: usize,
+    pub t: usize,
+    pub u: usize,
+    /// A, C, G, T, U or N in either case.
+    pub nucleotides: usize,
+}
+
+impl BaseCounts {
+    pub fn gc(&self) -> usize {
+        self.g + self.c
     }
+}
 
-    update_stats_simd(line)
+impl std::ops::AddAssign for BaseCounts {
+    fn add_assign(&mut self, other: Self) {
+        self.g += other.g;
+        self.c += other.c;
+        self.n += other.n;
+        self.seq_chars += other.seq_chars;
+        self.t += other.t;
+        self.u += other.u;
+        self.nucleotides += other.nucleotides;
+    }
+}
+
+/// Counts the base composition of `line` with the given kernel.
+/// `level` must be supported by the running CPU (see `SimdLevel::is_supported`).
+pub fn update_stats(line: &[u8], level: SimdLevel) -> BaseCounts {
+    match level {
+        SimdLevel::Scalar => update_stats_scalar(line),
+        SimdLevel::Portable => update_stats_portable(line),
+        #[cfg(target_arch = "x86_64")]
+        SimdLevel::Sse2 => unsafe { x86::update_stats_sse2(line) },
+        #[cfg(target_arch = "x86_64")]
+        SimdLevel::Avx2 => unsafe { x86::update_stats_avx2(line) },
+        #[cfg(target_arch = "x86_64")]
+        SimdLevel::Avx512 => unsafe { x86::update_stats_avx512(line) },
+        #[cfg(target_arch = "aarch64")]
+        SimdLevel::Neon => unsafe { neon::update_stats_neon(line) },
+        #[allow(unreachable_patterns)]
+        _ => update_stats_portable(line),
+    }
 }
 
-fn update_stats_scalar(line: &[u8]) -> (usize, usize, usize) {
-    let mut gc = 0;
-    let mut n = 0;
-    let mut seq_chars = 0;
+fn update_stats_scalar(line: &[u8]) -> BaseCounts {
+    let mut counts = BaseCounts::default();
     for &b in line {
         let val = LOOKUP[b as usize];
-        gc += (val & 1) as usize;
-        n += ((val & 2) >> 1) as usize;
-        seq_chars += (1 - (val >> 2)) as usize;
+        counts.g += (val & 1) as usize;
+        counts.c += ((val >> 6) & 1) as usize;
+        counts.n += ((val >> 1) & 1) as usize;
+        counts.seq_chars += (1 - ((val >> 2) & 1)) as usize;
+        counts.t += ((val >> 3) & 1) as usize;
+        counts.u += ((val >> 4) & 1) as usize;
+        counts.nucleotides += ((val >> 5) & 1) as usize;
     }
-    (gc, n, seq_chars)
+    counts
 }
 
-fn update_stats_simd(line: &[u8]) -> (usize, usize, usize) {
-    let mut gc_total = 0;
-    let mut n_total = 0;
-    let mut seq_chars_total = 0;
+fn update_stats_portable(line: &[u8]) -> BaseCounts {
+    let mut counts = BaseCounts::default();
 
     let mut chunks = line.chunks_exact(32);
 
@@ -59,6 +204,9 @@ fn update_stats_simd(line: &[u8]) -> (usize, usize, usize) {
     let v_g = u8x32::splat(b'g');
     let v_c = u8x32::splat(b'c');
     let v_n = u8x32::splat(b'n');
+    let v_a = u8x32::splat(b'a');
+    let v_t = u8x32::splat(b't');
+    let v_u = u8x32::splat(b'u');
     
     let v_space = u8x32::splat(b' ');
     let v_tab = u8x32::splat(b'\t');
@@ -76,11 +224,18 @@ fn update_stats_simd(line: &[u8]) -> (usize, usize, usize) {
         let is_c = v.simd_eq(v_c);
         let is_n = v.simd_eq(v_n);
         
+        let is_t = v.simd_eq(v_t);
+        let is_u = v.simd_eq(v_u);
+
         let is_gc = is_g | is_c;
-        let signed_gc: i8x32 = bytemuck::cast(is_gc);
-        let signed_n: i8x32 = bytemuck::cast(is_n);
-        gc_total += signed_gc.to_bitmask().count_ones() as usize;
-        n_total += signed_n.to_bitmask().count_ones() as usize;
+        let is_nucleotide = is_gc | is_n | is_t | is_u | v.simd_eq(v_a);
+        let count = |mask: u8x32| bytemuck::cast::<u8x32, i8x32>(mask).to_bitmask().count_ones() as usize;
+        counts.g += count(is_g);
+        counts.c += count(is_c);
+        counts.n += count(is_n);
+        counts.t += count(is_t);
+        counts.u += count(is_u);
+        counts.nucleotides += count(is_nucleotide);
 
         // Count skipped
         let s1 = v_chunk.simd_eq(v_space);
@@ -91,18 +246,558 @@ fn update_stats_simd(line: &[u8]) -> (usize, usize, usize) {
         let s6 = v_chunk.simd_eq(v_dot);
         
         let is_skipped = s1 | s2 | s3 | s4 | s5 | s6;
-        let signed_skipped: i8x32 = bytemuck::cast(is_skipped);
-        let skipped_count = signed_skipped.to_bitmask().count_ones() as usize;
-        seq_chars_total += 32 - skipped_count;
+        counts.seq_chars += 32 - count(is_skipped);
     }
 
     // Process tail
-    let (gc, n, sc) = update_stats_scalar(chunks.remainder());
-    gc_total += gc;
-    n_total += n;
-    seq_chars_total += sc;
+    counts += update_stats_scalar(chunks.remainder());
+    counts
+}
 
-    (gc_total, n_total, seq_chars_total)
+/// Result of scanning sequence bytes up to the next header.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SequenceScan {
+    /// Offset of the `\n` that directly precedes a `>`, if one was found.
+    pub header_newline: Option<usize>,
+    pub counts: BaseCounts,
+}
+
+/// Scans `data` for the first `\n>` and counts the base composition of the bytes
+/// before it, in a single pass. A `>` at offset 0 is not a boundary:
+/// whether it starts a header depends on the byte before `data`.
+///
+/// With `newlines`, the offsets of the `\n`s up to and including that of the
+/// `\n>` are appended to it, from the same pass.
+pub fn scan_sequence(
+    data: &[u8],
+    level: SimdLevel,
+    newlines: Option<&mut Vec<usize>>,
+) -> SequenceScan {
+    match level {
+        SimdLevel::Scalar => scan_two_pass(data, update_stats_scalar, newlines),
+        // `wide` cannot keep 64-bit masks out of LLVM's vector shuffles, which makes a
+        // fused loop slower than searching and counting separately.
+        SimdLevel::Portable => scan_two_pass(data, update_stats_portable, newlines),
+        #[cfg(target_arch = "x86_64")]
+        SimdLevel::Sse2 => unsafe { x86::scan_sequence_sse2(data, newlines) },
+        #[cfg(target_arch = "x86_64")]
+        SimdLevel::Avx2 => unsafe { x86::scan_sequence_avx2(data, newlines) },
+        #[cfg(target_arch = "x86_64")]
+        SimdLevel::Avx512 => unsafe { x86::scan_sequence_avx512(data, newlines) },
+        #[cfg(target_arch = "aarch64")]
+        SimdLevel::Neon => unsafe { neon::scan_sequence_neon(data, newlines) },
+        #[allow(unreachable_patterns)]
+        _ => scan_two_pass(data, update_stats_portable, newlines),
+    }
+}
+
+/// Two-pass reference: `memchr` for line ends, then a separate counting pass.
+fn scan_two_pass(
+    data: &[u8],
+    count: fn(&[u8]) -> BaseCounts,
+    mut newlines: Option<&mut Vec<usize>>,
+) -> SequenceScan {
+    let mut search_pos = 0;
+    let mut header_newline = None;
+    while let Some(pos) = memchr::memchr(b'\n', &data[search_pos..]) {
+        let actual_pos = search_pos + pos;
+        if let Some(newlines) = newlines.as_deref_mut() {
+            newlines.push(actual_pos);
+        }
+        if data.get(actual_pos + 1) == Some(&b'>') {
+            header_newline = Some(actual_pos);
+            break;
+        }
+        search_pos = actual_pos + 1;
+    }
+    SequenceScan {
+        header_newline,
+        counts: count(&data[..header_newline.unwrap_or(data.len())]),
+    }
+}
+
+/// One bit per byte of a 64-byte block, for each byte class the parser cares about.
+#[derive(Debug, Default, Clone, Copy)]
+struct BlockMasks {
+    g: u64,
+    c: u64,
+    n: u64,
+    skip: u64,
+    t: u64,
+    u: u64,
+    nucleotide: u64,
+    newline: u64,
+    header: u64,
+}
+
+/// Shared driver for the vectorized kernels. `classify` turns a 64-byte block into
+/// masks; header starts are `>` bits whose preceding bit (carried across blocks)
+/// is a newline, and counting stops at the first one.
+#[inline(always)]
+fn scan_blocks(
+    data: &[u8],
+    mut newlines: Option<&mut Vec<usize>>,
+    classify: impl Fn(&[u8]) -> BlockMasks,
+) -> SequenceScan {
+    let mut scan = SequenceScan::default();
+    let mut carry = 0u64;
+    let mut offset = 0;
+    while offset < data.len() {
+        let remaining = data.len() - offset;
+        let (masks, valid) = if remaining >= 64 {
+            (classify(&data[offset..offset + 64]), u64::MAX)
+        } else {
+            let mut padded = [0u8; 64];
+            padded[..remaining].copy_from_slice(&data[offset..]);
+            (classify(&padded), (1u64 << remaining) - 1)
+        };
+
+        let starts = masks.header & ((masks.newline << 1) | carry) & valid;
+        let keep = if starts != 0 {
+            (1u64 << starts.trailing_zeros()) - 1
+        } else {
+            valid
+        };
+        scan.counts += BaseCounts {
+            g: (masks.g & keep).count_ones() as usize,
+            c: (masks.c & keep).count_ones() as usize,
+            n: (masks.n & keep).count_ones() as usize,
+            seq_chars: (!masks.skip & keep).count_ones() as usize,
+            t: (masks.t & keep).count_ones() as usize,
+            u: (masks.u & keep).count_ones() as usize,
+            nucleotides: (masks.nucleotide & keep).count_ones() as usize,
+        };
+        if let Some(newlines) = newlines.as_deref_mut() {
+            // `keep` ends right after the newline of a `\n>`.
+            let mut bits = masks.newline & keep;
+            while bits != 0 {
+                newlines.push(offset + bits.trailing_zeros() as usize);
+                bits &= bits - 1;
+            }
+        }
+        if starts != 0 {
+            scan.header_newline = Some(offset + starts.trailing_zeros() as usize - 1);
+            return scan;
+        }
+        carry = masks.newline >> 63;
+        offset += 64;
+    }
+    scan
+}
+
+#[cfg(target_arch = "x86_64")]
+mod x86 {
+    use super::{BaseCounts, BlockMasks, SequenceScan, scan_blocks, update_stats_scalar};
+    use std::arch::x86_64::*;
+
+    #[target_feature(enable = "sse2")]
+    #[inline]
+    fn classify_sse2(block: &[u8]) -> BlockMasks {
+        let mut masks = BlockMasks::default();
+        let case_mask = _mm_set1_epi8(0x20);
+        for (quarter, bytes) in block.chunks_exact(16).enumerate() {
+            let raw = unsafe { _mm_loadu_si128(bytes.as_ptr().cast()) };
+            let v = _mm_or_si128(raw, case_mask);
+            macro_rules! eq {
+                ($x:expr, $b:expr) => {
+                    _mm_cmpeq_epi8($x, _mm_set1_epi8($b as i8))
+                };
+            }
+            macro_rules! bits {
+                ($m:expr) => {
+                    (_mm_movemask_epi8($m) as u16 as u64) << (quarter * 16)
+                };
+            }
+            let newline = eq!(raw, b'\n');
+            let (g, c) = (eq!(v, b'g'), eq!(v, b'c'));
+            let gc = _mm_or_si128(g, c);
+            let (n, t, u) = (eq!(v, b'n'), eq!(v, b't'), eq!(v, b'u'));
+            masks.g |= bits!(g);
+            masks.c |= bits!(c);
+            masks.n |= bits!(n);
+            masks.t |= bits!(t);
+            masks.u |= bits!(u);
+            masks.nucleotide |= bits!(_mm_or_si128(
+                _mm_or_si128(gc, n),
+                _mm_or_si128(_mm_or_si128(t, u), eq!(v, b'a')),
+            ));
+            masks.newline |= bits!(newline);
+            masks.header |= bits!(eq!(raw, b'>'));
+            masks.skip |= bits!(_mm_or_si128(
+                _mm_or_si128(newline, eq!(raw, b' ')),
+                _mm_or_si128(
+                    _mm_or_si128(eq!(raw, b'\t'), eq!(raw, b'\r')),
+                    _mm_or_si128(eq!(raw, b'-'), eq!(raw, b'.')),
+                ),
+            ));
+        }
+        masks
+    }
+
+    #[target_feature(enable = "avx2")]
+    #[inline]
+    fn classify_avx2(block: &[u8]) -> BlockMasks {
+        let mut masks = BlockMasks::default();
+        let case_mask = _mm256_set1_epi8(0x20);
+        for (half, bytes) in block.chunks_exact(32).enumerate() {
+            let raw = unsafe { _mm256_loadu_si256(bytes.as_ptr().cast()) };
+            let v = _mm256_or_si256(raw, case_mask);
+            // Macros rather than closures: closures are not inlined into AVX2 code.
+            macro_rules! eq {
+                ($x:expr, $b:expr) => {
+                    _mm256_cmpeq_epi8($x, _mm256_set1_epi8($b as i8))
+                };
+            }
+            macro_rules! bits {
+                ($m:expr) => {
+                    (_mm256_movemask_epi8($m) as u32 as u64) << (half * 32)
+                };
+            }
+            let newline = eq!(raw, b'\n');
+            let (g, c) = (eq!(v, b'g'), eq!(v, b'c'));
+            let gc = _mm256_or_si256(g, c);
+            let (n, t, u) = (eq!(v, b'n'), eq!(v, b't'), eq!(v, b'u'));
+            masks.g |= bits!(g);
+            masks.c |= bits!(c);
+            masks.n |= bits!(n);
+            masks.t |= bits!(t);
+            masks.u |= bits!(u);
+            masks.nucleotide |= bits!(_mm256_or_si256(
+                _mm256_or_si256(gc, n),
+                _mm256_or_si256(_mm256_or_si256(t, u), eq!(v, b'a')),
+            ));
+            masks.newline |= bits!(newline);
+            masks.header |= bits!(eq!(raw, b'>'));
+            masks.skip |= bits!(_mm256_or_si256(
+                _mm256_or_si256(newline, eq!(raw, b' ')),
+                _mm256_or_si256(
+                    _mm256_or_si256(eq!(raw, b'\t'), eq!(raw, b'\r')),
+                    _mm256_or_si256(eq!(raw, b'-'), eq!(raw, b'.')),
+                ),
+            ));
+        }
+        // Without AVX-512 mask registers LLVM rewrites the shifts in `scan_blocks` as
+        // byte shuffles of the comparison vectors and scalarizes them. Hiding the
+        // masks behind an empty asm block keeps them as plain integers.
+        for mask in [
+            &mut masks.g,
+            &mut masks.c,
+            &mut masks.n,
+            &mut masks.skip,
+            &mut masks.t,
+            &mut masks.u,
+            &mut masks.nucleotide,
+            &mut masks.newline,
+            &mut masks.header,
+        ] {
+            unsafe { std::arch::asm!("/* {0} */", inout(reg) *mask, options(pure, nomem, nostack)) };
+        }
+        masks
+    }
+
+    #[target_feature(enable = "avx512f,avx512bw")]
+    #[inline]
+    fn classify_avx512(block: &[u8]) -> BlockMasks {
+        let raw = unsafe { _mm512_loadu_si512(block.as_ptr().cast()) };
+        let v = _mm512_or_si512(raw, _mm512_set1_epi8(0x20));
+        macro_rules! eq {
+            ($x:expr, $b:expr) => {
+                _mm512_cmpeq_epi8_mask($x, _mm512_set1_epi8($b as i8))
+            };
+        }
+        let newline = eq!(raw, b'\n');
+        let (g, c) = (eq!(v, b'g'), eq!(v, b'c'));
+        let gc = g | c;
+        let (n, t, u) = (eq!(v, b'n'), eq!(v, b't'), eq!(v, b'u'));
+        BlockMasks {
+            g,
+            c,
+            n,
+            t,
+            u,
+            nucleotide: gc | n | t | u | eq!(v, b'a'),
+            skip: newline
+                | eq!(raw, b' ')
+                | eq!(raw, b'\t')
+                | eq!(raw, b'\r')
+                | eq!(raw, b'-')
+                | eq!(raw, b'.'),
+            newline,
+            header: eq!(raw, b'>'),
+        }
+    }
+
+    #[target_feature(enable = "sse2,popcnt")]
+    pub(super) fn scan_sequence_sse2(
+        data: &[u8],
+        newlines: Option<&mut Vec<usize>>,
+    ) -> SequenceScan {
+        scan_blocks(data, newlines, |block| classify_sse2(block))
+    }
+
+    #[target_feature(enable = "avx2,popcnt")]
+    pub(super) fn scan_sequence_avx2(
+        data: &[u8],
+        newlines: Option<&mut Vec<usize>>,
+    ) -> SequenceScan {
+        scan_blocks(data, newlines, |block| classify_avx2(block))
+    }
+
+    #[target_feature(enable = "avx512f,avx512bw,popcnt")]
+    pub(super) fn scan_sequence_avx512(
+        data: &[u8],
+        newlines: Option<&mut Vec<usize>>,
+    ) -> SequenceScan {
+        scan_blocks(data, newlines, |block| classify_avx512(block))
+    }
+
+    #[target_feature(enable = "sse2,popcnt")]
+    pub(super) unsafe fn update_stats_sse2(line: &[u8]) -> BaseCounts {
+        let mut counts = BaseCounts::default();
+        let mut chunks = line.chunks_exact(16);
+        let case_mask = _mm_set1_epi8(0x20);
+        for chunk in chunks.by_ref() {
+            let raw = unsafe { _mm_loadu_si128(chunk.as_ptr().cast()) };
+            let v = _mm_or_si128(raw, case_mask);
+            macro_rules! eq {
+                ($x:expr, $b:expr) => {
+                    _mm_cmpeq_epi8($x, _mm_set1_epi8($b as i8))
+                };
+            }
+            macro_rules! count {
+                ($m:expr) => {
+                    _mm_movemask_epi8($m).count_ones() as usize
+                };
+            }
+            let (is_g, is_c) = (eq!(v, b'g'), eq!(v, b'c'));
+            let is_gc = _mm_or_si128(is_g, is_c);
+            let (is_n, is_t, is_u) = (eq!(v, b'n'), eq!(v, b't'), eq!(v, b'u'));
+            let is_nucleotide = _mm_or_si128(
+                _mm_or_si128(is_gc, is_n),
+                _mm_or_si128(_mm_or_si128(is_t, is_u), eq!(v, b'a')),
+            );
+            let is_skipped = _mm_or_si128(
+                _mm_or_si128(eq!(raw, b' '), eq!(raw, b'\t')),
+                _mm_or_si128(
+                    _mm_or_si128(eq!(raw, b'\n'), eq!(raw, b'\r')),
+                    _mm_or_si128(eq!(raw, b'-'), eq!(raw, b'.')),
+                ),
+            );
+            counts.g += count!(is_g);
+            counts.c += count!(is_c);
+            counts.n += count!(is_n);
+            counts.t += count!(is_t);
+            counts.u += count!(is_u);
+            counts.nucleotides += count!(is_nucleotide);
+            counts.seq_chars += 16 - count!(is_skipped);
+        }
+        counts += update_stats_scalar(chunks.remainder());
+        counts
+    }
+
+    #[target_feature(enable = "avx2,popcnt")]
+    pub(super) unsafe fn update_stats_avx2(line: &[u8]) -> BaseCounts {
+        let mut counts = BaseCounts::default();
+        let mut chunks = line.chunks_exact(32);
+        let case_mask = _mm256_set1_epi8(0x20);
+        for chunk in chunks.by_ref() {
+            let raw = unsafe { _mm256_loadu_si256(chunk.as_ptr().cast()) };
+            let v = _mm256_or_si256(raw, case_mask);
+            macro_rules! eq {
+                ($x:expr, $b:expr) => {
+                    _mm256_cmpeq_epi8($x, _mm256_set1_epi8($b as i8))
+                };
+            }
+            macro_rules! count {
+                ($m:expr) => {
+                    _mm256_movemask_epi8($m).count_ones() as usize
+                };
+            }
+            let (is_g, is_c) = (eq!(v, b'g'), eq!(v, b'c'));
+            let is_gc = _mm256_or_si256(is_g, is_c);
+            let (is_n, is_t, is_u) = (eq!(v, b'n'), eq!(v, b't'), eq!(v, b'u'));
+            let is_nucleotide = _mm256_or_si256(
+                _mm256_or_si256(is_gc, is_n),
+                _mm256_or_si256(_mm256_or_si256(is_t, is_u), eq!(v, b'a')),
+            );
+            let is_skipped = _mm256_or_si256(
+                _mm256_or_si256(eq!(raw, b' '), eq!(raw, b'\t')),
+                _mm256_or_si256(
+                    _mm256_or_si256(eq!(raw, b'\n'), eq!(raw, b'\r')),
+                    _mm256_or_si256(eq!(raw, b'-'), eq!(raw, b'.')),
+                ),
+            );
+            counts.g += count!(is_g);
+            counts.c += count!(is_c);
+            counts.n += count!(is_n);
+            counts.t += count!(is_t);
+            counts.u += count!(is_u);
+            counts.nucleotides += count!(is_nucleotide);
+            counts.seq_chars += 32 - count!(is_skipped);
+        }
+        counts += update_stats_scalar(chunks.remainder());
+        counts
+    }
+
+    #[target_feature(enable = "avx512f,avx512bw,popcnt")]
+    pub(super) unsafe fn update_stats_avx512(line: &[u8]) -> BaseCounts {
+        let mut counts = BaseCounts::default();
+        let mut chunks = line.chunks_exact(64);
+        let case_mask = _mm512_set1_epi8(0x20);
+        for chunk in chunks.by_ref() {
+            let raw = unsafe { _mm512_loadu_si512(chunk.as_ptr().cast()) };
+            let v = _mm512_or_si512(raw, case_mask);
+            macro_rules! eq {
+                ($x:expr, $b:expr) => {
+                    _mm512_cmpeq_epi8_mask($x, _mm512_set1_epi8($b as i8))
+                };
+            }
+            let (is_g, is_c) = (eq!(v, b'g'), eq!(v, b'c'));
+            let is_gc = is_g | is_c;
+            let (is_n, is_t, is_u) = (eq!(v, b'n'), eq!(v, b't'), eq!(v, b'u'));
+            let is_nucleotide = is_gc | is_n | is_t | is_u | eq!(v, b'a');
+            let is_skipped = eq!(raw, b' ')
+                | eq!(raw, b'\t')
+                | eq!(raw, b'\n')
+                | eq!(raw, b'\r')
+                | eq!(raw, b'-')
+                | eq!(raw, b'.');
+            counts.g += is_g.count_ones() as usize;
+            counts.c += is_c.count_ones() as usize;
+            counts.n += is_n.count_ones() as usize;
+            counts.t += is_t.count_ones() as usize;
+            counts.u += is_u.count_ones() as usize;
+            counts.nucleotides += is_nucleotide.count_ones() as usize;
+            counts.seq_chars += 64 - is_skipped.count_ones() as usize;
+        }
+        counts += update_stats_scalar(chunks.remainder());
+        counts
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+mod neon {
+    use super::{BaseCounts, BlockMasks, SequenceScan, scan_blocks, update_stats_scalar};
+    use std::arch::aarch64::*;
+
+    /// Packs four 16-lane comparison results into one 64-bit mask.
+    #[target_feature(enable = "neon")]
+    #[inline]
+    fn to_bitmask(m: [uint8x16_t; 4]) -> u64 {
+        let weights: [u8; 16] = [1, 2, 4, 8, 16, 32, 64, 128, 1, 2, 4, 8, 16, 32, 64, 128];
+        let weights = unsafe { vld1q_u8(weights.as_ptr()) };
+        let sum01 = vpaddq_u8(vandq_u8(m[0], weights), vandq_u8(m[1], weights));
+        let sum23 = vpaddq_u8(vandq_u8(m[2], weights), vandq_u8(m[3], weights));
+        let sum = vpaddq_u8(sum01, sum23);
+        let sum = vpaddq_u8(sum, sum);
+        vgetq_lane_u64::<0>(vreinterpretq_u64_u8(sum))
+    }
+
+    #[target_feature(enable = "neon")]
+    #[inline]
+    fn classify_neon(block: &[u8]) -> BlockMasks {
+        let case_mask = vdupq_n_u8(0x20);
+        let mut g = [vdupq_n_u8(0); 4];
+        let mut c = [vdupq_n_u8(0); 4];
+        let mut n = [vdupq_n_u8(0); 4];
+        let mut t = [vdupq_n_u8(0); 4];
+        let mut u = [vdupq_n_u8(0); 4];
+        let mut nucleotide = [vdupq_n_u8(0); 4];
+        let mut skip = [vdupq_n_u8(0); 4];
+        let mut newline = [vdupq_n_u8(0); 4];
+        let mut header = [vdupq_n_u8(0); 4];
+        for (i, bytes) in block.chunks_exact(16).enumerate() {
+            let raw = unsafe { vld1q_u8(bytes.as_ptr()) };
+            let v = vorrq_u8(raw, case_mask);
+            macro_rules! eq {
+                ($x:expr, $b:expr) => {
+                    vceqq_u8($x, vdupq_n_u8($b))
+                };
+            }
+            g[i] = eq!(v, b'g');
+            c[i] = eq!(v, b'c');
+            n[i] = eq!(v, b'n');
+            t[i] = eq!(v, b't');
+            u[i] = eq!(v, b'u');
+            nucleotide[i] = vorrq_u8(
+                vorrq_u8(vorrq_u8(g[i], c[i]), n[i]),
+                vorrq_u8(vorrq_u8(t[i], u[i]), eq!(v, b'a')),
+            );
+            newline[i] = eq!(raw, b'\n');
+            header[i] = eq!(raw, b'>');
+            skip[i] = vorrq_u8(
+                vorrq_u8(newline[i], eq!(raw, b' ')),
+                vorrq_u8(
+                    vorrq_u8(eq!(raw, b'\t'), eq!(raw, b'\r')),
+                    vorrq_u8(eq!(raw, b'-'), eq!(raw, b'.')),
+                ),
+            );
+        }
+        BlockMasks {
+            g: to_bitmask(g),
+            c: to_bitmask(c),
+            n: to_bitmask(n),
+            skip: to_bitmask(skip),
+            t: to_bitmask(t),
+            u: to_bitmask(u),
+            nucleotide: to_bitmask(nucleotide),
+            newline: to_bitmask(newline),
+            header: to_bitmask(header),
+        }
+    }
+
+    #[target_feature(enable = "neon")]
+    pub(super) fn scan_sequence_neon(
+        data: &[u8],
+        newlines: Option<&mut Vec<usize>>,
+    ) -> SequenceScan {
+        scan_blocks(data, newlines, |block| classify_neon(block))
+    }
+
+    #[target_feature(enable = "neon")]
+    pub(super) unsafe fn update_stats_neon(line: &[u8]) -> BaseCounts {
+        let mut counts = BaseCounts::default();
+        let mut chunks = line.chunks_exact(16);
+        let case_mask = vdupq_n_u8(0x20);
+        for chunk in chunks.by_ref() {
+            let raw = unsafe { vld1q_u8(chunk.as_ptr()) };
+            let v = vorrq_u8(raw, case_mask);
+            macro_rules! eq {
+                ($x:expr, $b:expr) => {
+                    vceqq_u8($x, vdupq_n_u8($b))
+                };
+            }
+            // Matching lanes are 0xFF; shifting leaves a 1 per match to sum across lanes.
+            macro_rules! count {
+                ($m:expr) => {
+                    vaddvq_u8(vshrq_n_u8::<7>($m)) as usize
+                };
+            }
+            let (is_g, is_c) = (eq!(v, b'g'), eq!(v, b'c'));
+            let is_gc = vorrq_u8(is_g, is_c);
+            let (is_n, is_t, is_u) = (eq!(v, b'n'), eq!(v, b't'), eq!(v, b'u'));
+            let is_nucleotide = vorrq_u8(
+                vorrq_u8(is_gc, is_n),
+                vorrq_u8(vorrq_u8(is_t, is_u), eq!(v, b'a')),
+            );
+            let is_skipped = vorrq_u8(
+                vorrq_u8(eq!(raw, b' '), eq!(raw, b'\t')),
+                vorrq_u8(
+                    vorrq_u8(eq!(raw, b'\n'), eq!(raw, b'\r')),
+                    vorrq_u8(eq!(raw, b'-'), eq!(raw, b'.')),
+                ),
+            );
+            counts.g += count!(is_g);
+            counts.c += count!(is_c);
+            counts.n += count!(is_n);
+            counts.t += count!(is_t);
+            counts.u += count!(is_u);
+            counts.nucleotides += count!(is_nucleotide);
+            counts.seq_chars += 16 - count!(is_skipped);
+        }
+        counts += update_stats_scalar(chunks.remainder());
+        counts
+    }
 }
 
 #[cfg(test)]
@@ -133,15 +828,164 @@ mod tests {
         }
     }
 
+    const ALL_LEVELS: [SimdLevel; 6] = [
+        SimdLevel::Scalar,
+        SimdLevel::Portable,
+        SimdLevel::Sse2,
+        SimdLevel::Avx2,
+        SimdLevel::Avx512,
+        SimdLevel::Neon,
+    ];
+
     fn check_consistency(input: &[u8]) {
         let scalar_res = update_stats_scalar(input);
-        // Test normal dispatch
-        let dispatch_res = update_stats(input, false);
-        assert_eq!(scalar_res, dispatch_res, "Dispatched (SIMD enabled) result should match scalar result for len {}", input.len());
-        
-        // Test no_simd flag
-        let no_simd_res = update_stats(input, true);
-        assert_eq!(scalar_res, no_simd_res, "Dispatched (SIMD disabled) result should match scalar result for len {}", input.len());
+        // Every kernel this CPU can run must agree with the lookup table
+        for level in ALL_LEVELS.into_iter().filter(|l| l.is_supported()) {
+            let res = update_stats(input, level);
+            assert_eq!(scalar_res, res, "{} kernel result should match scalar result for len {}", level.name(), input.len());
+        }
+    }
+
+    fn scan_two_pass_scalar(input: &[u8]) -> SequenceScan {
+        scan_two_pass(input, update_stats_scalar, None)
+    }
+
+    fn check_scan_consistency(input: &[u8]) {
+        let reference = scan_two_pass_scalar(input);
+        let end = reference.header_newline.map_or(input.len(), |pos| pos + 1);
+        let expected: Vec<usize> = memchr::memchr_iter(b'\n', &input[..end]).collect();
+        for level in ALL_LEVELS.into_iter().filter(|l| l.is_supported()) {
+            assert_eq!(reference, scan_sequence(input, level, None), "{} scan for len {}", level.name(), input.len());
+            let mut newlines = vec![usize::MAX];
+            assert_eq!(reference, scan_sequence(input, level, Some(&mut newlines)));
+            assert_eq!(newlines[1..], expected, "{} newlines for len {}", level.name(), input.len());
+        }
+    }
+
+    #[test]
+    fn test_scan_sequence_finds_header_boundaries() {
+        let scan = scan_two_pass_scalar(b"ACGN\nGG\n>next\nAAAA");
+        assert_eq!(scan.header_newline, Some(7));
+        assert_eq!((scan.counts.g, scan.counts.c, scan.counts.n, scan.counts.seq_chars), (3, 1, 1, 6));
+
+        // A leading '>' is not a boundary, and neither is '>' mid-line.
+        let scan = scan_two_pass_scalar(b">AC>G\n");
+        assert_eq!(scan.header_newline, None);
+
+        // Boundaries on every offset around the 64-byte block edges.
+        for pos in 0..200 {
+            let mut buf = vec![b'G'; 256];
+            buf[pos] = b'\n';
+            buf[pos + 1] = b'>';
+            check_scan_consistency(&buf);
+            check_scan_consistency(&buf[..pos + 1]);
+            check_scan_consistency(&buf[..pos + 2]);
+        }
+    }
+
+    /// Throughput of `scan_sequence` against the two-pass loop it replaced (`memchr`
+    /// for `\n>`, then `update_stats` over the bytes before it), both driven over
+    /// the same records of the same buffer. Run with
+    /// `cargo test --release -- --ignored bench_fused_scan --nocapture`.
+    #[test]
+    #[ignore]
+    fn bench_fused_scan() {
+        use std::time::Instant;
+
+        fn two_pass(data: &[u8], level: SimdLevel) -> SequenceScan {
+            let mut search_pos = 0;
+            let mut header_newline = None;
+            while let Some(pos) = memchr::memchr(b'\n', &data[search_pos..]) {
+                let actual_pos = search_pos + pos;
+                if data.get(actual_pos + 1) == Some(&b'>') {
+                    header_newline = Some(actual_pos);
+                    break;
+                }
+                search_pos = actual_pos + 1;
+            }
+            let counts = update_stats(&data[..header_newline.unwrap_or(data.len())], level);
+            SequenceScan { header_newline, counts }
+        }
+
+        /// Counts of every record, skipping the headers as the parser does.
+        fn drive(data: &[u8], scan: impl Fn(&[u8]) -> SequenceScan) -> BaseCounts {
+            let mut total = BaseCounts::default();
+            let mut pos = 0;
+            while pos < data.len() {
+                if data[pos] == b'>' {
+                    pos += memchr::memchr(b'\n', &data[pos..]).map_or(data.len() - pos, |end| end + 1);
+                    continue;
+                }
+                let result = scan(&data[pos..]);
+                total += result.counts;
+                pos += result.header_newline.map_or(data.len() - pos, |end| end + 1);
+            }
+            total
+        }
+
+        let sample = std::fs::read("test/humantest.fna").unwrap();
+        let body = &sample[memchr::memchr(b'\n', &sample).unwrap() + 1..];
+        let mut data = Vec::with_capacity(256 * 1024 * 1024);
+        let mut copy = 0;
+        while data.len() < 256 * 1024 * 1024 {
+            data.extend_from_slice(format!(">copy{copy}\n").as_bytes());
+            data.extend_from_slice(body);
+            copy += 1;
+        }
+        let gib = data.len() as f64 / (1024.0 * 1024.0 * 1024.0);
+
+        for level in ALL_LEVELS.into_iter().filter(|l| l.is_supported()) {
+            // Best of five runs of each, to keep scheduler noise out of the ratio
+            let (mut two_pass_secs, mut fused_secs) = (f64::MAX, f64::MAX);
+            let (mut expected, mut counts) = (BaseCounts::default(), BaseCounts::default());
+            for _ in 0..5 {
+                let start = Instant::now();
+                expected = drive(&data, |d| two_pass(d, level));
+                two_pass_secs = two_pass_secs.min(start.elapsed().as_secs_f64());
+
+                let start = Instant::now();
+                counts = drive(&data, |d| scan_sequence(d, level, None));
+                fused_secs = fused_secs.min(start.elapsed().as_secs_f64());
+            }
+
+            assert_eq!(counts, expected, "{}", level.name());
+            println!(
+                "{:>8} kernel, {:.2} GiB: two-pass {:.2} GiB/s, fused {:.2} GiB/s ({:.2}x)",
+                level.name(),
+                gib,
+                gib / two_pass_secs,
+                gib / fused_secs,
+                two_pass_secs / fused_secs
+            );
+        }
+    }
+
+    #[test]
+    fn test_fuzz_scan_sequence() {
+        let mut rng = SimpleRng::new(4242);
+        for _ in 0..300 {
+            let len = (rng.next_u8() as usize) * 8 + (rng.next_u8() as usize);
+            let mut buf = vec![0u8; len];
+            rng.fill_bytes(&mut buf);
+            for b in buf.iter_mut() {
+                *b = match *b % 12 {
+                    0..=2 => b'\n',
+                    3 => b'>',
+                    4 => b'G',
+                    5 => b'n',
+                    6 => b'c',
+                    7 => b'-',
+                    _ => b'A',
+                };
+            }
+            check_scan_consistency(&buf);
+        }
+    }
+
+    #[test]
+    fn test_detected_level_is_supported() {
+        assert!(SimdLevel::detect().is_supported());
+        assert!(SimdLevel::Scalar.is_supported());
     }
 
     #[test]
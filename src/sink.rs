@@ -0,0 +1,226 @@
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0>
+// at your option. This file may not be copied, modified,
+// or distributed except according to those terms.
+
+//! Per-record side outputs of the FASTA parsers.
+//!
+//! Duplicate IDs and sequences, checksums, the `.fai` index and the GC track are
+//! each built by a sink fed the bytes of every record. The parser finds the record
+//! boundaries and reads the ID once, and hands both to all of its sinks in turn.
+
+use crate::checksum::ChecksumBuilder;
+use crate::duplicates::SequenceDigests;
+use crate::fai::FaiBuilder;
+use crate::gc_track::GcTrackBuilder;
+use crate::ids::IdTracker;
+use crate::process_files::AnalysisResults;
+use std::any::Any;
+
+/// Receives the records of one input, or of one chunk of it, in order. Header and
+/// sequence bytes may arrive in pieces of any size.
+pub trait RecordSink: Any + Send {
+    /// Starts a record at its `>`.
+    fn begin_record(&mut self);
+
+    /// Header bytes following the `>`, including the newline that ends them.
+    fn header_bytes(&mut self, _bytes: &[u8]) {}
+
+    /// Sequence bytes, line breaks included. Bytes before the first header come
+    /// here too, outside of any record. `line_ends` holds the offset of each `\n`
+    /// in `bytes` if the sink wants them, and is empty otherwise.
+    fn sequence_bytes(&mut self, bytes: &[u8], line_ends: &[usize]);
+
+    /// Whether `sequence_bytes` needs the offsets of the line breaks. The parser
+    /// finds them in the pass that counts the bases.
+    fn wants_line_ends(&self) -> bool {
+        false
+    }
+
+    /// Ends the open record. `id` is its header up to the first whitespace and
+    /// `keep` whether it passed the length and header filters.
+    fn end_record(&mut self, id: &[u8], keep: bool);
+
+    /// Appends a sink of the same kind that read the input following this one's,
+    /// starting at a record boundary. This sink's last record must have been
+    /// ended; the open record of `next`, if any, stays open.
+    fn append(&mut self, next: Box<dyn RecordSink>);
+
+    /// Stores what was built in `results`.
+    fn finish(self: Box<Self>, results: &mut AnalysisResults);
+}
+
+/// The sink `next` of the same kind as the one it is appended to.
+fn same_kind<T: RecordSink>(next: Box<dyn RecordSink>) -> T {
+    let next: Box<dyn Any> = next;
+    *next
+        .downcast()
+        .expect("sinks are appended to sinks of the same kind")
+}
+
+/// The sinks of one parser, fed together.
+#[derive(Default)]
+pub struct RecordSinks(Vec<Box<dyn RecordSink>>);
+
+impl RecordSinks {
+    pub fn push(&mut self, sink: impl RecordSink) {
+        self.0.push(Box::new(sink));
+    }
+
+    pub fn begin_record(&mut self) {
+        for sink in &mut self.0 {
+            sink.begin_record();
+        }
+    }
+
+    pub fn header_bytes(&mut self, bytes: &[u8]) {
+        for sink in &mut self.0 {
+            sink.header_bytes(bytes);
+        }
+    }
+
+    pub fn sequence_bytes(&mut self, bytes: &[u8], line_ends: &[usize]) {
+        for sink in &mut self.0 {
+            sink.sequence_bytes(bytes, line_ends);
+        }
+    }
+
+    pub fn wants_line_ends(&self) -> bool {
+        self.0.iter().any(|sink| sink.wants_line_ends())
+    }
+
+    pub fn end_record(&mut self, id: &[u8], keep: bool) {
+        for sink in &mut self.0 {
+            sink.end_record(id, keep);
+        }
+    }
+
+    /// Appends the sinks of a parser built with the same options.
+    pub fn append(&mut self, next: RecordSinks) {
+        for (sink, next) in self.0.iter_mut().zip(next.0) {
+            sink.append(next);
+        }
+    }
+
+    pub fn finish(self, results: &mut AnalysisResults) {
+        for sink in self.0 {
+            sink.finish(results);
+        }
+    }
+}
+
+impl RecordSink for IdTracker {
+    fn begin_record(&mut self) {}
+
+    fn sequence_bytes(&mut self, _bytes: &[u8], _line_ends: &[usize]) {}
+
+    fn end_record(&mut self, id: &[u8], keep: bool) {
+        if keep {
+            self.insert(id);
+        }
+    }
+
+    fn append(&mut self, _next: Box<dyn RecordSink>) {
+        unreachable!("IDs are tracked over whole inputs only");
+    }
+
+    fn finish(self: Box<Self>, results: &mut AnalysisResults) {
+        results.duplicate_ids = Some(IdTracker::finish(*self));
+    }
+}
+
+impl RecordSink for SequenceDigests {
+    fn begin_record(&mut self) {
+        SequenceDigests::begin_record(self);
+    }
+
+    fn sequence_bytes(&mut self, bytes: &[u8], _line_ends: &[usize]) {
+        SequenceDigests::sequence_bytes(self, bytes);
+    }
+
+    fn end_record(&mut self, id: &[u8], keep: bool) {
+        SequenceDigests::end_record(self, id, keep);
+    }
+
+    fn append(&mut self, next: Box<dyn RecordSink>) {
+        SequenceDigests::append(self, same_kind(next));
+    }
+
+    fn finish(self: Box<Self>, results: &mut AnalysisResults) {
+        results.duplicate_sequences = Some(SequenceDigests::finish(*self));
+    }
+}
+
+impl RecordSink for ChecksumBuilder {
+    fn begin_record(&mut self) {
+        ChecksumBuilder::begin_record(self);
+    }
+
+    fn sequence_bytes(&mut self, bytes: &[u8], _line_ends: &[usize]) {
+        ChecksumBuilder::sequence_bytes(self, bytes);
+    }
+
+    fn end_record(&mut self, id: &[u8], keep: bool) {
+        ChecksumBuilder::end_record(self, id, keep);
+    }
+
+    fn append(&mut self, next: Box<dyn RecordSink>) {
+        ChecksumBuilder::append(self, same_kind(next));
+    }
+
+    fn finish(self: Box<Self>, results: &mut AnalysisResults) {
+        results.checksums = Some(ChecksumBuilder::finish(*self));
+    }
+}
+
+impl RecordSink for FaiBuilder {
+    fn begin_record(&mut self) {
+        FaiBuilder::begin_record(self);
+    }
+
+    fn header_bytes(&mut self, bytes: &[u8]) {
+        FaiBuilder::header_bytes(self, bytes);
+    }
+
+    fn sequence_bytes(&mut self, bytes: &[u8], line_ends: &[usize]) {
+        FaiBuilder::sequence_bytes(self, bytes, line_ends);
+    }
+
+    fn wants_line_ends(&self) -> bool {
+        true
+    }
+
+    fn end_record(&mut self, id: &[u8], keep: bool) {
+        FaiBuilder::end_record(self, id, keep);
+    }
+
+    fn append(&mut self, next: Box<dyn RecordSink>) {
+        FaiBuilder::append(self, same_kind(next));
+    }
+
+    fn finish(self: Box<Self>, results: &mut AnalysisResults) {
+        results.fai = Some(FaiBuilder::finish(*self));
+    }
+}
+
+impl RecordSink for GcTrackBuilder {
+    fn begin_record(&mut self) {
+        GcTrackBuilder::begin_record(self);
+    }
+
+    fn sequence_bytes(&mut self, bytes: &[u8], _line_ends: &[usize]) {
+        GcTrackBuilder::sequence_bytes(self, bytes);
+    }
+
+    fn end_record(&mut self, id: &[u8], keep: bool) {
+        GcTrackBuilder::end_record(self, id, keep);
+    }
+
+    fn append(&mut self, next: Box<dyn RecordSink>) {
+        GcTrackBuilder::append(self, same_kind(next));
+    }
+
+    fn finish(self: Box<Self>, results: &mut AnalysisResults) {
+        results.gc_track = Some(GcTrackBuilder::finish(*self));
+    }
+}
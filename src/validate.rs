@@ -0,0 +1,350 @@
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0>
+// at your option. This file may not be copied, modified,
+// or distributed except according to those terms.
+
+//! Strict FASTA validation for `--validate`.
+//!
+//! The statistics parsers are deliberately lenient: text before the first header
+//! is skipped, any byte counts towards a sequence and empty records are accepted.
+//! The validator runs alongside them over the same decoded bytes and records every
+//! problem with its line and byte offset, so a broken file can be fixed instead of
+//! silently producing odd numbers.
+
+use std::collections::HashMap;
+use std::fmt;
+
+/// Issues kept per file. Anything beyond is only counted.
+pub const MAX_REPORTED_ISSUES: usize = 100;
+
+/// Bytes allowed in a sequence line, for nucleotides (IUPAC codes) and protein.
+const fn allowed_table(letters: &[u8]) -> [bool; 256] {
+    let mut table = [false; 256];
+    let mut i = 0;
+    while i < letters.len() {
+        table[letters[i] as usize] = true;
+        table[letters[i].to_ascii_lowercase() as usize] = true;
+        i += 1;
+    }
+    table[b'-' as usize] = true;
+    table[b'.' as usize] = true;
+    table
+}
+
+const NUCLEOTIDE_BYTES: [bool; 256] = allowed_table(b"ACGTURYSWKMBDHVN");
+const PROTEIN_BYTES: [bool; 256] = allowed_table(b"ABCDEFGHIJKLMNOPQRSTUVWXYZ*");
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IssueKind {
+    InvalidCharacter(u8),
+    EmptySequence(String),
+    EmptyId,
+    DuplicateId { id: String, first_line: usize },
+    LeadingGarbage,
+    NulByte,
+    MixedLineEndings,
+}
+
+impl fmt::Display for IssueKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IssueKind::InvalidCharacter(byte) => {
+                write!(f, "invalid character '{}' in sequence", byte.escape_ascii())
+            }
+            IssueKind::EmptySequence(id) => write!(f, "record '{id}' has no sequence"),
+            IssueKind::EmptyId => write!(f, "header has no ID"),
+            IssueKind::DuplicateId { id, first_line } => {
+                write!(f, "duplicate ID '{id}' (first seen on line {first_line})")
+            }
+            IssueKind::LeadingGarbage => write!(f, "text before the first header"),
+            IssueKind::NulByte => write!(f, "NUL byte"),
+            IssueKind::MixedLineEndings => {
+                write!(
+                    f,
+                    "line ending differs from the earlier lines (mixed LF and CRLF)"
+                )
+            }
+        }
+    }
+}
+
+/// A problem found in the input. `line` is 1-based, `offset` is the 0-based byte
+/// offset in the decompressed stream.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationIssue {
+    pub line: usize,
+    pub offset: u64,
+    pub kind: IssueKind,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ValidationReport {
+    /// The first `MAX_REPORTED_ISSUES` issues, in input order.
+    pub issues: Vec<ValidationIssue>,
+    /// Number of issues found, including those not kept.
+    pub total: usize,
+}
+
+impl ValidationReport {
+    pub fn is_valid(&self) -> bool {
+        self.total == 0
+    }
+
+    /// Adds the issues of another input. Their lines and offsets stay those of
+    /// the input they were found in.
+    pub fn absorb(&mut self, other: &ValidationReport) {
+        let room = MAX_REPORTED_ISSUES.saturating_sub(self.issues.len());
+        self.issues.extend(other.issues.iter().take(room).cloned());
+        self.total += other.total;
+    }
+
+    fn push(&mut self, line: usize, offset: u64, kind: IssueKind) {
+        if self.issues.len() < MAX_REPORTED_ISSUES {
+            self.issues.push(ValidationIssue { line, offset, kind });
+        }
+        self.total += 1;
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum LineEnding {
+    Lf,
+    CrLf,
+}
+
+/// Byte-at-a-time FASTA checker. Input may be fed in buffers split anywhere.
+pub struct Validator {
+    allowed: &'static [bool; 256],
+    report: ValidationReport,
+    line: usize,
+    offset: u64,
+    at_line_start: bool,
+    /// Offset of a `\r` whose meaning depends on the next byte.
+    pending_cr: Option<u64>,
+    line_ending: Option<LineEnding>,
+    mixed_reported: bool,
+    garbage_reported: bool,
+    /// An invalid character was already reported on the current line.
+    line_reported: bool,
+    started: bool,
+    in_header: bool,
+    /// The ID token, up to the first whitespace, has been read.
+    id_complete: bool,
+    id: Vec<u8>,
+    header_line: usize,
+    header_offset: u64,
+    record_residues: usize,
+    /// First line of each ID seen so far.
+    ids: HashMap<Vec<u8>, usize>,
+}
+
+impl Validator {
+    pub fn new(protein: bool) -> Self {
+        Self {
+            allowed: if protein {
+                &PROTEIN_BYTES
+            } else {
+                &NUCLEOTIDE_BYTES
+            },
+            report: ValidationReport::default(),
+            line: 1,
+            offset: 0,
+            at_line_start: true,
+            pending_cr: None,
+            line_ending: None,
+            mixed_reported: false,
+            garbage_reported: false,
+            line_reported: false,
+            started: false,
+            in_header: false,
+            id_complete: false,
+            id: Vec::new(),
+            header_line: 0,
+            header_offset: 0,
+            record_residues: 0,
+            ids: HashMap::new(),
+        }
+    }
+
+    pub fn feed(&mut self, data: &[u8]) {
+        for &byte in data {
+            let cr_offset = self.pending_cr.take();
+            match byte {
+                b'\n' if cr_offset.is_some() => self.end_line(LineEnding::CrLf),
+                b'\n' => self.end_line(LineEnding::Lf),
+                _ => {
+                    if let Some(cr_offset) = cr_offset {
+                        // A '\r' not followed by '\n' is just another byte.
+                        self.content_byte(b'\r', cr_offset);
+                    }
+                    if byte == b'\r' {
+                        self.pending_cr = Some(self.offset);
+                    } else {
+                        self.content_byte(byte, self.offset);
+                    }
+                }
+            }
+            self.offset += 1;
+        }
+    }
+
+    fn content_byte(&mut self, byte: u8, offset: u64) {
+        if byte == 0 {
+            self.report.push(self.line, offset, IssueKind::NulByte);
+            self.at_line_start = false;
+            return;
+        }
+        if self.at_line_start && byte == b'>' {
+            self.end_record();
+            self.started = true;
+            self.in_header = true;
+            self.id_complete = false;
+            self.header_line = self.line;
+            self.header_offset = offset;
+        } else if self.in_header {
+            if byte.is_ascii_whitespace() {
+                self.id_complete = true;
+            } else if !self.id_complete {
+                self.id.push(byte);
+            }
+        } else if !self.started {
+            if !self.garbage_reported {
+                self.report
+                    .push(self.line, offset, IssueKind::LeadingGarbage);
+                self.garbage_reported = true;
+            }
+        } else if self.allowed[byte as usize] {
+            self.record_residues += 1;
+        } else if !self.line_reported {
+            self.report
+                .push(self.line, offset, IssueKind::InvalidCharacter(byte));
+            self.line_reported = true;
+        }
+        self.at_line_start = false;
+    }
+
+    fn end_line(&mut self, ending: LineEnding) {
+        match self.line_ending {
+            None => self.line_ending = Some(ending),
+            Some(first) if first != ending && !self.mixed_reported => {
+                self.report
+                    .push(self.line, self.offset, IssueKind::MixedLineEndings);
+                self.mixed_reported = true;
+            }
+            Some(_) => {}
+        }
+        if self.in_header {
+            self.end_header();
+        }
+        self.line += 1;
+        self.at_line_start = true;
+        self.line_reported = false;
+    }
+
+    fn end_header(&mut self) {
+        self.in_header = false;
+        if self.id.is_empty() {
+            self.report
+                .push(self.header_line, self.header_offset, IssueKind::EmptyId);
+            return;
+        }
+        if let Some(&first_line) = self.ids.get(&self.id) {
+            let id = String::from_utf8_lossy(&self.id).into_owned();
+            self.report.push(
+                self.header_line,
+                self.header_offset,
+                IssueKind::DuplicateId { id, first_line },
+            );
+        } else {
+            self.ids.insert(self.id.clone(), self.header_line);
+        }
+    }
+
+    fn end_record(&mut self) {
+        if !self.started {
+            return;
+        }
+        if self.in_header {
+            self.end_header();
+        }
+        if self.record_residues == 0 {
+            let id = String::from_utf8_lossy(&self.id).into_owned();
+            self.report.push(
+                self.header_line,
+                self.header_offset,
+                IssueKind::EmptySequence(id),
+            );
+        }
+        self.id.clear();
+        self.record_residues = 0;
+    }
+
+    pub fn finish(mut self) -> ValidationReport {
+        if let Some(cr_offset) = self.pending_cr.take() {
+            self.content_byte(b'\r', cr_offset);
+        }
+        self.end_record();
+        self.report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn validate(chunks: &[&[u8]], protein: bool) -> ValidationReport {
+        let mut validator = Validator::new(protein);
+        for chunk in chunks {
+            validator.feed(chunk);
+        }
+        validator.finish()
+    }
+
+    #[test]
+    fn test_reports_issues_with_locations() {
+        let data = b"junk\n>a desc\nACGT\n>b\n>a again\nAC!G\0T\r\n> no id\nACGT\n";
+        let report = validate(&[data], false);
+        let found: Vec<_> = report
+            .issues
+            .iter()
+            .map(|i| (i.line, i.offset, i.kind.clone()))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (1, 0, IssueKind::LeadingGarbage),
+                (4, 18, IssueKind::EmptySequence("b".to_string())),
+                (
+                    5,
+                    21,
+                    IssueKind::DuplicateId {
+                        id: "a".to_string(),
+                        first_line: 2
+                    }
+                ),
+                (6, 32, IssueKind::InvalidCharacter(b'!')),
+                (6, 34, IssueKind::NulByte),
+                (6, 37, IssueKind::MixedLineEndings),
+                (7, 38, IssueKind::EmptyId),
+            ]
+        );
+        assert_eq!(report.total, 7);
+        assert!(!report.is_valid());
+
+        let protein = validate(&[b">p1 kinase\nMVLSPADKTN*\n>p2\nXXBZ\n"], true);
+        assert!(protein.is_valid(), "{protein:?}");
+        let dna = validate(&[b">p1 kinase\nMVLSPADKTN*\n"], false);
+        assert_eq!(dna.issues[0].kind, IssueKind::InvalidCharacter(b'L'));
+    }
+
+    #[test]
+    fn test_validation_split_across_buffers() {
+        let data: &[u8] = b">a\r\nAC\rGT\r\n>b\r\n\r\n>c x\nNN\n";
+        let whole = validate(&[data], false);
+        assert_eq!(whole.total, 3);
+        for split in 1..data.len() {
+            let (a, b) = data.split_at(split);
+            assert_eq!(validate(&[a, b], false), whole, "split at {split}");
+        }
+    }
+}